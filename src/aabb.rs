@@ -0,0 +1,82 @@
+use ray::Ray;
+use vec3::Vec3;
+
+// Axis-aligned bounding box, stored as its min & max corners.
+#[derive(Clone, Copy, Debug)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    pub fn new(min: Vec3, max: Vec3) -> Aabb {
+        Aabb { min, max }
+    }
+    pub fn centroid(&self) -> Vec3 {
+        0.5 * (self.min + self.max)
+    }
+    pub fn surface_area(&self) -> f64 {
+        let d = self.max - self.min;
+        2.0 * (d.x() * d.y() + d.y() * d.z() + d.z() * d.x())
+    }
+    // Slab test (Andrew Kensler's version from "The Next Week").
+    #[inline(always)]
+    pub fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> bool {
+        let mut t_min = t_min;
+        let mut t_max = t_max;
+        for a in 0..3 {
+            let inv_d = 1.0 / r.direction[a];
+            let mut t0 = (self.min[a] - r.origin[a]) * inv_d;
+            let mut t1 = (self.max[a] - r.origin[a]) * inv_d;
+            if inv_d < 0.0 {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+            t_min = if t0 > t_min { t0 } else { t_min };
+            t_max = if t1 < t_max { t1 } else { t_max };
+            if t_max <= t_min {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+pub fn surrounding_box(box0: &Aabb, box1: &Aabb) -> Aabb {
+    Aabb::new(
+        Vec3::new(
+            box0.min.x().min(box1.min.x()),
+            box0.min.y().min(box1.min.y()),
+            box0.min.z().min(box1.min.z()),
+        ),
+        Vec3::new(
+            box0.max.x().max(box1.max.x()),
+            box0.max.y().max(box1.max.y()),
+            box0.max.z().max(box1.max.z()),
+        ),
+    )
+}
+
+// ======================================================================
+// Unit testing
+// ======================================================================
+#[test]
+fn test_aabb_hit() {
+    let b = Aabb::new(Vec3::new(-1.0, -1.0, -1.0), Vec3::new(1.0, 1.0, 1.0));
+    let toward = Ray::new(Vec3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+    let away = Ray::new(Vec3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, 1.0));
+    let past = Ray::new(Vec3::new(2.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+    assert!(b.hit(&toward, 0.001, 100.0));
+    assert!(!b.hit(&away, 0.001, 100.0));
+    assert!(!b.hit(&past, 0.001, 100.0));
+    assert!(!b.hit(&toward, 0.001, 3.0));
+}
+#[test]
+fn test_surrounding_box() {
+    let b = surrounding_box(
+        &Aabb::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 1.0, 1.0)),
+        &Aabb::new(Vec3::new(-1.0, 0.5, 0.5), Vec3::new(0.5, 2.0, 0.5)),
+    );
+    assert_eq!(b.min, Vec3::new(-1.0, 0.0, 0.0));
+    assert_eq!(b.max, Vec3::new(1.0, 2.0, 1.0));
+    assert_eq!(b.surface_area(), 2.0 * (2.0 * 2.0 + 2.0 * 1.0 + 1.0 * 2.0));
+}
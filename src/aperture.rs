@@ -0,0 +1,255 @@
+use std::f64::consts::PI;
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+
+use rand::Rng;
+
+use environment::Distribution2D;
+use texture::{load_image, TextureError};
+use vec3::{random_in_unit_disk, Vec3};
+
+// The shape of the lens opening, which out of focus highlights (bokeh)
+// take on.  Each fits across the aperture.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum ApertureShape {
+    #[default]
+    Disk,
+    // a regular polygon with a corner at the top, turned anticlockwise
+    // by rotation degrees
+    Polygon {
+        blades: usize,
+        rotation: f64,
+    },
+    Mask(Arc<ApertureMask>),
+}
+
+// A picture of the opening, filling a square across the aperture: the
+// brighter, the more light gets through there.  Points on the lens are
+// picked in proportion to it.
+pub struct ApertureMask {
+    width: usize,
+    height: usize,
+    distribution: Distribution2D,
+}
+
+impl fmt::Debug for ApertureMask {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ApertureMask({}x{})", self.width, self.height)
+    }
+}
+
+// compared by identity, like environment maps
+impl PartialEq for ApertureMask {
+    fn eq(&self, other: &ApertureMask) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl ApertureMask {
+    // texels are row by row, top row first
+    pub fn new(width: usize, height: usize, texels: &[Vec3]) -> ApertureMask {
+        assert!(width > 0 && height > 0 && texels.len() == width * height);
+        let func: Vec<f64> = texels
+            .iter()
+            .map(|c| (c.x() + c.y() + c.z()).max(0.0))
+            .collect();
+        ApertureMask {
+            width,
+            height,
+            distribution: Distribution2D::new(&func, width, height),
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<ApertureMask, TextureError> {
+        let (width, height, texels) = load_image(path)?;
+        if texels.iter().all(|c| c.x() + c.y() + c.z() <= 0.0) {
+            return Err(TextureError::Format(
+                "aperture mask is black all over".to_string(),
+            ));
+        }
+        Ok(ApertureMask::new(width, height, &texels))
+    }
+}
+
+// how often to try a point in a mask before deciding it lets no light
+// through the cat-eye
+const MASK_TRIES: usize = 256;
+
+// The lens opening, plus two things real lenses do to it.  Cat-eye
+// (mechanical vignetting) clips the opening more & more towards the
+// edges of the frame, from 0 for none to 1, when the corners see about
+// 40% of it.  Anamorphic lenses squeeze it sideways, making it squeeze
+// times as tall as it is wide.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Aperture {
+    pub shape: ApertureShape,
+    pub cat_eye: f64,
+    pub squeeze: f64,
+}
+
+impl Default for Aperture {
+    fn default() -> Aperture {
+        Aperture {
+            shape: ApertureShape::Disk,
+            cat_eye: 0.0,
+            squeeze: 1.0,
+        }
+    }
+}
+
+impl Aperture {
+    // A point on the lens, in units of the aperture's radius, for a ray
+    // towards image position (x, y), which is from the middle of the
+    // frame with the corners 1 away.  None if no light gets through the
+    // lens towards there.
+    pub fn sample<R: Rng>(&self, x: f64, y: f64, rng: &mut R) -> Option<Vec3> {
+        let squeezed = |p: Vec3| Vec3::new(p.x() / self.squeeze, p.y(), 0.0);
+        if self.cat_eye == 0.0 {
+            return Some(squeezed(self.shape_sample(rng)));
+        }
+        // the lens seen through a rear opening shifted across it.  The
+        // shift is at most 1, so the opening always takes in some of the
+        // middle of a disk or polygon, and rejection gets there.  A mask
+        // may let no light through there at all, so give up on it after
+        // a while.
+        let shift = Vec3::new(x, y, 0.0) * self.cat_eye;
+        let mut p = self.shape_sample(rng);
+        let mut tries = 1;
+        while (p - shift).squared_length() > 1.0 {
+            if tries == MASK_TRIES {
+                if let ApertureShape::Mask(_) = self.shape {
+                    return None;
+                }
+            }
+            p = self.shape_sample(rng);
+            tries += 1;
+        }
+        Some(squeezed(p))
+    }
+
+    fn shape_sample<R: Rng>(&self, rng: &mut R) -> Vec3 {
+        match self.shape {
+            ApertureShape::Disk => random_in_unit_disk(rng),
+            ApertureShape::Polygon { blades, rotation } => {
+                // evenly within one of the triangles about the middle
+                let n = blades as f64;
+                let k = ((rng.gen::<f64>() * n) as usize).min(blades - 1) as f64;
+                let corner = |k: f64| {
+                    let angle = PI / 2.0 + rotation.to_radians() + 2.0 * PI * k / n;
+                    Vec3::new(angle.cos(), angle.sin(), 0.0)
+                };
+                let a = rng.gen::<f64>().sqrt();
+                let b = rng.gen::<f64>();
+                a * ((1.0 - b) * corner(k) + b * corner(k + 1.0))
+            }
+            ApertureShape::Mask(ref mask) => {
+                let (u, v, _) = mask
+                    .distribution
+                    .sample((rng.gen::<f64>(), rng.gen::<f64>()));
+                Vec3::new(2.0 * u - 1.0, 1.0 - 2.0 * v, 0.0)
+            }
+        }
+    }
+}
+
+// ======================================================================
+// Unit testing
+// ======================================================================
+#[test]
+fn test_aperture_shapes() {
+    use rand::{SeedableRng, StdRng};
+    use vec3::dot;
+    let seed: &[_] = &[25];
+    let mut rng: StdRng = SeedableRng::from_seed(seed);
+    // the plain disk takes the same random numbers as it always has
+    let mut other: StdRng = SeedableRng::from_seed(seed);
+    let disk = Aperture::default();
+    for _ in 0..10 {
+        assert_eq!(
+            disk.sample(0.9, 0.4, &mut rng),
+            Some(random_in_unit_disk(&mut other))
+        );
+    }
+    // a hexagon with a corner at the top: inside every edge, & out to
+    // the corners
+    let hexagon = Aperture {
+        shape: ApertureShape::Polygon {
+            blades: 6,
+            rotation: 0.0,
+        },
+        ..Aperture::default()
+    };
+    let apothem = (PI / 6.0).cos();
+    let mut highest: f64 = 0.0;
+    for _ in 0..2000 {
+        let p = hexagon.sample(0.0, 0.0, &mut rng).unwrap();
+        for k in 0..6 {
+            let angle = PI / 2.0 + PI / 6.0 + k as f64 * PI / 3.0;
+            let normal = Vec3::new(angle.cos(), angle.sin(), 0.0);
+            assert!(dot(&p, &normal) <= apothem + 1e-12);
+        }
+        highest = highest.max(p.y());
+    }
+    assert!(highest > 0.9);
+    // cat-eye: clipped towards the corner it is aimed at
+    let cat_eye = Aperture {
+        cat_eye: 1.0,
+        ..Aperture::default()
+    };
+    let corner = Vec3::new(0.6, 0.8, 0.0);
+    for _ in 0..200 {
+        let p = cat_eye.sample(corner.x(), corner.y(), &mut rng).unwrap();
+        assert!(p.squared_length() < 1.0 && (p - corner).squared_length() <= 1.0);
+    }
+    // anamorphic: narrower than it is tall
+    let squeezed = Aperture {
+        squeeze: 2.0,
+        ..Aperture::default()
+    };
+    for _ in 0..200 {
+        assert!(squeezed.sample(0.0, 0.0, &mut rng).unwrap().x().abs() < 0.5);
+    }
+    // a mask with light only in its top right quarter
+    let black = Vec3::new(0.0, 0.0, 0.0);
+    let white = Vec3::new(1.0, 1.0, 1.0);
+    let masked = Aperture {
+        shape: ApertureShape::Mask(Arc::new(ApertureMask::new(
+            2,
+            2,
+            &[black, white, black, black],
+        ))),
+        ..Aperture::default()
+    };
+    for _ in 0..200 {
+        let p = masked.sample(0.0, 0.0, &mut rng).unwrap();
+        assert!(p.x() >= 0.0 && p.y() > 0.0 && p.x() < 1.0 && p.y() <= 1.0);
+    }
+    // the same mask with cat-eye: only what both let through, spread
+    // over all of it rather than piled up anywhere
+    let masked_cat_eye = Aperture {
+        cat_eye: 1.0,
+        ..masked.clone()
+    };
+    let mut quarters = [0; 4];
+    for _ in 0..2000 {
+        let p = masked_cat_eye.sample(corner.x(), corner.y(), &mut rng).unwrap();
+        assert!(p.x() >= 0.0 && p.y() > 0.0 && p.x() < 1.0 && p.y() <= 1.0);
+        assert!((p - corner).squared_length() <= 1.0);
+        quarters[(p.x() >= 0.5) as usize + 2 * (p.y() >= 0.5) as usize] += 1;
+    }
+    assert!(quarters.iter().all(|&n| n > 200));
+    // & nothing to share in the opposite corner, which still finishes
+    assert_eq!(masked_cat_eye.sample(-corner.x(), -corner.y(), &mut rng), None);
+    // squeezing without cat-eye keeps a square mask square
+    let square = Aperture {
+        shape: ApertureShape::Mask(Arc::new(ApertureMask::new(1, 1, &[white]))),
+        squeeze: 2.0,
+        ..Aperture::default()
+    };
+    let corners = (0..2000)
+        .map(|_| square.sample(0.0, 0.0, &mut rng).unwrap())
+        .filter(|p| (2.0 * p.x()).abs() > 0.8 && p.y().abs() > 0.8)
+        .count();
+    assert!(corners > 40);
+}
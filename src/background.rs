@@ -0,0 +1,89 @@
+use environment::EnvironmentMap;
+use hitable::Hitable;
+use ray::Ray;
+use sky::Sky;
+use std::str::FromStr;
+use std::sync::Arc;
+use vec3::{unit_vector, Vec3};
+
+// What a ray sees when it leaves the scene without hitting anything.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum Background {
+    // the blue-white sky from the book
+    #[default]
+    Gradient,
+    Constant(Vec3),
+    Environment(Arc<EnvironmentMap>),
+    Sky(Arc<Sky>),
+}
+
+impl Background {
+    pub fn color(&self, r: &Ray) -> Vec3 {
+        match *self {
+            Background::Gradient => {
+                let unit_direction = unit_vector(r.direction);
+                let t = 0.5 * (unit_direction.y() + 1.0);
+                (1.0 - t) * Vec3::new(1.0, 1.0, 1.0) + t * Vec3::new(0.5, 0.7, 1.0)
+            }
+            Background::Constant(c) => c,
+            Background::Environment(ref map) => map.color(&r.direction),
+            Background::Sky(ref sky) => sky.color(&r.direction),
+        }
+    }
+    // the parts of it that are sampled as lights
+    pub fn lights(&self) -> Vec<Arc<dyn Hitable>> {
+        match *self {
+            Background::Environment(ref map) => vec![map.clone()],
+            Background::Sky(ref sky) => sky.lights(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+// "gradient", "black" or an "R,G,B" color
+impl FromStr for Background {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Background, String> {
+        match s {
+            "gradient" => Ok(Background::Gradient),
+            "black" => Ok(Background::Constant(Vec3::new(0.0, 0.0, 0.0))),
+            _ => {
+                let c: Vec<f64> = s
+                    .split(',')
+                    .map(|c| c.trim().parse::<f64>())
+                    .collect::<Result<_, _>>()
+                    .map_err(|_| bad_background(s))?;
+                if c.len() != 3 {
+                    return Err(bad_background(s));
+                }
+                Ok(Background::Constant(Vec3::new(c[0], c[1], c[2])))
+            }
+        }
+    }
+}
+
+fn bad_background(s: &str) -> String {
+    format!(
+        "unknown background '{}' (expected gradient, black or R,G,B)",
+        s
+    )
+}
+
+// ======================================================================
+// Unit testing
+// ======================================================================
+#[test]
+fn test_background() {
+    let up = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 2.0, 0.0));
+    assert_eq!(Background::Gradient.color(&up), Vec3::new(0.5, 0.7, 1.0));
+    assert_eq!(
+        "black".parse::<Background>().unwrap().color(&up),
+        Vec3::new(0.0, 0.0, 0.0)
+    );
+    assert_eq!(
+        "0.1, 0.2,0.3".parse::<Background>(),
+        Ok(Background::Constant(Vec3::new(0.1, 0.2, 0.3)))
+    );
+    assert!("0.1,0.2".parse::<Background>().is_err());
+    assert!("sky".parse::<Background>().is_err());
+}
@@ -0,0 +1,195 @@
+use aabb::{surrounding_box, Aabb};
+use hitable::{HitRecord, Hitable};
+use hitable_list::HitableList;
+use ray::Ray;
+use vec3::Vec3;
+
+// Bounding volume hierarchy.  Built once from a list of objects and
+// then traced instead of scanning every object for every ray.  Splits
+// are chosen with the surface area heuristic (SAH).  A hierarchy of
+// nothing, for a scene with no objects, hits nothing & has no box.
+pub struct BvhNode {
+    left: Box<dyn Hitable>,
+    right: Option<Box<dyn Hitable>>,
+    bbox: Aabb,
+}
+
+impl BvhNode {
+    pub fn new(objects: Vec<Box<dyn Hitable>>) -> BvhNode {
+        if objects.is_empty() {
+            // inside out, so no ray meets it
+            let far = Vec3::new(f64::MAX, f64::MAX, f64::MAX);
+            return BvhNode {
+                left: Box::new(HitableList::new()),
+                right: None,
+                bbox: Aabb::new(far, -far),
+            };
+        }
+        let items = objects
+            .into_iter()
+            .map(|object| {
+                let mut bbox = Aabb::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 0.0));
+                if !object.bounding_box(&mut bbox) {
+                    panic!("no bounding box in BvhNode::new");
+                }
+                (bbox, object)
+            })
+            .collect();
+        BvhNode::build(items)
+    }
+
+    fn build(mut items: Vec<(Aabb, Box<dyn Hitable>)>) -> BvhNode {
+        let bbox = items
+            .iter()
+            .skip(1)
+            .fold(items[0].0, |acc, item| surrounding_box(&acc, &item.0));
+        match items.len() {
+            1 => BvhNode {
+                left: items.pop().unwrap().1,
+                right: None,
+                bbox,
+            },
+            2 => {
+                let right = items.pop().unwrap().1;
+                let left = items.pop().unwrap().1;
+                BvhNode {
+                    left,
+                    right: Some(right),
+                    bbox,
+                }
+            }
+            _ => {
+                let (axis, split) = sah_split(&mut items);
+                sort_by_centroid(&mut items, axis);
+                let right_items = items.split_off(split);
+                BvhNode {
+                    left: Box::new(BvhNode::build(items)),
+                    right: Some(Box::new(BvhNode::build(right_items))),
+                    bbox,
+                }
+            }
+        }
+    }
+}
+
+fn sort_by_centroid(items: &mut [(Aabb, Box<dyn Hitable>)], axis: usize) {
+    items.sort_by(|a, b| {
+        a.0.centroid()[axis]
+            .partial_cmp(&b.0.centroid()[axis])
+            .unwrap_or(std::cmp::Ordering::Equal)
+    });
+}
+
+// Find the axis & split index with the lowest SAH cost.  The cost of a
+// split is the object count on each side weighted by that side's
+// surface area; the constant traversal cost is the same for every
+// split so it is left out.
+fn sah_split(items: &mut [(Aabb, Box<dyn Hitable>)]) -> (usize, usize) {
+    let n = items.len();
+    let mut best_axis = 0;
+    let mut best_split = n / 2;
+    let mut best_cost = f64::MAX;
+    let mut right_area = vec![0.0; n];
+    for axis in 0..3 {
+        sort_by_centroid(items, axis);
+        // sweep from the right to get the area of every suffix
+        let mut acc = items[n - 1].0;
+        for i in (1..n).rev() {
+            acc = surrounding_box(&acc, &items[i].0);
+            right_area[i] = acc.surface_area();
+        }
+        // then from the left, evaluating each split as we go
+        let mut acc = items[0].0;
+        for i in 1..n {
+            let cost = i as f64 * acc.surface_area() + (n - i) as f64 * right_area[i];
+            if cost < best_cost {
+                best_cost = cost;
+                best_axis = axis;
+                best_split = i;
+            }
+            acc = surrounding_box(&acc, &items[i].0);
+        }
+    }
+    (best_axis, best_split)
+}
+
+impl Hitable for BvhNode {
+    fn hit<'a>(&'a self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord<'a>) -> bool {
+        if !self.bbox.hit(r, t_min, t_max) {
+            return false;
+        }
+        let hit_left = self.left.hit(r, t_min, t_max, rec);
+        let hit_right = match self.right {
+            Some(ref right) => right.hit(r, t_min, if hit_left { rec.t } else { t_max }, rec),
+            None => false,
+        };
+        hit_left || hit_right
+    }
+    fn bounding_box(&self, output_box: &mut Aabb) -> bool {
+        *output_box = self.bbox;
+        self.bbox.min.x() <= self.bbox.max.x()
+    }
+}
+
+// ======================================================================
+// Unit testing
+// ======================================================================
+#[test]
+fn test_bvh_matches_list() {
+    use material::Material;
+    use rand::{Rng, SeedableRng, StdRng};
+    use sphere::Sphere;
+    use texture::solid;
+    use vec3::unit_vector;
+
+    let seed: &[_] = &[42];
+    let mut rng: StdRng = SeedableRng::from_seed(seed);
+    let mut list = HitableList::new();
+    let mut bvh_list = HitableList::new();
+    for _ in 0..200 {
+        let center = Vec3::new(
+            20.0 * rng.gen::<f64>() - 10.0,
+            20.0 * rng.gen::<f64>() - 10.0,
+            20.0 * rng.gen::<f64>() - 10.0,
+        );
+        let radius = 0.1 + 0.5 * rng.gen::<f64>();
+        let material = Material::Lambertian {
+            albedo: solid(Vec3::new(
+                rng.gen::<f64>(),
+                rng.gen::<f64>(),
+                rng.gen::<f64>(),
+            )),
+        };
+        list.push(Sphere::new(center, radius, material.clone()));
+        bvh_list.push(Sphere::new(center, radius, material));
+    }
+    let bvh = bvh_list.into_bvh();
+    for _ in 0..1000 {
+        let dir = unit_vector(Vec3::new(
+            rng.gen::<f64>() - 0.5,
+            rng.gen::<f64>() - 0.5,
+            rng.gen::<f64>() - 0.5,
+        ));
+        let r = Ray::new(Vec3::new(0.0, 0.0, 0.0), dir);
+        let mut list_rec = HitRecord::new();
+        let mut bvh_rec = HitRecord::new();
+        let list_hit = list.hit(&r, 0.001, f64::MAX, &mut list_rec);
+        let bvh_hit = bvh.hit(&r, 0.001, f64::MAX, &mut bvh_rec);
+        assert_eq!(list_hit, bvh_hit);
+        if list_hit {
+            assert_eq!(list_rec.t, bvh_rec.t);
+            assert_eq!(list_rec.normal, bvh_rec.normal);
+        }
+    }
+}
+#[test]
+fn test_empty_bvh() {
+    let bvh = BvhNode::new(Vec::new());
+    let mut rec = HitRecord::new();
+    for dir in &[Vec3::new(1.0, 0.0, 0.0), Vec3::new(-1.0, -2.0, 3.0)] {
+        let r = Ray::new(Vec3::new(0.0, 0.0, 0.0), *dir);
+        assert!(!bvh.hit(&r, 0.001, f64::MAX, &mut rec));
+    }
+    let mut bbox = Aabb::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 0.0));
+    assert!(!bvh.bounding_box(&mut bbox));
+}
@@ -1,7 +1,165 @@
+use aperture::Aperture;
 use ray::Ray;
 use rand::Rng;
-use vec3::{cross, random_in_unit_disk, unit_vector, Vec3};
+use vec3::{cross, unit_vector, Vec3};
 use std;
+use std::f64::consts::PI;
+use std::str::FromStr;
+
+// How the camera maps the image to rays.  All but perspective are
+// pinhole cameras, ignoring the aperture.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Projection {
+    // through a thin lens, vfov degrees top to bottom
+    #[default]
+    Perspective,
+    // parallel rays from a view height units tall
+    Orthographic {
+        height: f64,
+    },
+    // all the way round: longitude across (the centre looking at lookat)
+    // & latitude up, for a 2:1 image
+    Equirectangular,
+    // equidistant: the angle from the view direction grows evenly with
+    // the distance from the centre, reaching fov / 2 at the edges of the
+    // image's shorter side
+    Fisheye {
+        fov: f64,
+    },
+    // six 90 degree views side by side, for a 6:1 image: right, left,
+    // up, down, back & forward.  Each is as seen from the middle, with
+    // the up & down views' edges nearest the forward one at their
+    // bottom & top.
+    Cubemap,
+}
+
+// "perspective", "orthographic,HEIGHT", "equirectangular",
+// "fisheye[,FOV]" or "cubemap"
+impl FromStr for Projection {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Projection, String> {
+        let parts: Vec<&str> = s.split(',').map(|p| p.trim()).collect();
+        let number = |p: &str| match p.parse::<f64>() {
+            Ok(x) if x.is_finite() && x > 0.0 => Ok(x),
+            _ => Err(bad_projection(s)),
+        };
+        match parts[..] {
+            ["perspective"] => Ok(Projection::Perspective),
+            ["orthographic", height] => Ok(Projection::Orthographic {
+                height: number(height)?,
+            }),
+            ["equirectangular"] => Ok(Projection::Equirectangular),
+            ["fisheye"] => Ok(Projection::Fisheye { fov: 180.0 }),
+            ["fisheye", fov] => Ok(Projection::Fisheye { fov: number(fov)? }),
+            ["cubemap"] => Ok(Projection::Cubemap),
+            _ => Err(bad_projection(s)),
+        }
+    }
+}
+
+fn bad_projection(s: &str) -> String {
+    format!(
+        "unknown projection '{}' (expected perspective, orthographic,HEIGHT, equirectangular, fisheye[,FOV] or cubemap)",
+        s
+    )
+}
+
+// Which half of the image each eye gets: the left eye's is the top or
+// the left one.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StereoLayout {
+    TopBottom,
+    SideBySide,
+}
+
+impl FromStr for StereoLayout {
+    type Err = String;
+    fn from_str(s: &str) -> Result<StereoLayout, String> {
+        match s {
+            "top_bottom" => Ok(StereoLayout::TopBottom),
+            "side_by_side" => Ok(StereoLayout::SideBySide),
+            _ => Err(format!(
+                "unknown stereo layout '{}' (expected top_bottom or side_by_side)",
+                s
+            )),
+        }
+    }
+}
+
+// A pair of eyes interocular apart, across the camera, packed into one
+// image.  Perspective eyes turn their views to agree on what is at the
+// convergence distance (the focus distance if None), which then looks
+// to be at the screen; nearer things stand out of it.  An
+// equirectangular camera gives an omnidirectional stereo (ODS)
+// panorama, with the eyes moving round a circle to stay beside every
+// direction they look in.  Other projections just move each eye to the
+// side.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Stereo {
+    pub layout: StereoLayout,
+    pub interocular: f64,
+    pub convergence: Option<f64>,
+}
+
+impl Stereo {
+    // with the eyes 6.5cm apart, if the scene is in metres
+    pub fn new(layout: StereoLayout) -> Stereo {
+        Stereo {
+            layout,
+            interocular: 0.065,
+            convergence: None,
+        }
+    }
+
+    // of each eye's view, for a whole image of the given aspect ratio
+    pub fn eye_aspect(&self, aspect: f64) -> f64 {
+        match self.layout {
+            StereoLayout::TopBottom => 2.0 * aspect,
+            StereoLayout::SideBySide => aspect / 2.0,
+        }
+    }
+
+    // where (s, t) in the whole image is in its eye's view, & how far
+    // along u that eye is from the middle
+    fn eye(&self, s: f64, t: f64) -> (f64, f64, f64) {
+        let half = self.interocular / 2.0;
+        match self.layout {
+            StereoLayout::TopBottom if t >= 0.5 => (s, 2.0 * t - 1.0, -half),
+            StereoLayout::TopBottom => (s, 2.0 * t, half),
+            StereoLayout::SideBySide if s < 0.5 => (2.0 * s, t, -half),
+            StereoLayout::SideBySide => (2.0 * s - 1.0, t, half),
+        }
+    }
+}
+
+// "LAYOUT[,INTEROCULAR[,CONVERGENCE]]"
+impl FromStr for Stereo {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Stereo, String> {
+        let parts: Vec<&str> = s.split(',').map(|p| p.trim()).collect();
+        let mut stereo = Stereo::new(parts[0].parse()?);
+        let number = |p: &str, what: &str| match p.parse::<f64>() {
+            Ok(x) if x.is_finite() && x > 0.0 => Ok(x),
+            _ => Err(format!(
+                "bad {} '{}' (expected a number greater than 0)",
+                what, p
+            )),
+        };
+        if parts.len() > 3 {
+            return Err(format!(
+                "bad stereo '{}' (expected LAYOUT[,INTEROCULAR[,CONVERGENCE]])",
+                s
+            ));
+        }
+        if let Some(p) = parts.get(1) {
+            stereo.interocular = number(p, "interocular distance")?;
+        }
+        if let Some(p) = parts.get(2) {
+            stereo.convergence = Some(number(p, "convergence distance")?);
+        }
+        Ok(stereo)
+    }
+}
 
 #[derive(Debug)]
 pub struct Camera {
@@ -11,8 +169,15 @@ pub struct Camera {
     vertical: Vec3,
     u: Vec3,
     v: Vec3,
-    w: Vec3,
+    w: Vec3, // backwards, away from lookat
+    aspect: f64,
     lens_radius: f64,
+    aperture: Aperture,
+    focus_dist: f64,
+    time0: f64, // shutter open & close times
+    time1: f64,
+    projection: Projection,
+    stereo: Option<Stereo>,
 }
 
 impl Camera {
@@ -40,15 +205,317 @@ impl Camera {
             u,
             v,
             w,
+            aspect,
             lens_radius: aperture / 2.0,
+            aperture: Aperture::default(),
+            focus_dist,
+            time0: 0.0,
+            time1: 0.0,
+            projection: Projection::Perspective,
+            stereo: None,
+        }
+    }
+    // rays are sent at random times between open and close
+    pub fn with_shutter(self, open: f64, close: f64) -> Camera {
+        Camera {
+            time0: open,
+            time1: close,
+            ..self
+        }
+    }
+    pub fn with_projection(self, projection: Projection) -> Camera {
+        Camera { projection, ..self }
+    }
+    // the shape of the lens opening, for perspective cameras
+    pub fn with_aperture(self, aperture: Aperture) -> Camera {
+        Camera { aperture, ..self }
+    }
+    // The aspect ratio given to new() must be each eye's, from
+    // Stereo::eye_aspect().
+    pub fn with_stereo(self, stereo: Stereo) -> Camera {
+        Camera {
+            stereo: Some(stereo),
+            ..self
         }
     }
-    pub fn get_ray<R: Rng>(&self, s: f64, t: f64, rng: &mut R) -> Ray {
-        let rd = self.lens_radius * random_in_unit_disk(rng);
-        let offset = self.u * rd.x() + self.v * rd.y();
-        Ray::new(
-            self.origin + offset,
-            self.lower_left_corner + s * self.horizontal + t * self.vertical - self.origin - offset,
+    // None if the lens lets no light through towards (s, t), which an
+    // aperture mask with cat-eye can do
+    pub fn get_ray<R: Rng>(&self, s: f64, t: f64, rng: &mut R) -> Option<Ray> {
+        let (s, t, shift) = match self.stereo {
+            Some(ref stereo) => stereo.eye(s, t),
+            None => (s, t, 0.0),
+        };
+        let (origin, direction) = match self.projection {
+            Projection::Perspective => {
+                let mut eye = self.origin;
+                let mut target = self.lower_left_corner + s * self.horizontal + t * self.vertical;
+                if shift != 0.0 {
+                    // off axis, through where the middle camera's ray is
+                    // at the convergence distance
+                    let convergence = self
+                        .stereo
+                        .and_then(|stereo| stereo.convergence)
+                        .unwrap_or(self.focus_dist);
+                    let scale = convergence / self.focus_dist;
+                    let through = self.origin + scale * (target - self.origin);
+                    eye = self.origin + shift * self.u;
+                    target = eye + (through - eye) / scale;
+                }
+                let edge = (self.aspect * self.aspect + 1.0).sqrt();
+                let rd = self.lens_radius
+                    * self.aperture.sample(
+                        (2.0 * s - 1.0) * self.aspect / edge,
+                        (2.0 * t - 1.0) / edge,
+                        rng,
+                    )?;
+                let offset = self.u * rd.x() + self.v * rd.y();
+                (eye + offset, target - eye - offset)
+            }
+            Projection::Orthographic { height } => (
+                self.origin
+                    + (s - 0.5) * self.aspect * height * self.u
+                    + (t - 0.5) * height * self.v
+                    + shift * self.u,
+                -self.w,
+            ),
+            Projection::Equirectangular if shift != 0.0 => {
+                // to the side of the way it looks, level with the horizon
+                let (sin_l, cos_l) = (2.0 * PI * (s - 0.5)).sin_cos();
+                (
+                    self.origin + shift * (cos_l * self.u + sin_l * self.w),
+                    self.view_direction(s, t),
+                )
+            }
+            _ => (self.origin + shift * self.u, self.view_direction(s, t)),
+        };
+        // an instantaneous shutter takes no random number, keeping still
+        // images the same as before there was motion blur
+        let time = if self.time1 > self.time0 {
+            self.time0 + rng.gen::<f64>() * (self.time1 - self.time0)
+        } else {
+            self.time0
+        };
+        Some(Ray::with_time(origin, direction, time))
+    }
+    // where the pinhole projections look, in the camera's frame
+    fn view_direction(&self, s: f64, t: f64) -> Vec3 {
+        let local = match self.projection {
+            Projection::Equirectangular => {
+                let longitude = 2.0 * PI * (s - 0.5);
+                let latitude = PI * (t - 0.5);
+                Vec3::new(
+                    latitude.cos() * longitude.sin(),
+                    latitude.sin(),
+                    -latitude.cos() * longitude.cos(),
+                )
+            }
+            Projection::Fisheye { fov } => {
+                // from the centre, with the shorter side from -1 to 1
+                let scale = self.aspect.max(1.0) / self.aspect.min(1.0);
+                let (x, y) = if self.aspect >= 1.0 {
+                    ((2.0 * s - 1.0) * scale, 2.0 * t - 1.0)
+                } else {
+                    (2.0 * s - 1.0, (2.0 * t - 1.0) * scale)
+                };
+                let r = (x * x + y * y).sqrt();
+                let angle = r * fov.to_radians() / 2.0;
+                let (sin_a, cos_a) = angle.sin_cos();
+                if r > 0.0 {
+                    Vec3::new(sin_a * x / r, sin_a * y / r, -cos_a)
+                } else {
+                    Vec3::new(0.0, 0.0, -1.0)
+                }
+            }
+            Projection::Cubemap => {
+                let face = ((s * 6.0) as usize).min(5);
+                // across each face & up it, from -1 to 1
+                let x = 2.0 * (s * 6.0 - face as f64) - 1.0;
+                let y = 2.0 * t - 1.0;
+                match face {
+                    0 => Vec3::new(1.0, y, x),
+                    1 => Vec3::new(-1.0, y, -x),
+                    2 => Vec3::new(x, 1.0, y),
+                    3 => Vec3::new(x, -1.0, -y),
+                    4 => Vec3::new(-x, y, 1.0),
+                    _ => Vec3::new(x, y, -1.0),
+                }
+            }
+            Projection::Perspective | Projection::Orthographic { .. } => unreachable!(),
+        };
+        local.x() * self.u + local.y() * self.v + local.z() * self.w
+    }
+}
+
+// ======================================================================
+// Unit testing
+// ======================================================================
+#[test]
+fn test_projections() {
+    use rand::{SeedableRng, StdRng};
+    let seed: &[_] = &[23];
+    let mut rng: StdRng = SeedableRng::from_seed(seed);
+    let camera = |projection: Projection, aspect: f64| {
+        Camera::new(
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, -1.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            90.0,
+            aspect,
+            0.0,
+            1.0,
         )
+        .with_projection(projection)
+    };
+    let close = |a: Vec3, b: Vec3| (unit_vector(a) - unit_vector(b)).length() < 1e-9;
+    let forward = Vec3::new(0.0, 0.0, -1.0);
+    let ortho = camera(Projection::Orthographic { height: 2.0 }, 2.0);
+    let r = ortho.get_ray(1.0, 0.75, &mut rng).unwrap();
+    assert_eq!(r.origin, Vec3::new(2.0, 0.5, 0.0));
+    assert!(close(r.direction, forward));
+    // the middle looks ahead; the edges look behind, & the top up
+    let pano = camera(Projection::Equirectangular, 2.0);
+    assert!(close(
+        pano.get_ray(0.5, 0.5, &mut rng).unwrap().direction,
+        forward
+    ));
+    assert!(close(
+        pano.get_ray(0.75, 0.5, &mut rng).unwrap().direction,
+        Vec3::new(1.0, 0.0, 0.0)
+    ));
+    assert!(close(
+        pano.get_ray(0.0, 0.5, &mut rng).unwrap().direction,
+        -forward
+    ));
+    assert!(close(
+        pano.get_ray(0.3, 1.0, &mut rng).unwrap().direction,
+        Vec3::new(0.0, 1.0, 0.0)
+    ));
+    // 90 degrees out at the top & bottom of a 180 degree fisheye
+    let fisheye = camera(Projection::Fisheye { fov: 180.0 }, 1.5);
+    assert!(close(
+        fisheye.get_ray(0.5, 0.5, &mut rng).unwrap().direction,
+        forward
+    ));
+    assert!(close(
+        fisheye.get_ray(0.5, 1.0, &mut rng).unwrap().direction,
+        Vec3::new(0.0, 1.0, 0.0)
+    ));
+    let d = fisheye
+        .get_ray(0.5 + 0.25 / 1.5, 0.5, &mut rng)
+        .unwrap()
+        .direction;
+    assert!(close(d, Vec3::new(1.0, 0.0, -1.0)));
+    // each face's centre looks along its axis
+    let cube = camera(Projection::Cubemap, 6.0);
+    let centre = |face: f64| {
+        cube.get_ray((face + 0.5) / 6.0, 0.5, &mut rng.clone())
+            .unwrap()
+            .direction
+    };
+    assert!(close(centre(0.0), Vec3::new(1.0, 0.0, 0.0)));
+    assert!(close(centre(2.0), Vec3::new(0.0, 1.0, 0.0)));
+    assert!(close(centre(4.0), Vec3::new(0.0, 0.0, 1.0)));
+    assert!(close(centre(5.0), forward));
+    // the top of the forward face is up, & its right is +x
+    assert!(close(
+        cube.get_ray(5.5 / 6.0, 1.0, &mut rng).unwrap().direction,
+        Vec3::new(0.0, 1.0, -1.0)
+    ));
+    assert!(close(
+        cube.get_ray(1.0, 0.5, &mut rng).unwrap().direction,
+        Vec3::new(1.0, 0.0, -1.0)
+    ));
+    // neighbouring faces meet: right's right edge is back's left edge,
+    // & the up face's bottom edge is the forward face's top
+    assert!(close(
+        cube.get_ray((1.0 - 1e-12) / 6.0, 0.3, &mut rng)
+            .unwrap()
+            .direction,
+        cube.get_ray(4.0 / 6.0, 0.3, &mut rng).unwrap().direction
+    ));
+    assert!(close(
+        cube.get_ray(2.7 / 6.0, 0.0, &mut rng).unwrap().direction,
+        cube.get_ray(5.7 / 6.0, 1.0, &mut rng).unwrap().direction
+    ));
+
+    assert_eq!(
+        "orthographic, 4".parse::<Projection>(),
+        Ok(Projection::Orthographic { height: 4.0 })
+    );
+    assert_eq!(
+        "fisheye".parse::<Projection>(),
+        Ok(Projection::Fisheye { fov: 180.0 })
+    );
+    assert!("orthographic".parse::<Projection>().is_err());
+    assert!("fisheye,-10".parse::<Projection>().is_err());
+}
+#[test]
+fn test_stereo() {
+    use rand::{SeedableRng, StdRng};
+    use vec3::dot;
+    let seed: &[_] = &[24];
+    let mut rng: StdRng = SeedableRng::from_seed(seed);
+    let mut stereo = Stereo::new(StereoLayout::TopBottom);
+    stereo.interocular = 0.2;
+    stereo.convergence = Some(4.0);
+    assert_eq!(stereo.eye_aspect(1.0), 2.0);
+    let camera = Camera::new(
+        Vec3::new(0.0, 0.0, 0.0),
+        Vec3::new(0.0, 0.0, -1.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        90.0,
+        2.0,
+        0.0,
+        2.0,
+    )
+    .with_stereo(stereo);
+    // the left eye is on top; both see the same point at the
+    // convergence distance
+    let left = camera.get_ray(0.3, 0.85, &mut rng).unwrap();
+    let right = camera.get_ray(0.3, 0.35, &mut rng).unwrap();
+    assert_eq!(left.origin, Vec3::new(-0.1, 0.0, 0.0));
+    assert_eq!(right.origin, Vec3::new(0.1, 0.0, 0.0));
+    let at = |r: &Ray, depth: f64| r.origin + (depth / -r.direction.z()) * r.direction;
+    assert!((at(&left, 4.0) - at(&right, 4.0)).length() < 1e-12);
+    assert!((at(&left, 4.0) - Vec3::new(-3.2, 1.6, -4.0)).length() < 1e-12);
+    assert!((at(&left, 2.0) - at(&right, 2.0)).length() > 0.09);
+
+    // omnidirectional stereo: the eyes are on a circle, beside the view
+    let mut ods = Stereo::new(StereoLayout::SideBySide);
+    ods.interocular = 0.2;
+    let camera = Camera::new(
+        Vec3::new(1.0, 2.0, 3.0),
+        Vec3::new(1.0, 2.0, 2.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        90.0,
+        2.0,
+        0.0,
+        1.0,
+    )
+    .with_projection(Projection::Equirectangular)
+    .with_stereo(ods);
+    for &(s, t) in &[(0.1, 0.5), (0.3, 0.8), (0.45, 0.2)] {
+        let left = camera.get_ray(s, t, &mut rng).unwrap();
+        let right = camera.get_ray(s + 0.5, t, &mut rng).unwrap();
+        assert!((left.direction - right.direction).length() < 1e-12);
+        let middle = 0.5 * (left.origin + right.origin);
+        assert!((middle - Vec3::new(1.0, 2.0, 3.0)).length() < 1e-12);
+        let across = right.origin - left.origin;
+        assert!((across.length() - 0.2).abs() < 1e-12);
+        assert!(dot(&across, &left.direction).abs() < 1e-12);
+        assert_eq!(across.y(), 0.0);
     }
+    // looking forward, the right eye is to the right
+    let right = camera.get_ray(0.75, 0.5, &mut rng).unwrap();
+    assert!((right.origin - Vec3::new(1.1, 2.0, 3.0)).length() < 1e-12);
+
+    assert_eq!(
+        "side_by_side,0.1,3".parse::<Stereo>(),
+        Ok(Stereo {
+            layout: StereoLayout::SideBySide,
+            interocular: 0.1,
+            convergence: Some(3.0),
+        })
+    );
+    assert!("top_bottom,0.1,3,4".parse::<Stereo>().is_err());
 }
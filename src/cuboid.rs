@@ -0,0 +1,85 @@
+use aabb::Aabb;
+use flip_normals::FlipNormals;
+use hitable::{HitRecord, Hitable};
+use hitable_list::HitableList;
+use material::Material;
+use ray::Ray;
+use rect::AaRect;
+use vec3::Vec3;
+
+// An axis-aligned box made of six rectangles (the book's `box`, but
+// that name would hide std's Box).
+pub struct Cuboid {
+    pmin: Vec3,
+    pmax: Vec3,
+    sides: HitableList,
+}
+
+impl Cuboid {
+    pub fn new(p0: Vec3, p1: Vec3, material: Material) -> Cuboid {
+        let mut sides = HitableList::new();
+        sides.push(AaRect::xy(
+            p0.x(),
+            p1.x(),
+            p0.y(),
+            p1.y(),
+            p1.z(),
+            material.clone(),
+        ));
+        sides.push(FlipNormals::new(AaRect::xy(
+            p0.x(),
+            p1.x(),
+            p0.y(),
+            p1.y(),
+            p0.z(),
+            material.clone(),
+        )));
+        sides.push(AaRect::xz(
+            p0.x(),
+            p1.x(),
+            p0.z(),
+            p1.z(),
+            p1.y(),
+            material.clone(),
+        ));
+        sides.push(FlipNormals::new(AaRect::xz(
+            p0.x(),
+            p1.x(),
+            p0.z(),
+            p1.z(),
+            p0.y(),
+            material.clone(),
+        )));
+        sides.push(AaRect::yz(
+            p0.y(),
+            p1.y(),
+            p0.z(),
+            p1.z(),
+            p1.x(),
+            material.clone(),
+        ));
+        sides.push(FlipNormals::new(AaRect::yz(
+            p0.y(),
+            p1.y(),
+            p0.z(),
+            p1.z(),
+            p0.x(),
+            material,
+        )));
+        Cuboid {
+            pmin: p0,
+            pmax: p1,
+            sides,
+        }
+    }
+}
+
+impl Hitable for Cuboid {
+    fn hit<'a>(&'a self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord<'a>) -> bool {
+        self.sides.hit(r, t_min, t_max, rec)
+    }
+    fn bounding_box(&self, output_box: &mut Aabb) -> bool {
+        *output_box = Aabb::new(self.pmin, self.pmax);
+        true
+    }
+}
@@ -0,0 +1,318 @@
+use std::f64::consts::PI;
+use std::fmt;
+use std::path::Path;
+
+use aabb::Aabb;
+use hitable::{HitRecord, Hitable};
+use ray::Ray;
+use texture::{load_image, TextureError};
+use vec3::{unit_vector, Vec3};
+
+// A piecewise constant pdf over [0,1), in proportion to func, which
+// must not be negative.  If func is all 0 it is uniform instead.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Distribution1D {
+    func: Vec<f64>,
+    cdf: Vec<f64>,
+    integral: f64,
+}
+
+impl Distribution1D {
+    pub fn new(func: Vec<f64>) -> Distribution1D {
+        let n = func.len();
+        let mut cdf = vec![0.0; n + 1];
+        for i in 0..n {
+            cdf[i + 1] = cdf[i] + func[i] / n as f64;
+        }
+        let integral = cdf[n];
+        for (i, c) in cdf.iter_mut().enumerate() {
+            *c = if integral > 0.0 {
+                *c / integral
+            } else {
+                i as f64 / n as f64
+            };
+        }
+        Distribution1D {
+            func,
+            cdf,
+            integral,
+        }
+    }
+
+    pub fn integral(&self) -> f64 {
+        self.integral
+    }
+
+    // the pdf of the piece x is in
+    fn pdf_at(&self, i: usize) -> f64 {
+        if self.integral > 0.0 {
+            self.func[i] / self.integral
+        } else {
+            1.0
+        }
+    }
+
+    // a point in [0,1) for the uniform u, the pdf there and its piece
+    pub fn sample(&self, u: f64) -> (f64, f64, usize) {
+        let n = self.func.len();
+        // the last piece whose cdf starts at or below u
+        let i = (self.cdf.partition_point(|&c| c <= u) - 1).min(n - 1);
+        let width = self.cdf[i + 1] - self.cdf[i];
+        let du = if width > 0.0 {
+            (u - self.cdf[i]) / width
+        } else {
+            0.0
+        };
+        ((i as f64 + du) / n as f64, self.pdf_at(i), i)
+    }
+
+    pub fn pdf(&self, x: f64) -> f64 {
+        let n = self.func.len();
+        self.pdf_at(((x * n as f64) as usize).min(n - 1))
+    }
+}
+
+// A pdf over the unit square: a row is picked from how much is in
+// each, then a column within it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Distribution2D {
+    rows: Vec<Distribution1D>,
+    marginal: Distribution1D,
+}
+
+impl Distribution2D {
+    // func is row by row, top row first
+    pub fn new(func: &[f64], width: usize, height: usize) -> Distribution2D {
+        let rows: Vec<Distribution1D> = func
+            .chunks(width)
+            .take(height)
+            .map(|row| Distribution1D::new(row.to_vec()))
+            .collect();
+        let marginal = Distribution1D::new(rows.iter().map(|r| r.integral()).collect());
+        Distribution2D { rows, marginal }
+    }
+
+    // (x, y) and the pdf there
+    pub fn sample(&self, sample: (f64, f64)) -> (f64, f64, f64) {
+        let (y, pdf_y, row) = self.marginal.sample(sample.1);
+        let (x, pdf_x, _) = self.rows[row].sample(sample.0);
+        (x, y, pdf_x * pdf_y)
+    }
+
+    pub fn pdf(&self, x: f64, y: f64) -> f64 {
+        let n = self.rows.len();
+        let row = ((y * n as f64) as usize).min(n - 1);
+        self.marginal.pdf(y) * self.rows[row].pdf(x)
+    }
+}
+
+// Light from infinitely far away in every direction, read from a
+// latitude-longitude (equirectangular) image.  The middle of the image
+// is towards -z and its top is straight up; rotation turns it about the
+// y axis.  It is a light that rays never hit: color() asks it what
+// rays that miss everything see, and for next event estimation it picks
+// directions in proportion to how bright the image is there.
+pub struct EnvironmentMap {
+    width: usize,
+    height: usize,
+    texels: Vec<Vec3>, // top row first
+    rotation: f64,     // radians
+    intensity: f64,
+    distribution: Distribution2D,
+}
+
+impl fmt::Debug for EnvironmentMap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "EnvironmentMap({}x{})", self.width, self.height)
+    }
+}
+
+// compared by identity, as the images are large
+impl PartialEq for EnvironmentMap {
+    fn eq(&self, other: &EnvironmentMap) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl EnvironmentMap {
+    // rotation in degrees; intensity scales the image
+    pub fn new(
+        width: usize,
+        height: usize,
+        texels: Vec<Vec3>,
+        rotation: f64,
+        intensity: f64,
+    ) -> EnvironmentMap {
+        assert!(width > 0 && height > 0 && texels.len() == width * height);
+        // brightness per solid angle: rows near the poles cover less
+        let mut func = Vec::with_capacity(width * height);
+        for j in 0..height {
+            let sin_theta = (PI * (j as f64 + 0.5) / height as f64).sin();
+            for c in &texels[j * width..(j + 1) * width] {
+                func.push((c.x() + c.y() + c.z()).max(0.0) * sin_theta);
+            }
+        }
+        EnvironmentMap {
+            width,
+            height,
+            texels,
+            rotation: rotation.to_radians(),
+            intensity,
+            distribution: Distribution2D::new(&func, width, height),
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(
+        path: P,
+        rotation: f64,
+        intensity: f64,
+    ) -> Result<EnvironmentMap, TextureError> {
+        let (width, height, texels) = load_image(path)?;
+        Ok(EnvironmentMap::new(
+            width, height, texels, rotation, intensity,
+        ))
+    }
+
+    // an image of f, which gives what is seen in each direction
+    pub fn bake<F: Fn(&Vec3) -> Vec3>(width: usize, height: usize, f: F) -> EnvironmentMap {
+        let mut texels = Vec::with_capacity(width * height);
+        for j in 0..height {
+            for i in 0..width {
+                let u = (i as f64 + 0.5) / width as f64;
+                let v = (j as f64 + 0.5) / height as f64;
+                texels.push(f(&unrotated_direction(u, v)));
+            }
+        }
+        EnvironmentMap::new(width, height, texels, 0.0, 1.0)
+    }
+
+    // image coordinates, both in [0,1) from the top left, for a unit
+    // direction
+    fn image_coords(&self, d: &Vec3) -> (f64, f64) {
+        let (sin_r, cos_r) = self.rotation.sin_cos();
+        let x = d.x() * cos_r - d.z() * sin_r;
+        let z = d.x() * sin_r + d.z() * cos_r;
+        let phi = x.atan2(-z);
+        let theta = d.y().clamp(-1.0, 1.0).acos();
+        (0.5 + phi / (2.0 * PI), theta / PI)
+    }
+
+    fn direction(&self, u: f64, v: f64) -> Vec3 {
+        let d = unrotated_direction(u, v);
+        let (sin_r, cos_r) = self.rotation.sin_cos();
+        Vec3::new(
+            d.x() * cos_r + d.z() * sin_r,
+            d.y(),
+            -d.x() * sin_r + d.z() * cos_r,
+        )
+    }
+
+    // what is seen along the direction d
+    pub fn color(&self, d: &Vec3) -> Vec3 {
+        let (u, v) = self.image_coords(&unit_vector(*d));
+        let i = ((u * self.width as f64) as usize).min(self.width - 1);
+        let j = ((v * self.height as f64) as usize).min(self.height - 1);
+        self.intensity * self.texels[j * self.width + i]
+    }
+}
+
+fn unrotated_direction(u: f64, v: f64) -> Vec3 {
+    let (phi, theta) = (2.0 * PI * (u - 0.5), PI * v);
+    Vec3::new(
+        theta.sin() * phi.sin(),
+        theta.cos(),
+        -theta.sin() * phi.cos(),
+    )
+}
+
+impl Hitable for EnvironmentMap {
+    fn hit(&self, _r: &Ray, _t_min: f64, _t_max: f64, _rec: &mut HitRecord) -> bool {
+        false
+    }
+    fn bounding_box(&self, _output_box: &mut Aabb) -> bool {
+        false
+    }
+    fn is_light(&self) -> bool {
+        true
+    }
+    fn pdf_value(&self, _o: &Vec3, v: &Vec3) -> f64 {
+        let (u, t) = self.image_coords(&unit_vector(*v));
+        let sin_theta = (PI * t).sin();
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+        // from the unit square to the sphere
+        self.distribution.pdf(u, t) / (2.0 * PI * PI * sin_theta)
+    }
+    fn random(&self, _o: &Vec3, sample: (f64, f64)) -> Vec3 {
+        let (u, v, _) = self.distribution.sample(sample);
+        self.direction(u, v)
+    }
+}
+
+// ======================================================================
+// Unit testing
+// ======================================================================
+#[test]
+fn test_distribution() {
+    let d = Distribution1D::new(vec![1.0, 3.0, 0.0, 4.0]);
+    assert_eq!(d.integral(), 2.0);
+    let (x, pdf, i) = d.sample(0.25);
+    assert_eq!((i, pdf), (1, 1.5));
+    assert!((x - (0.25 + 0.125 / 0.375 * 0.25)).abs() < 1e-12);
+    assert_eq!(d.pdf(0.6), 0.0);
+    // the empty piece is never picked
+    for k in 0..100 {
+        assert_ne!(d.sample(k as f64 / 100.0).2, 2);
+    }
+    let flat = Distribution1D::new(vec![0.0, 0.0]);
+    assert_eq!(flat.sample(0.75).0, 0.75);
+}
+#[test]
+fn test_environment_map_sampling() {
+    use rand::{Rng, SeedableRng, StdRng};
+    let seed: &[_] = &[19];
+    let mut rng: StdRng = SeedableRng::from_seed(seed);
+    // dim everywhere with one bright texel
+    let (width, height) = (16, 8);
+    let mut texels = vec![Vec3::new(0.1, 0.1, 0.1); width * height];
+    texels[2 * width + 11] = Vec3::new(50.0, 40.0, 30.0);
+    let map = EnvironmentMap::new(width, height, texels, 30.0, 2.0);
+    let o = Vec3::new(0.0, 0.0, 0.0);
+    // directions & image coordinates go back & forth
+    let d = map.direction(0.3, 0.6);
+    let (u, v) = map.image_coords(&d);
+    assert!((u - 0.3).abs() < 1e-9 && (v - 0.6).abs() < 1e-9);
+    // the middle of the image is towards -z, turned by the rotation
+    let ahead = Vec3::new(
+        -(30f64.to_radians().sin()),
+        0.0,
+        -(30f64.to_radians().cos()),
+    );
+    let (u, v) = map.image_coords(&ahead);
+    assert!((u - 0.5).abs() < 1e-9 && (v - 0.5).abs() < 1e-9);
+    // the pdf integrates to 1 over the sphere, & estimating the power
+    // by sampling agrees with summing the texels
+    let n = 20000;
+    let (mut pdf_sum, mut power) = (0.0, 0.0);
+    for _ in 0..n {
+        let z = 1.0 - 2.0 * rng.gen::<f64>();
+        let phi = 2.0 * PI * rng.gen::<f64>();
+        let r = (1.0 - z * z).sqrt();
+        pdf_sum += map.pdf_value(&o, &Vec3::new(r * phi.cos(), r * phi.sin(), z));
+        let s = map.random(&o, (rng.gen::<f64>(), rng.gen::<f64>()));
+        assert!((s.length() - 1.0).abs() < 1e-9);
+        power += map.color(&s).y() / map.pdf_value(&o, &s);
+    }
+    assert!((pdf_sum * 4.0 * PI / n as f64 - 1.0).abs() < 0.05);
+    let mut expected = 0.0;
+    for j in 0..height {
+        let band =
+            (PI * j as f64 / height as f64).cos() - (PI * (j + 1) as f64 / height as f64).cos();
+        for i in 0..width {
+            let texel = if (i, j) == (11, 2) { 40.0 } else { 0.1 };
+            expected += 2.0 * texel * band * 2.0 * PI / width as f64;
+        }
+    }
+    assert!((power / n as f64 / expected - 1.0).abs() < 0.01);
+}
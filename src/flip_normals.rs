@@ -0,0 +1,40 @@
+use aabb::Aabb;
+use hitable::{HitRecord, Hitable};
+use ray::Ray;
+use vec3::Vec3;
+
+// Turns an object inside out, e.g. for the walls of a room.
+pub struct FlipNormals {
+    object: Box<dyn Hitable>,
+}
+
+impl FlipNormals {
+    pub fn new<H: Hitable + 'static>(object: H) -> FlipNormals {
+        FlipNormals {
+            object: Box::new(object),
+        }
+    }
+}
+
+impl Hitable for FlipNormals {
+    fn hit<'a>(&'a self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord<'a>) -> bool {
+        if self.object.hit(r, t_min, t_max, rec) {
+            rec.normal = -rec.normal;
+            true
+        } else {
+            false
+        }
+    }
+    fn bounding_box(&self, output_box: &mut Aabb) -> bool {
+        self.object.bounding_box(output_box)
+    }
+    fn is_light(&self) -> bool {
+        self.object.is_light()
+    }
+    fn pdf_value(&self, o: &Vec3, v: &Vec3) -> f64 {
+        self.object.pdf_value(o, v)
+    }
+    fn random(&self, o: &Vec3, sample: (f64, f64)) -> Vec3 {
+        self.object.random(o, sample)
+    }
+}
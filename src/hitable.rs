@@ -1,28 +1,70 @@
 use vec3::Vec3;
 use ray::Ray;
 use material::Material;
+use aabb::Aabb;
+use std::sync::Arc;
 
 #[derive(Debug)]
-pub struct HitRecord {
+pub struct HitRecord<'a> {
     pub t: f64,
     pub p: Vec3,
     pub normal: Vec3,
-    pub material: Material, // FIXME reference to avoid copy?
+    pub u: f64, // surface coordinates
+    pub v: f64,
+    pub material: &'a Material,
 }
 
-pub trait Hitable {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool;
+// Send + Sync so a world of trait objects can be shared across rayon threads.
+pub trait Hitable: Send + Sync {
+    fn hit<'a>(&'a self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord<'a>) -> bool;
+    // returns false for objects that cannot be bounded
+    fn bounding_box(&self, output_box: &mut Aabb) -> bool;
+
+    // Lights are emissive objects that can be sampled directly: random()
+    // turns two uniform numbers into a direction from o towards the
+    // object, and pdf_value() is the solid angle pdf of picking v that way.
+    fn is_light(&self) -> bool {
+        false
+    }
+    fn pdf_value(&self, _o: &Vec3, _v: &Vec3) -> f64 {
+        0.0
+    }
+    fn random(&self, _o: &Vec3, _sample: (f64, f64)) -> Vec3 {
+        Vec3::new(1.0, 0.0, 0.0)
+    }
+}
+
+// so one object can be in both the world and the list of lights
+impl<H: Hitable + ?Sized> Hitable for Arc<H> {
+    fn hit<'a>(&'a self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord<'a>) -> bool {
+        (**self).hit(r, t_min, t_max, rec)
+    }
+    fn bounding_box(&self, output_box: &mut Aabb) -> bool {
+        (**self).bounding_box(output_box)
+    }
+    fn is_light(&self) -> bool {
+        (**self).is_light()
+    }
+    fn pdf_value(&self, o: &Vec3, v: &Vec3) -> f64 {
+        (**self).pdf_value(o, v)
+    }
+    fn random(&self, o: &Vec3, sample: (f64, f64)) -> Vec3 {
+        (**self).random(o, sample)
+    }
 }
 
-impl HitRecord {
-    pub fn new() -> HitRecord {
+// stands in until something is hit
+static NO_MATERIAL: Material = Material::Dielectric { ref_idx: 1.0 };
+
+impl HitRecord<'static> {
+    pub fn new() -> HitRecord<'static> {
         HitRecord {
             t: 0.0,
             p: Vec3::new(0.0, 0.0, 0.0),
             normal: Vec3::new(0.0, 0.0, 0.0),
-            material: Material::Lambertian {
-                albedo: Vec3::new(0.0, 0.0, 0.0),
-            },
+            u: 0.0,
+            v: 0.0,
+            material: &NO_MATERIAL,
         }
     }
 }
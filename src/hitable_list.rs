@@ -1,41 +1,112 @@
+use aabb::{surrounding_box, Aabb};
+use bvh::BvhNode;
+use hitable::{HitRecord, Hitable};
+use light::LightList;
 use ray::Ray;
-use hitable;
-use sphere::Sphere;
+use std::mem;
+use std::sync::Arc;
+use vec3::Vec3;
 
-// Ideally, HitableList is just Vec<Box<Hitable>>
-// But, I don't know how to do that in Rust yet
+// Any mix of Hitable objects.  Hitable requires Send + Sync, so the
+// boxed objects can still be shared across rayon threads.
 pub struct HitableList {
-    spheres: Vec<Sphere>, // FIXME generalize, but that's another book
+    objects: Vec<Box<dyn Hitable>>,
 }
 
 impl HitableList {
     pub fn new() -> HitableList {
         HitableList {
-            spheres: Vec::new(),
+            objects: Vec::new(),
         }
     }
-    pub fn push(&mut self, sphere: Sphere) {
-        self.spheres.push(sphere);
+    pub fn push<H: Hitable + 'static>(&mut self, object: H) {
+        self.objects.push(Box::new(object));
+    }
+    pub fn push_boxed(&mut self, object: Box<dyn Hitable>) {
+        self.objects.push(object);
+    }
+    // The lights, for next event estimation.  They are shared with the
+    // list, so they are still hit by rays as well.
+    pub fn share_lights(&mut self) -> LightList {
+        let mut lights = Vec::new();
+        for object in &mut self.objects {
+            if object.is_light() {
+                let owned = mem::replace(object, Box::new(HitableList::new()));
+                let shared: Arc<dyn Hitable> = Arc::from(owned);
+                *object = Box::new(shared.clone());
+                lights.push(shared);
+            }
+        }
+        LightList::new(lights)
+    }
+    // build a bounding volume hierarchy to trace instead of the list
+    pub fn into_bvh(self) -> BvhNode {
+        BvhNode::new(self.objects)
     }
 }
 
-impl hitable::Hitable for HitableList {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut hitable::HitRecord) -> bool {
-        let mut temp_rec = hitable::HitRecord::new();
+impl Hitable for HitableList {
+    fn hit<'a>(&'a self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord<'a>) -> bool {
+        // each object only writes rec (including its material) on a hit,
+        // so the closest hit is what is left in rec at the end.
         let mut hit_anything = false;
         let mut closest_so_far = t_max;
-        for i in 0..self.spheres.len() {
-            if self.spheres[i].hit(r, t_min, closest_so_far, &mut temp_rec) {
+        for object in &self.objects {
+            if object.hit(r, t_min, closest_so_far, rec) {
                 hit_anything = true;
-                closest_so_far = temp_rec.t;
-                // Hmm, seems like something to encapsulate (FIXME)
-                // could not just do rec = temp_rec (or variants)
-                rec.t = temp_rec.t;
-                rec.p = temp_rec.p;
-                rec.normal = temp_rec.normal;
-                rec.material = self.spheres[i].material;
+                closest_so_far = rec.t;
             }
         }
         hit_anything
     }
+    fn bounding_box(&self, output_box: &mut Aabb) -> bool {
+        let mut temp_box = Aabb::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 0.0));
+        let mut first = true;
+        for object in &self.objects {
+            if !object.bounding_box(&mut temp_box) {
+                return false;
+            }
+            *output_box = if first {
+                temp_box
+            } else {
+                surrounding_box(output_box, &temp_box)
+            };
+            first = false;
+        }
+        !first
+    }
+}
+
+// ======================================================================
+// Unit testing
+// ======================================================================
+#[test]
+fn test_hit_reports_closest_material() {
+    use material::Material;
+    use sphere::Sphere;
+    use texture::solid;
+
+    let mut list = HitableList::new();
+    list.push(Sphere::new(
+        Vec3::new(0.0, 0.0, -4.0),
+        1.0,
+        Material::Dielectric { ref_idx: 1.5 },
+    ));
+    list.push(Sphere::new(
+        Vec3::new(0.0, 0.0, -2.0),
+        0.5,
+        Material::Lambertian {
+            albedo: solid(Vec3::new(0.1, 0.2, 0.3)),
+        },
+    ));
+    let r = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0));
+    let mut rec = HitRecord::new();
+    assert!(list.hit(&r, 0.001, f64::MAX, &mut rec));
+    assert_eq!(rec.t, 1.5);
+    match *rec.material {
+        Material::Lambertian { ref albedo } => {
+            assert_eq!(albedo.value(rec.u, rec.v, &rec.p), Vec3::new(0.1, 0.2, 0.3))
+        }
+        _ => panic!("expected the nearer Lambertian sphere"),
+    }
 }
@@ -0,0 +1,367 @@
+use std::cell::Cell;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+// Light fixture profiles from IES LM-63 photometric files: how many
+// candela a fixture gives off in each direction, measured on a grid of
+// vertical & horizontal angles.  Only type C photometry, the usual one
+// for building lights, is handled: vertical angles go from 0 (straight
+// down, the nadir) to 180 (straight up), and horizontal angles go
+// anticlockwise around the vertical, seen from above.
+#[derive(Debug)]
+pub enum IesError {
+    Io(io::Error),
+    Parse { line: usize, message: String },
+}
+
+impl fmt::Display for IesError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            IesError::Io(ref e) => write!(f, "{}", e),
+            IesError::Parse {
+                ref line,
+                ref message,
+            } => write!(f, "line {}: {}", line, message),
+        }
+    }
+}
+
+impl Error for IesError {}
+
+impl From<io::Error> for IesError {
+    fn from(e: io::Error) -> IesError {
+        IesError::Io(e)
+    }
+}
+
+// More angles than any real file has (a tenth of a degree apart, all the
+// way round, is 3601)
+const MAX_ANGLES: usize = 100_000;
+
+// Which horizontal angles the file covers; the rest are mirror images.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Symmetry {
+    Rotational, // one horizontal angle, the same all the way round
+    Quadrant,   // 0 to 90, mirrored across the 0-180 and 90-270 planes
+    Bilateral,  // 0 to 180, mirrored across the 0-180 plane
+    None,       // 0 to 360
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct IesProfile {
+    vertical: Vec<f64>,   // degrees, ascending
+    horizontal: Vec<f64>, // degrees, ascending
+    candela: Vec<f64>,    // for each horizontal angle, each vertical one
+    pub symmetry: Symmetry,
+    pub max_candela: f64,
+}
+
+impl IesProfile {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<IesProfile, IesError> {
+        IesProfile::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn parse(text: &str) -> Result<IesProfile, IesError> {
+        let lines: Vec<&str> = text.lines().collect();
+        // keywords & free text, up to the TILT line
+        let tilt = lines
+            .iter()
+            .position(|l| l.trim_start().starts_with("TILT="))
+            .ok_or(IesError::Parse {
+                line: lines.len(),
+                message: "no TILT line".to_string(),
+            })?;
+        // the rest is numbers, split by spaces, commas & lines
+        let mut numbers = lines[tilt + 1..].iter().enumerate().flat_map(|(i, l)| {
+            l.split(|c: char| c.is_whitespace() || c == ',')
+                .filter(|t| !t.is_empty())
+                .map(move |t| (tilt + i + 2, t))
+        });
+        let last_line = Cell::new(tilt + 1);
+        let mut next = |what: &str| -> Result<f64, IesError> {
+            match numbers.next() {
+                Some((line, token)) => {
+                    last_line.set(line);
+                    match token.parse::<f64>() {
+                        Ok(x) if x.is_finite() => Ok(x),
+                        _ => Err(IesError::Parse {
+                            line,
+                            message: format!("bad {} '{}'", what, token),
+                        }),
+                    }
+                }
+                None => Err(IesError::Parse {
+                    line: last_line.get(),
+                    message: format!("missing {}", what),
+                }),
+            }
+        };
+        // a scale factor just read
+        let factor = |x: f64, what: &str| -> Result<f64, IesError> {
+            if x > 0.0 {
+                Ok(x)
+            } else {
+                Err(IesError::Parse {
+                    line: last_line.get(),
+                    message: format!("{} must be greater than 0", what),
+                })
+            }
+        };
+        // a number of angles just read
+        let count = |n: f64, what: &str| -> Result<usize, IesError> {
+            if n.fract() == 0.0 && (0.0..=MAX_ANGLES as f64).contains(&n) {
+                Ok(n as usize)
+            } else {
+                Err(IesError::Parse {
+                    line: last_line.get(),
+                    message: format!("bad {} {}", what, n),
+                })
+            }
+        };
+        // Tilt data only matters for lamps used at an angle, so it is
+        // read past & ignored.
+        if lines[tilt].trim() == "TILT=INCLUDE" {
+            next("lamp to luminaire geometry")?;
+            let n = count(next("number of tilt angles")?, "number of tilt angles")?;
+            for _ in 0..2 * n {
+                next("tilt data")?;
+            }
+        }
+        let _lamps = next("number of lamps")?;
+        let _lumens = next("lumens per lamp")?;
+        let multiplier = factor(next("candela multiplier")?, "candela multiplier")?;
+        let n_vertical = count(
+            next("number of vertical angles")?,
+            "number of vertical angles",
+        )?;
+        let n_horizontal = count(
+            next("number of horizontal angles")?,
+            "number of horizontal angles",
+        )?;
+        let n_candela = n_vertical
+            .checked_mul(n_horizontal)
+            .ok_or(IesError::Parse {
+                line: last_line.get(),
+                message: "too many candela values".to_string(),
+            })?;
+        let photometric_type = next("photometric type")?;
+        for what in &["units type", "width", "length", "height"] {
+            next(what)?;
+        }
+        let ballast_factor = factor(next("ballast factor")?, "ballast factor")?;
+        next("ballast lamp photometric factor")?;
+        next("input watts")?;
+        let mut read =
+            |n: usize, what: &str| (0..n).map(|_| next(what)).collect::<Result<Vec<_>, _>>();
+        let vertical = read(n_vertical, "vertical angle")?;
+        let horizontal = read(n_horizontal, "horizontal angle")?;
+        let candela = read(n_candela, "candela value")?;
+
+        let err = |message: &str| IesError::Parse {
+            line: tilt + 2,
+            message: message.to_string(),
+        };
+        if photometric_type != 1.0 {
+            return Err(err("only type C photometry is supported"));
+        }
+        if n_vertical == 0 || n_horizontal == 0 {
+            return Err(err("no angles"));
+        }
+        let ascending = |a: &[f64]| a.windows(2).all(|w| w[0] < w[1]);
+        if !ascending(&vertical) || !ascending(&horizontal) {
+            return Err(err("angles must be in ascending order"));
+        }
+        if vertical[0] < 0.0 || vertical[n_vertical - 1] > 180.0 {
+            return Err(err("vertical angles must be between 0 and 180"));
+        }
+        if horizontal[0] != 0.0 {
+            return Err(err("horizontal angles must start at 0"));
+        }
+        let last = horizontal[n_horizontal - 1];
+        let symmetry = if last == 0.0 {
+            Symmetry::Rotational
+        } else if last == 90.0 {
+            Symmetry::Quadrant
+        } else if last == 180.0 {
+            Symmetry::Bilateral
+        } else if last == 360.0 {
+            Symmetry::None
+        } else {
+            return Err(err("horizontal angles must end at 0, 90, 180 or 360"));
+        };
+        let candela: Vec<f64> = candela
+            .iter()
+            .map(|c| c * multiplier * ballast_factor)
+            .collect();
+        if candela.iter().any(|&c| c < 0.0 || !c.is_finite()) {
+            return Err(err("candela values must be finite and not negative"));
+        }
+        let max_candela = candela.iter().cloned().fold(0.0, f64::max);
+        Ok(IesProfile {
+            vertical,
+            horizontal,
+            candela,
+            symmetry,
+            max_candela,
+        })
+    }
+
+    // Candela at a vertical & horizontal angle in degrees, interpolated
+    // bilinearly.  0 outside the vertical angles measured.
+    pub fn candela(&self, vertical: f64, horizontal: f64) -> f64 {
+        let mut h = horizontal.rem_euclid(360.0);
+        match self.symmetry {
+            Symmetry::Rotational => h = 0.0,
+            Symmetry::Quadrant => {
+                if h > 180.0 {
+                    h = 360.0 - h;
+                }
+                if h > 90.0 {
+                    h = 180.0 - h;
+                }
+            }
+            Symmetry::Bilateral => {
+                if h > 180.0 {
+                    h = 360.0 - h;
+                }
+            }
+            Symmetry::None => {}
+        }
+        let (v0, v1, tv) = match bracket(&self.vertical, vertical) {
+            Some(b) => b,
+            None => return 0.0,
+        };
+        let (h0, h1, th) = bracket(&self.horizontal, h).unwrap_or((0, 0, 0.0));
+        let n = self.vertical.len();
+        let at = |hi: usize, vi: usize| self.candela[hi * n + vi];
+        let lerp = |a: f64, b: f64, t: f64| a + (b - a) * t;
+        lerp(
+            lerp(at(h0, v0), at(h0, v1), tv),
+            lerp(at(h1, v0), at(h1, v1), tv),
+            th,
+        )
+    }
+}
+
+// the entries either side of x & how far it is between them
+fn bracket(angles: &[f64], x: f64) -> Option<(usize, usize, f64)> {
+    let n = angles.len();
+    if x < angles[0] || x > angles[n - 1] {
+        return None;
+    }
+    if n == 1 {
+        return Some((0, 0, 0.0));
+    }
+    let i = (angles.partition_point(|&a| a <= x).max(1) - 1).min(n - 2);
+    let t = (x - angles[i]) / (angles[i + 1] - angles[i]);
+    Some((i, i + 1, t))
+}
+
+// ======================================================================
+// Unit testing
+// ======================================================================
+#[test]
+fn test_parse_ies() {
+    let text = "IESNA:LM-63-2002
+[TEST] 1234
+[MANUFAC] Example
+TILT=NONE
+1 1000 2 3 3 1 1 0.1 0.1 0.05
+1.0 1.0 20
+0 45 90
+0, 90, 180
+100 80 10
+50 40 5
+0 0 0
+";
+    let profile = IesProfile::parse(text).unwrap();
+    assert_eq!(profile.symmetry, Symmetry::Bilateral);
+    assert_eq!(profile.max_candela, 200.0);
+    assert_eq!(profile.candela(0.0, 0.0), 200.0);
+    // between measured angles, & mirrored across the 0-180 plane
+    assert_eq!(profile.candela(22.5, 0.0), 180.0);
+    assert_eq!(profile.candela(45.0, 45.0), 120.0);
+    assert_eq!(profile.candela(45.0, 315.0), 120.0);
+    assert_eq!(profile.candela(120.0, 0.0), 0.0);
+
+    let rotational =
+        "TILT=INCLUDE\n1\n2\n0 90\n1 1\n1 -1 1 2 1 1 1 0 0 0\n1 1 10\n0 90\n0\n10 30\n";
+    let profile = IesProfile::parse(rotational).unwrap();
+    assert_eq!(profile.symmetry, Symmetry::Rotational);
+    assert_eq!(profile.candela(45.0, 123.0), 20.0);
+
+    let quadrant = "TILT=NONE\n1 -1 1 1 2 1 1 0 0 0\n1 1 10\n0\n0 90\n10 30\n";
+    let profile = IesProfile::parse(quadrant).unwrap();
+    assert_eq!(profile.candela(0.0, 135.0), 20.0);
+    assert_eq!(profile.candela(0.0, 270.0), 30.0);
+
+    match IesProfile::parse("TILT=NONE\n1 -1 1 2 1 2 1 0 0 0\n1 1 10\n0 90\n0\n1 1\n") {
+        Err(IesError::Parse { message, .. }) => assert!(message.contains("type C")),
+        _ => panic!("expected a parse error"),
+    }
+    match IesProfile::parse("TILT=NONE\n1 -1 1 2 1 1 1 0 0 0\n1 1 10\n0 90\n0\n1\n") {
+        Err(IesError::Parse { line, message }) => {
+            assert_eq!(line, 6);
+            assert!(message.contains("missing candela value"));
+        }
+        _ => panic!("expected a parse error"),
+    }
+    // counts that are not whole numbers, or absurdly large
+    for counts in &["2.5 1", "1 -1", "1e30 1e30", "NaN 1"] {
+        let text = format!(
+            "TILT=NONE\n1 -1 1 {} 1 1 0 0 0\n1 1 10\n0 90\n0\n1 1\n",
+            counts
+        );
+        match IesProfile::parse(&text) {
+            Err(IesError::Parse { line, message }) => {
+                assert_eq!(line, 2);
+                assert!(message.contains("number of"));
+            }
+            _ => panic!("expected a parse error"),
+        }
+    }
+    // bad scale factors, numbers that are not finite, & candela too
+    // large once scaled
+    for (text, line, what) in &[
+        (
+            "1 -1 -1 2 1 1 1 0 0 0\n1 1 10\n0 90\n0\n1 1\n",
+            2,
+            "multiplier",
+        ),
+        (
+            "1 -1 1 2 1 1 1 0 0 0\n0 1 10\n0 90\n0\n1 1\n",
+            3,
+            "ballast factor",
+        ),
+        (
+            "1 -1 1 2 1 1 1 0 0 0\n1 1 10\n0 nan\n0\n1 1\n",
+            4,
+            "vertical angle",
+        ),
+        (
+            "1 -1 1 2 1 1 1 0 0 0\n1 1 10\n0 90\n0\n1 inf\n",
+            6,
+            "candela value",
+        ),
+        (
+            "1 -1 1e300 2 1 1 1 0 0 0\n1 1 10\n0 90\n0\n1 1e300\n",
+            2,
+            "finite",
+        ),
+    ] {
+        match IesProfile::parse(&format!("TILT=NONE\n{}", text)) {
+            Err(IesError::Parse { line: l, message }) => {
+                assert_eq!(l, *line);
+                assert!(message.contains(what));
+            }
+            _ => panic!("expected a parse error"),
+        }
+    }
+    match IesProfile::parse("TILT=INCLUDE\n1\n1e300\n") {
+        Err(IesError::Parse { line, .. }) => assert_eq!(line, 3),
+        _ => panic!("expected a parse error"),
+    }
+}
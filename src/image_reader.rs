@@ -0,0 +1,387 @@
+use std::io::Read;
+
+use flate2::read::ZlibDecoder;
+use vec3::Vec3;
+
+// Decoders for the high dynamic range formats image_writer writes.
+// Both give linear texels, top row first.
+
+pub type Image = (usize, usize, Vec<Vec3>);
+
+pub fn is_hdr(data: &[u8]) -> bool {
+    data.starts_with(b"#?RADIANCE") || data.starts_with(b"#?RGBE")
+}
+
+pub fn is_exr(data: &[u8]) -> bool {
+    data.starts_with(&[0x76, 0x2f, 0x31, 0x01])
+}
+
+// ======================================================================
+// Radiance .hdr
+// ======================================================================
+
+// the inverse of image_writer's to_rgbe
+fn from_rgbe(rgbe: &[u8]) -> Vec3 {
+    if rgbe[3] == 0 {
+        return Vec3::new(0.0, 0.0, 0.0);
+    }
+    let scale = 2f64.powi(rgbe[3] as i32 - (128 + 8));
+    Vec3::new(
+        (rgbe[0] as f64 + 0.5) * scale,
+        (rgbe[1] as f64 + 0.5) * scale,
+        (rgbe[2] as f64 + 0.5) * scale,
+    )
+}
+
+// Only the usual top to bottom, left to right orientation is handled.
+pub fn decode_hdr(data: &[u8]) -> Result<Image, String> {
+    let bad = |what: &str| format!("bad HDR: {}", what);
+    // the header is text lines, then a blank line, then the resolution
+    let mut pos = 0;
+    let next_line = |pos: &mut usize| -> Result<String, String> {
+        let start = *pos;
+        let end = data[start..]
+            .iter()
+            .position(|&b| b == b'\n')
+            .ok_or_else(|| bad("truncated header"))?;
+        *pos = start + end + 1;
+        Ok(String::from_utf8_lossy(&data[start..start + end]).into_owned())
+    };
+    loop {
+        let line = next_line(&mut pos)?;
+        if line.is_empty() {
+            break;
+        }
+        if let Some(format) = line.strip_prefix("FORMAT=") {
+            if format != "32-bit_rle_rgbe" {
+                return Err(bad(&format!("unsupported format {}", format)));
+            }
+        }
+    }
+    let resolution = next_line(&mut pos)?;
+    let fields: Vec<&str> = resolution.split_whitespace().collect();
+    let (height, width) = match fields[..] {
+        ["-Y", h, "+X", w] => (
+            h.parse::<usize>().map_err(|_| bad("bad height"))?,
+            w.parse::<usize>().map_err(|_| bad("bad width"))?,
+        ),
+        _ => return Err(bad(&format!("unsupported orientation '{}'", resolution))),
+    };
+
+    let mut texels = Vec::with_capacity(width * height);
+    let mut scanline = vec![0u8; 4 * width];
+    let truncated = || bad("truncated pixel data");
+    for _ in 0..height {
+        let rle = (8..0x8000).contains(&width)
+            && data.len() >= pos + 4
+            && data[pos] == 2
+            && data[pos + 1] == 2
+            && ((data[pos + 2] as usize) << 8 | data[pos + 3] as usize) == width;
+        if rle {
+            pos += 4;
+            // each component is run length encoded in turn
+            for c in 0..4 {
+                let mut x = 0;
+                while x < width {
+                    let count = *data.get(pos).ok_or_else(truncated)? as usize;
+                    pos += 1;
+                    if count > 128 {
+                        let count = count - 128;
+                        let value = *data.get(pos).ok_or_else(truncated)?;
+                        pos += 1;
+                        if x + count > width {
+                            return Err(bad("run past the end of a scanline"));
+                        }
+                        for i in 0..count {
+                            scanline[4 * (x + i) + c] = value;
+                        }
+                        x += count;
+                    } else {
+                        if count == 0 || x + count > width {
+                            return Err(bad("bad run length"));
+                        }
+                        let values = data.get(pos..pos + count).ok_or_else(truncated)?;
+                        pos += count;
+                        for (i, &value) in values.iter().enumerate() {
+                            scanline[4 * (x + i) + c] = value;
+                        }
+                        x += count;
+                    }
+                }
+            }
+        } else {
+            let flat = data.get(pos..pos + 4 * width).ok_or_else(truncated)?;
+            scanline.copy_from_slice(flat);
+            pos += 4 * width;
+        }
+        texels.extend(scanline.chunks(4).map(from_rgbe));
+    }
+    Ok((width, height, texels))
+}
+
+// ======================================================================
+// OpenEXR
+// ======================================================================
+
+fn f16_to_f32(h: u16) -> f32 {
+    let sign = ((h as u32) & 0x8000) << 16;
+    let exp = ((h >> 10) & 0x1f) as u32;
+    let mant = (h & 0x3ff) as u32;
+    let bits = if exp == 0 {
+        if mant == 0 {
+            sign
+        } else {
+            // subnormal: renormalize
+            let shift = mant.leading_zeros() - 21;
+            sign | ((113 - shift) << 23) | (((mant << shift) & 0x3ff) << 13)
+        }
+    } else if exp == 0x1f {
+        sign | 0x7f80_0000 | (mant << 13)
+    } else {
+        sign | ((exp + 112) << 23) | (mant << 13)
+    };
+    f32::from_bits(bits)
+}
+
+struct Channel {
+    name: String,
+    pixel_type: u32, // 0 uint, 1 half, 2 float
+}
+
+impl Channel {
+    fn size(&self) -> usize {
+        if self.pixel_type == 1 {
+            2
+        } else {
+            4
+        }
+    }
+}
+
+fn read_u32(data: &[u8], pos: usize) -> Option<u32> {
+    let b = data.get(pos..pos + 4)?;
+    Some(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+fn read_cstr(data: &[u8], pos: &mut usize) -> Option<String> {
+    let end = data[*pos..].iter().position(|&b| b == 0)?;
+    let s = String::from_utf8_lossy(&data[*pos..*pos + end]).into_owned();
+    *pos += end + 1;
+    Some(s)
+}
+
+// undo the byte reordering & delta coding ZIP and RLE blocks share
+fn unpredict(t: &mut [u8]) -> Vec<u8> {
+    for i in 1..t.len() {
+        t[i] = t[i - 1].wrapping_add(t[i]).wrapping_sub(128);
+    }
+    let half = t.len().div_ceil(2);
+    let mut out = Vec::with_capacity(t.len());
+    for i in 0..half {
+        out.push(t[i]);
+        if half + i < t.len() {
+            out.push(t[half + i]);
+        }
+    }
+    out
+}
+
+fn decode_rle(data: &[u8]) -> Option<Vec<u8>> {
+    let mut out = Vec::new();
+    let mut pos = 0;
+    while pos < data.len() {
+        let count = data[pos] as i8;
+        pos += 1;
+        if count < 0 {
+            let n = (-(count as i32)) as usize;
+            out.extend_from_slice(data.get(pos..pos + n)?);
+            pos += n;
+        } else {
+            let value = *data.get(pos)?;
+            pos += 1;
+            out.extend(std::iter::repeat_n(value, count as usize + 1));
+        }
+    }
+    Some(out)
+}
+
+// Single part scanline files, uncompressed or with RLE, ZIPS or ZIP
+// compression, with R, G & B channels (or just Y).
+pub fn decode_exr(data: &[u8]) -> Result<Image, String> {
+    let bad = |what: &str| format!("bad EXR: {}", what);
+    let truncated = || bad("truncated file");
+    let version = read_u32(data, 4).ok_or_else(truncated)?;
+    if version & 0xff != 2 || version & 0x1a00 != 0 {
+        return Err(bad("only single part scanline images are supported"));
+    }
+    let mut pos = 8;
+    let mut channels = Vec::new();
+    let mut compression = None;
+    let mut window = None;
+    loop {
+        let name = read_cstr(data, &mut pos).ok_or_else(truncated)?;
+        if name.is_empty() {
+            break;
+        }
+        let _kind = read_cstr(data, &mut pos).ok_or_else(truncated)?;
+        let size = read_u32(data, pos).ok_or_else(truncated)? as usize;
+        pos += 4;
+        let value = data.get(pos..pos + size).ok_or_else(truncated)?;
+        pos += size;
+        match name.as_str() {
+            "channels" => {
+                let mut p = 0;
+                loop {
+                    let name = read_cstr(value, &mut p).ok_or_else(truncated)?;
+                    if name.is_empty() {
+                        break;
+                    }
+                    let pixel_type = read_u32(value, p).ok_or_else(truncated)?;
+                    if pixel_type > 2 {
+                        return Err(bad(&format!("unknown pixel type {}", pixel_type)));
+                    }
+                    let x_sampling = read_u32(value, p + 8).ok_or_else(truncated)?;
+                    let y_sampling = read_u32(value, p + 12).ok_or_else(truncated)?;
+                    if x_sampling != 1 || y_sampling != 1 {
+                        return Err(bad("subsampled channels are not supported"));
+                    }
+                    p += 16;
+                    channels.push(Channel { name, pixel_type });
+                }
+            }
+            "compression" => compression = value.first().cloned(),
+            "dataWindow" => {
+                let v: Vec<i32> = (0..4)
+                    .map(|i| read_u32(value, 4 * i).map(|x| x as i32))
+                    .collect::<Option<_>>()
+                    .ok_or_else(truncated)?;
+                window = Some((v[0], v[1], v[2], v[3]));
+            }
+            _ => {}
+        }
+    }
+    let (x_min, y_min, x_max, y_max) = window.ok_or_else(|| bad("no dataWindow"))?;
+    if x_max < x_min || y_max < y_min {
+        return Err(bad("empty dataWindow"));
+    }
+    let (width, height) = ((x_max - x_min + 1) as usize, (y_max - y_min + 1) as usize);
+    let lines_per_block = match compression {
+        Some(0) | Some(1) | Some(2) => 1,
+        Some(3) => 16,
+        Some(c) => return Err(bad(&format!("unsupported compression type {}", c))),
+        None => return Err(bad("no compression attribute")),
+    };
+    let find = |name: &str| channels.iter().position(|c| c.name == name);
+    let rgb = match (find("R"), find("G"), find("B"), find("Y")) {
+        (Some(r), Some(g), Some(b), _) => [r, g, b],
+        (_, _, _, Some(y)) => [y, y, y],
+        _ => return Err(bad("no R, G & B or Y channels")),
+    };
+    if rgb.iter().any(|&c| channels[c].pixel_type == 0) {
+        return Err(bad("integer colour channels are not supported"));
+    }
+
+    let line_size: usize = channels.iter().map(|c| c.size() * width).sum();
+    let blocks = height.div_ceil(lines_per_block);
+    let mut texels = vec![Vec3::new(0.0, 0.0, 0.0); width * height];
+    for b in 0..blocks {
+        let offset = data
+            .get(pos + 8 * b..pos + 8 * b + 8)
+            .ok_or_else(truncated)?;
+        let mut o = [0u8; 8];
+        o.copy_from_slice(offset);
+        let offset = u64::from_le_bytes(o) as usize;
+        let y = read_u32(data, offset).ok_or_else(truncated)? as i32;
+        let size = read_u32(data, offset + 4).ok_or_else(truncated)? as usize;
+        let packed = data
+            .get(offset + 8..offset + 8 + size)
+            .ok_or_else(truncated)?;
+        if y < y_min || y > y_max {
+            return Err(bad("block outside the dataWindow"));
+        }
+        let first = (y - y_min) as usize;
+        let lines = lines_per_block.min(height - first);
+        let expected = lines * line_size;
+        let block = if size == expected || compression == Some(0) {
+            packed.to_vec()
+        } else if compression == Some(1) {
+            let mut t = decode_rle(packed).ok_or_else(|| bad("bad RLE data"))?;
+            unpredict(&mut t)
+        } else {
+            let mut t = Vec::with_capacity(expected);
+            ZlibDecoder::new(packed)
+                .read_to_end(&mut t)
+                .map_err(|e| bad(&e.to_string()))?;
+            unpredict(&mut t)
+        };
+        if block.len() != expected {
+            return Err(bad("block is the wrong size"));
+        }
+        // each line holds all of one channel, then all of the next...
+        for l in 0..lines {
+            let line = &block[l * line_size..(l + 1) * line_size];
+            let mut start = 0;
+            for (ci, channel) in channels.iter().enumerate() {
+                for (k, _) in rgb.iter().enumerate().filter(|&(_, &c)| c == ci) {
+                    for x in 0..width {
+                        let v = &line[start + x * channel.size()..];
+                        let value = if channel.pixel_type == 1 {
+                            f16_to_f32(u16::from_le_bytes([v[0], v[1]]))
+                        } else {
+                            f32::from_le_bytes([v[0], v[1], v[2], v[3]])
+                        };
+                        texels[(first + l) * width + x][k] = value as f64;
+                    }
+                }
+                start += channel.size() * width;
+            }
+        }
+    }
+    Ok((width, height, texels))
+}
+
+// ======================================================================
+// Unit testing
+// ======================================================================
+#[test]
+fn test_decode_hdr_and_exr() {
+    use image_writer::{write_image, ImageFormat};
+    // wide enough for run length encoded HDR scanlines; row 0 is the
+    // bottom of the framebuffer but the top of a decoded image
+    let framebuffer: Vec<Vec<[f64; 3]>> = (0..2)
+        .map(|j| {
+            (0..12)
+                .map(|i| [i as f64 * 0.25, j as f64 + 0.5, 4.0])
+                .collect()
+        })
+        .collect();
+    for (format, tolerance) in &[
+        (ImageFormat::Hdr, 1e-2),
+        (ImageFormat::ExrHalf, 1e-3),
+        (ImageFormat::ExrFloat, 0.0),
+    ] {
+        let mut data = Vec::new();
+        write_image(&mut data, *format, &framebuffer, 1).unwrap();
+        let (width, height, texels) = if is_hdr(&data) {
+            decode_hdr(&data).unwrap()
+        } else {
+            assert!(is_exr(&data));
+            decode_exr(&data).unwrap()
+        };
+        assert_eq!((width, height), (12, 2));
+        for (j, row) in framebuffer.iter().rev().enumerate() {
+            for (i, p) in row.iter().enumerate() {
+                let expected = Vec3::new(p[0], p[1], p[2]);
+                let error = (texels[j * width + i] - expected).length();
+                assert!(error <= *tolerance * expected.length());
+            }
+        }
+    }
+    assert!(decode_hdr(b"#?RADIANCE\n\n+Y 1 +X 1\n").is_err());
+    // RLE & ZIP blocks: runs, then the bytes interleaved & delta coded
+    assert_eq!(
+        decode_rle(&[2, 7, 0xfe, 1, 2]).unwrap(),
+        vec![7, 7, 7, 1, 2]
+    );
+    assert_eq!(unpredict(&mut [10, 128, 128, 129]), vec![10, 10, 10, 11]);
+}
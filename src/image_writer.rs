@@ -0,0 +1,550 @@
+use std::io::{self, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use png;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ImageFormat {
+    #[default]
+    PpmAscii, // P3
+    PpmBinary, // P6
+    Png8,
+    Png16,
+    // linear, high dynamic range formats
+    ExrHalf,
+    ExrFloat,
+    Hdr, // Radiance RGBE
+    Pfm,
+}
+
+impl FromStr for ImageFormat {
+    type Err = String;
+    fn from_str(s: &str) -> Result<ImageFormat, String> {
+        match s {
+            "p3" => Ok(ImageFormat::PpmAscii),
+            "p6" => Ok(ImageFormat::PpmBinary),
+            "png" | "png8" => Ok(ImageFormat::Png8),
+            "png16" => Ok(ImageFormat::Png16),
+            "exr" | "exr16" => Ok(ImageFormat::ExrHalf),
+            "exr32" => Ok(ImageFormat::ExrFloat),
+            "hdr" => Ok(ImageFormat::Hdr),
+            "pfm" => Ok(ImageFormat::Pfm),
+            _ => Err(format!(
+                "unknown image format '{}' (expected p3, p6, png, png16, exr, exr32, hdr or pfm)",
+                s
+            )),
+        }
+    }
+}
+
+impl ImageFormat {
+    // binary ppm, 8 bit png & half float exr unless asked for something else
+    pub fn from_path(path: &str) -> Option<ImageFormat> {
+        let ext = Path::new(path).extension()?.to_str()?.to_lowercase();
+        match ext.as_str() {
+            "ppm" => Some(ImageFormat::PpmBinary),
+            "png" => Some(ImageFormat::Png8),
+            "exr" => Some(ImageFormat::ExrHalf),
+            "hdr" => Some(ImageFormat::Hdr),
+            "pfm" => Some(ImageFormat::Pfm),
+            _ => None,
+        }
+    }
+}
+
+// final div by samples & gamma correction.  Values over 1.0 saturate
+// in the integer conversions below.
+#[inline(always)]
+fn to_display(sum: f64, ns: usize) -> f64 {
+    (sum / ns as f64).sqrt()
+}
+
+// HDR formats get the plain average, before any gamma or tonemapping
+#[inline(always)]
+fn to_linear(sum: f64, ns: usize) -> f32 {
+    (sum / ns as f64) as f32
+}
+
+fn to_u8(sum: f64, ns: usize) -> u8 {
+    (255.99 * to_display(sum, ns)) as u8
+}
+
+fn to_u16(sum: f64, ns: usize) -> u16 {
+    (65535.99 * to_display(sum, ns)) as u16
+}
+
+// The framebuffer holds the sum of all ns samples for each pixel, with
+// row 0 at the bottom of the image.
+pub fn write_image(
+    out: &mut dyn Write,
+    format: ImageFormat,
+    framebuffer: &[Vec<[f64; 3]>],
+    ns: usize,
+) -> io::Result<()> {
+    match format {
+        ImageFormat::PpmAscii => write_ppm_ascii(out, framebuffer, ns),
+        ImageFormat::PpmBinary => write_ppm_binary(out, framebuffer, ns),
+        ImageFormat::Png8 => write_png(out, framebuffer, ns, png::BitDepth::Eight),
+        ImageFormat::Png16 => write_png(out, framebuffer, ns, png::BitDepth::Sixteen),
+        ImageFormat::ExrHalf => write_exr(out, framebuffer, ns, ExrPixel::Half),
+        ImageFormat::ExrFloat => write_exr(out, framebuffer, ns, ExrPixel::Float),
+        ImageFormat::Hdr => write_hdr(out, framebuffer, ns),
+        ImageFormat::Pfm => write_pfm(out, framebuffer, ns),
+    }?;
+    out.flush()
+}
+
+fn write_ppm_ascii(
+    out: &mut dyn Write,
+    framebuffer: &[Vec<[f64; 3]>],
+    ns: usize,
+) -> io::Result<()> {
+    writeln!(
+        out,
+        "P3\n{0} {1} 255",
+        framebuffer[0].len(),
+        framebuffer.len()
+    )?;
+    for row in framebuffer.iter().rev() {
+        for pixel in row.iter() {
+            let ri = to_u8(pixel[0], ns);
+            let gi = to_u8(pixel[1], ns);
+            let bi = to_u8(pixel[2], ns);
+            writeln!(out, "{0} {1} {2}", ri, gi, bi)?;
+        }
+    }
+    Ok(())
+}
+
+fn write_ppm_binary(
+    out: &mut dyn Write,
+    framebuffer: &[Vec<[f64; 3]>],
+    ns: usize,
+) -> io::Result<()> {
+    write!(
+        out,
+        "P6\n{0} {1}\n255\n",
+        framebuffer[0].len(),
+        framebuffer.len()
+    )?;
+    let mut data = Vec::with_capacity(3 * framebuffer[0].len());
+    for row in framebuffer.iter().rev() {
+        data.clear();
+        for pixel in row.iter() {
+            data.extend(pixel.iter().map(|c| to_u8(*c, ns)));
+        }
+        out.write_all(&data)?;
+    }
+    Ok(())
+}
+
+fn write_png(
+    out: &mut dyn Write,
+    framebuffer: &[Vec<[f64; 3]>],
+    ns: usize,
+    depth: png::BitDepth,
+) -> io::Result<()> {
+    let nx = framebuffer[0].len();
+    let ny = framebuffer.len();
+    let mut encoder = png::Encoder::new(out, nx as u32, ny as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(depth);
+    let mut data = Vec::with_capacity(6 * nx * ny);
+    for row in framebuffer.iter().rev() {
+        for pixel in row.iter() {
+            for c in pixel.iter() {
+                match depth {
+                    // png wants 16 bit samples big-endian
+                    png::BitDepth::Sixteen => data.extend_from_slice(&to_u16(*c, ns).to_be_bytes()),
+                    _ => data.push(to_u8(*c, ns)),
+                }
+            }
+        }
+    }
+    let mut writer = encoder.write_header().map_err(io::Error::other)?;
+    writer.write_image_data(&data).map_err(io::Error::other)?;
+    writer.finish().map_err(io::Error::other)
+}
+
+// ======================================================================
+// High dynamic range formats
+// ======================================================================
+#[derive(Clone, Copy, PartialEq)]
+enum ExrPixel {
+    Half,
+    Float,
+}
+
+// IEEE 754 half float, rounding to nearest even
+fn f32_to_f16(value: f32) -> u16 {
+    let x = value.to_bits();
+    let sign = ((x >> 16) & 0x8000) as u16;
+    let exp = ((x >> 23) & 0xff) as i32;
+    let mant = x & 0x7f_ffff;
+    if exp == 0xff {
+        // inf stays inf, nan stays nan
+        return sign | 0x7c00 | if mant != 0 { 0x200 } else { 0 };
+    }
+    let e = exp - 127 + 15;
+    if e >= 0x1f {
+        return sign | 0x7c00; // too big, becomes inf
+    }
+    if e <= 0 {
+        // subnormal half, or zero if too small
+        if e < -10 {
+            return sign;
+        }
+        let m = mant | 0x80_0000;
+        let shift = (14 - e) as u32;
+        let mut h = m >> shift;
+        let rem = m & ((1 << shift) - 1);
+        let halfway = 1 << (shift - 1);
+        if rem > halfway || (rem == halfway && (h & 1) == 1) {
+            h += 1;
+        }
+        return sign | h as u16;
+    }
+    let mut h = ((e as u32) << 10) | (mant >> 13);
+    let rem = mant & 0x1fff;
+    // a carry out of the mantissa correctly bumps the exponent
+    if rem > 0x1000 || (rem == 0x1000 && (h & 1) == 1) {
+        h += 1;
+    }
+    sign | h as u16
+}
+
+fn exr_attribute(header: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+    header.extend_from_slice(name.as_bytes());
+    header.push(0);
+    header.extend_from_slice(kind.as_bytes());
+    header.push(0);
+    header.extend_from_slice(&(value.len() as i32).to_le_bytes());
+    header.extend_from_slice(value);
+}
+
+// Single part, scanline, uncompressed OpenEXR.
+fn write_exr(
+    out: &mut dyn Write,
+    framebuffer: &[Vec<[f64; 3]>],
+    ns: usize,
+    pixel: ExrPixel,
+) -> io::Result<()> {
+    let nx = framebuffer[0].len();
+    let ny = framebuffer.len();
+    let (pixel_type, pixel_size): (i32, usize) = match pixel {
+        ExrPixel::Half => (1, 2),
+        ExrPixel::Float => (2, 4),
+    };
+
+    let mut header = vec![0x76, 0x2f, 0x31, 0x01, 2, 0, 0, 0];
+    // channels are stored in alphabetical order
+    let mut channels = Vec::new();
+    for name in &["B", "G", "R"] {
+        channels.extend_from_slice(name.as_bytes());
+        channels.push(0);
+        channels.extend_from_slice(&pixel_type.to_le_bytes());
+        channels.extend_from_slice(&[0, 0, 0, 0]); // pLinear + reserved
+        channels.extend_from_slice(&1i32.to_le_bytes()); // x sampling
+        channels.extend_from_slice(&1i32.to_le_bytes()); // y sampling
+    }
+    channels.push(0);
+    exr_attribute(&mut header, "channels", "chlist", &channels);
+    exr_attribute(&mut header, "compression", "compression", &[0]);
+    let mut window = Vec::new();
+    for v in &[0, 0, nx as i32 - 1, ny as i32 - 1] {
+        window.extend_from_slice(&v.to_le_bytes());
+    }
+    exr_attribute(&mut header, "dataWindow", "box2i", &window);
+    exr_attribute(&mut header, "displayWindow", "box2i", &window);
+    exr_attribute(&mut header, "lineOrder", "lineOrder", &[0]); // increasing y
+    exr_attribute(
+        &mut header,
+        "pixelAspectRatio",
+        "float",
+        &1.0f32.to_le_bytes(),
+    );
+    exr_attribute(&mut header, "screenWindowCenter", "v2f", &[0; 8]);
+    exr_attribute(
+        &mut header,
+        "screenWindowWidth",
+        "float",
+        &1.0f32.to_le_bytes(),
+    );
+    header.push(0);
+
+    // offset table, one block per scanline
+    let data_size = 3 * nx * pixel_size;
+    let block_size = 8 + data_size;
+    let first_block = header.len() + 8 * ny;
+    for y in 0..ny {
+        header.extend_from_slice(&((first_block + y * block_size) as u64).to_le_bytes());
+    }
+    out.write_all(&header)?;
+
+    // exr y goes down the image
+    let mut block = Vec::with_capacity(block_size);
+    for (y, row) in framebuffer.iter().rev().enumerate() {
+        block.clear();
+        block.extend_from_slice(&(y as i32).to_le_bytes());
+        block.extend_from_slice(&(data_size as i32).to_le_bytes());
+        for c in (0..3).rev() {
+            for p in row.iter() {
+                let v = to_linear(p[c], ns);
+                match pixel {
+                    ExrPixel::Half => block.extend_from_slice(&f32_to_f16(v).to_le_bytes()),
+                    ExrPixel::Float => block.extend_from_slice(&v.to_le_bytes()),
+                }
+            }
+        }
+        out.write_all(&block)?;
+    }
+    Ok(())
+}
+
+// shared-exponent RGBE pixel as used by Radiance .hdr files
+fn to_rgbe(r: f32, g: f32, b: f32) -> [u8; 4] {
+    // negative & NaN channels are black, and anything brighter than the
+    // largest RGBE value (including infinity) is that
+    let largest = 255.0 / 256.0 * 2f32.powi(127);
+    let clamp = |c: f32| c.max(0.0).min(largest);
+    let (r, g, b) = (clamp(r), clamp(g), clamp(b));
+    let v = r.max(g).max(b);
+    if v < 1e-32 {
+        return [0, 0, 0, 0];
+    }
+    let mut e = v.log2().floor() as i32 + 1;
+    if v / 2f32.powi(e) >= 1.0 {
+        e += 1; // log2 rounded down across a power of two
+    }
+    let scale = 256.0 / 2f32.powi(e);
+    [
+        (r * scale) as u8,
+        (g * scale) as u8,
+        (b * scale) as u8,
+        (e + 128) as u8,
+    ]
+}
+
+// run length encode one component of a scanline (Greg Ward's scheme)
+fn write_hdr_rle(out: &mut Vec<u8>, data: &[u8]) {
+    const MIN_RUN: usize = 4;
+    let n = data.len();
+    let mut cur = 0;
+    while cur < n {
+        // find the next run of at least MIN_RUN
+        let mut beg_run = cur;
+        let mut run_count = 0;
+        let mut old_run_count = 0;
+        while run_count < MIN_RUN && beg_run < n {
+            beg_run += run_count;
+            old_run_count = run_count;
+            run_count = 1;
+            while beg_run + run_count < n
+                && run_count < 127
+                && data[beg_run] == data[beg_run + run_count]
+            {
+                run_count += 1;
+            }
+        }
+        // a short run right before the long one
+        if old_run_count > 1 && old_run_count == beg_run - cur {
+            out.push(128 + old_run_count as u8);
+            out.push(data[cur]);
+            cur = beg_run;
+        }
+        // everything up to the run as literals
+        while cur < beg_run {
+            let count = (beg_run - cur).min(128);
+            out.push(count as u8);
+            out.extend_from_slice(&data[cur..cur + count]);
+            cur += count;
+        }
+        if run_count >= MIN_RUN {
+            out.push(128 + run_count as u8);
+            out.push(data[beg_run]);
+            cur += run_count;
+        }
+    }
+}
+
+fn write_hdr(out: &mut dyn Write, framebuffer: &[Vec<[f64; 3]>], ns: usize) -> io::Result<()> {
+    let nx = framebuffer[0].len();
+    let ny = framebuffer.len();
+    write!(
+        out,
+        "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {} +X {}\n",
+        ny, nx
+    )?;
+    let mut line = Vec::new();
+    let mut components: Vec<Vec<u8>> = (0..4).map(|_| Vec::with_capacity(nx)).collect();
+    for row in framebuffer.iter().rev() {
+        line.clear();
+        for component in &mut components {
+            component.clear();
+        }
+        for p in row.iter() {
+            let rgbe = to_rgbe(
+                to_linear(p[0], ns),
+                to_linear(p[1], ns),
+                to_linear(p[2], ns),
+            );
+            for (component, value) in components.iter_mut().zip(rgbe.iter()) {
+                component.push(*value);
+            }
+        }
+        // run length encoding is only defined for these widths
+        if (8..0x8000).contains(&nx) {
+            line.extend_from_slice(&[2, 2, (nx >> 8) as u8, (nx & 0xff) as u8]);
+            for component in &components {
+                write_hdr_rle(&mut line, component);
+            }
+        } else {
+            for i in 0..nx {
+                line.extend(components.iter().map(|c| c[i]));
+            }
+        }
+        out.write_all(&line)?;
+    }
+    Ok(())
+}
+
+// Portable float map.  Little endian (negative scale), bottom row first.
+fn write_pfm(out: &mut dyn Write, framebuffer: &[Vec<[f64; 3]>], ns: usize) -> io::Result<()> {
+    write!(
+        out,
+        "PF\n{} {}\n-1.0\n",
+        framebuffer[0].len(),
+        framebuffer.len()
+    )?;
+    let mut data = Vec::with_capacity(12 * framebuffer[0].len());
+    for row in framebuffer.iter() {
+        data.clear();
+        for p in row.iter() {
+            for c in p.iter() {
+                data.extend_from_slice(&to_linear(*c, ns).to_le_bytes());
+            }
+        }
+        out.write_all(&data)?;
+    }
+    Ok(())
+}
+
+// ======================================================================
+// Unit testing
+// ======================================================================
+#[test]
+fn test_format_from_path() {
+    assert_eq!(
+        ImageFormat::from_path("out.ppm"),
+        Some(ImageFormat::PpmBinary)
+    );
+    assert_eq!(
+        ImageFormat::from_path("dir/OUT.PNG"),
+        Some(ImageFormat::Png8)
+    );
+    assert_eq!(ImageFormat::from_path("out.jpg"), None);
+    assert_eq!(ImageFormat::from_path("out"), None);
+    assert_eq!("png16".parse::<ImageFormat>(), Ok(ImageFormat::Png16));
+}
+#[test]
+fn test_write_ppm() {
+    // 2x1 image, 4 samples/pixel: one black & one white pixel
+    let framebuffer = vec![vec![[0.0, 0.0, 0.0], [4.0, 4.0, 4.0]]];
+    let mut ascii = Vec::new();
+    write_image(&mut ascii, ImageFormat::PpmAscii, &framebuffer, 4).unwrap();
+    assert_eq!(
+        String::from_utf8(ascii).unwrap(),
+        "P3\n2 1 255\n0 0 0\n255 255 255\n"
+    );
+    let mut binary = Vec::new();
+    write_image(&mut binary, ImageFormat::PpmBinary, &framebuffer, 4).unwrap();
+    assert_eq!(binary, b"P6\n2 1\n255\n\x00\x00\x00\xff\xff\xff".to_vec());
+}
+#[test]
+fn test_write_png16() {
+    let framebuffer = vec![vec![[1.0, 0.25, 0.0]]];
+    let mut data = Vec::new();
+    write_image(&mut data, ImageFormat::Png16, &framebuffer, 1).unwrap();
+    let decoder = png::Decoder::new(&data[..]);
+    let mut reader = decoder.read_info().unwrap();
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buf).unwrap();
+    assert_eq!(info.bit_depth, png::BitDepth::Sixteen);
+    assert_eq!(&buf[..6], &[0xff, 0xff, 0x7f, 0xff, 0x00, 0x00]);
+}
+#[test]
+fn test_f32_to_f16() {
+    assert_eq!(f32_to_f16(0.0), 0x0000);
+    assert_eq!(f32_to_f16(1.0), 0x3c00);
+    assert_eq!(f32_to_f16(-2.0), 0xc000);
+    assert_eq!(f32_to_f16(0.333_333_34), 0x3555);
+    assert_eq!(f32_to_f16(65504.0), 0x7bff);
+    assert_eq!(f32_to_f16(1.0e6), 0x7c00);
+    assert_eq!(f32_to_f16(2f32.powi(-24)), 0x0001);
+    assert_eq!(f32_to_f16(2f32.powi(-26)), 0x0000);
+}
+#[test]
+fn test_write_pfm() {
+    // radiance above 1.0 must survive
+    let framebuffer = vec![vec![[8.0, 2.0, 0.0]]];
+    let mut data = Vec::new();
+    write_image(&mut data, ImageFormat::Pfm, &framebuffer, 2).unwrap();
+    let mut expected = b"PF\n1 1\n-1.0\n".to_vec();
+    for v in &[4.0f32, 1.0, 0.0] {
+        expected.extend_from_slice(&v.to_le_bytes());
+    }
+    assert_eq!(data, expected);
+}
+#[test]
+fn test_write_hdr() {
+    assert_eq!(to_rgbe(1.0, 0.5, 0.0), [128, 64, 0, 129]);
+    assert_eq!(to_rgbe(0.0, 0.0, 0.0), [0, 0, 0, 0]);
+    assert_eq!(to_rgbe(f32::NAN, -1.0, 0.0), [0, 0, 0, 0]);
+    assert_eq!(to_rgbe(f32::INFINITY, f32::NAN, 0.0), [255, 0, 0, 255]);
+    assert_eq!(to_rgbe(0.0, 0.0, f32::MAX), [0, 0, 255, 255]);
+    // 1e300 is infinite as f32
+    let framebuffer = vec![vec![[1e300, 0.5, 0.0]]];
+    let mut data = Vec::new();
+    write_image(&mut data, ImageFormat::Hdr, &framebuffer, 1).unwrap();
+    assert!(data.ends_with(&[255, 0, 0, 255]));
+    // a run of identical pixels is run length encoded per component
+    let framebuffer = vec![vec![[1.0, 0.5, 0.0]; 10]];
+    let mut data = Vec::new();
+    write_image(&mut data, ImageFormat::Hdr, &framebuffer, 1).unwrap();
+    let header = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 1 +X 10\n";
+    assert_eq!(&data[..header.len()], &header[..]);
+    assert_eq!(
+        &data[header.len()..],
+        &[2, 2, 0, 10, 138, 128, 138, 64, 138, 0, 138, 129]
+    );
+}
+#[test]
+fn test_write_exr() {
+    let framebuffer = vec![vec![[1.0, 2.0, 3.0], [0.5, 0.5, 0.5]]];
+    let mut half = Vec::new();
+    write_image(&mut half, ImageFormat::ExrHalf, &framebuffer, 1).unwrap();
+    let mut float = Vec::new();
+    write_image(&mut float, ImageFormat::ExrFloat, &framebuffer, 1).unwrap();
+    assert_eq!(&half[..8], &[0x76, 0x2f, 0x31, 0x01, 2, 0, 0, 0]);
+    // the one scanline block: y, size, then B, G & R for both pixels
+    let block: Vec<u8> = [0i32, 12]
+        .iter()
+        .flat_map(|v| v.to_le_bytes().to_vec())
+        .chain(
+            [3.0f32, 0.5, 2.0, 0.5, 1.0, 0.5]
+                .iter()
+                .flat_map(|v| f32_to_f16(*v).to_le_bytes().to_vec()),
+        )
+        .collect();
+    assert!(half.ends_with(&block));
+    // the offset table points at the block
+    let offset_pos = half.len() - block.len() - 8;
+    let mut offset = [0u8; 8];
+    offset.copy_from_slice(&half[offset_pos..offset_pos + 8]);
+    assert_eq!(
+        u64::from_le_bytes(offset) as usize,
+        half.len() - block.len()
+    );
+    assert_eq!(float.len() - half.len(), 6 * 2);
+}
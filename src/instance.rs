@@ -0,0 +1,134 @@
+use std::sync::Arc;
+
+use aabb::Aabb;
+use hitable::{HitRecord, Hitable};
+use ray::Ray;
+use vec3::{unit_vector, Mat4, Vec3};
+
+// A shared object placed in the world by an affine transform, so one
+// mesh can appear many times without copying its triangles.  Rays are
+// taken into the object's space rather than the object into the world.
+pub struct Instance {
+    object: Arc<dyn Hitable>,
+    transform: Mat4,
+    inverse: Mat4,
+    // normals go back out with the inverse transpose
+    normal_transform: Mat4,
+}
+
+impl Instance {
+    // panics if the transform is singular; check transform.inverse() first
+    pub fn new(object: Arc<dyn Hitable>, transform: Mat4) -> Instance {
+        let inverse = transform
+            .inverse()
+            .expect("Instance::new called with a singular transform");
+        Instance {
+            object,
+            transform,
+            inverse,
+            normal_transform: inverse.transpose(),
+        }
+    }
+}
+
+impl Hitable for Instance {
+    fn hit<'a>(&'a self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord<'a>) -> bool {
+        // the direction is not renormalized, so t is the same in both spaces
+        let local = Ray::with_time(
+            self.inverse.transform_point(r.origin),
+            self.inverse.transform_vector(r.direction),
+            r.time,
+        );
+        if !self.object.hit(&local, t_min, t_max, rec) {
+            return false;
+        }
+        rec.p = r.point_at_parameter(rec.t);
+        rec.normal = unit_vector(self.normal_transform.transform_vector(rec.normal));
+        true
+    }
+    fn bounding_box(&self, output_box: &mut Aabb) -> bool {
+        let mut local = Aabb::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 0.0));
+        if !self.object.bounding_box(&mut local) {
+            return false;
+        }
+        // the box around all eight transformed corners
+        let mut min = Vec3::new(f64::MAX, f64::MAX, f64::MAX);
+        let mut max = Vec3::new(-f64::MAX, -f64::MAX, -f64::MAX);
+        for i in 0..8 {
+            let corner = Vec3::new(
+                if i & 1 == 0 {
+                    local.min.x()
+                } else {
+                    local.max.x()
+                },
+                if i & 2 == 0 {
+                    local.min.y()
+                } else {
+                    local.max.y()
+                },
+                if i & 4 == 0 {
+                    local.min.z()
+                } else {
+                    local.max.z()
+                },
+            );
+            let p = self.transform.transform_point(corner);
+            for a in 0..3 {
+                min[a] = min[a].min(p[a]);
+                max[a] = max[a].max(p[a]);
+            }
+        }
+        *output_box = Aabb::new(min, max);
+        true
+    }
+}
+
+// ======================================================================
+// Unit testing
+// ======================================================================
+#[test]
+fn test_instance_hit() {
+    use material::Material;
+    use sphere::Sphere;
+    let sphere: Arc<dyn Hitable> = Arc::new(Sphere::new(
+        Vec3::new(0.0, 0.0, 0.0),
+        1.0,
+        Material::Dielectric { ref_idx: 1.5 },
+    ));
+    // squash it flat in y, then move it down the -z axis
+    let flat = Instance::new(
+        sphere.clone(),
+        Mat4::scale(Vec3::new(1.0, 0.5, 1.0)).then(&Mat4::translate(Vec3::new(0.0, 0.0, -5.0))),
+    );
+    let r = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0));
+    let mut rec = HitRecord::new();
+    assert!(flat.hit(&r, 0.001, f64::MAX, &mut rec));
+    assert!((rec.t - 4.0).abs() < 1e-9);
+    assert!((rec.p - Vec3::new(0.0, 0.0, -4.0)).length() < 1e-9);
+    assert!((rec.normal - Vec3::new(0.0, 0.0, 1.0)).length() < 1e-9);
+    // a ray just above the squashed sphere misses it
+    let above = Ray::new(Vec3::new(0.0, 0.6, 0.0), Vec3::new(0.0, 0.0, -1.0));
+    assert!(!flat.hit(&above, 0.001, f64::MAX, &mut rec));
+    // off the pole, the normal tilts twice as far as the scaled point would
+    let slant = Ray::new(Vec3::new(0.0, 0.25, 0.0), Vec3::new(0.0, 0.0, -1.0));
+    assert!(flat.hit(&slant, 0.001, f64::MAX, &mut rec));
+    let local = Vec3::new(0.0, 0.5, (0.75f64).sqrt());
+    let expected = unit_vector(Vec3::new(0.0, 2.0 * local.y(), local.z()));
+    assert!((rec.normal - expected).length() < 1e-9);
+}
+#[test]
+fn test_instance_bounding_box() {
+    use cuboid::Cuboid;
+    use material::Material;
+    let cube: Arc<dyn Hitable> = Arc::new(Cuboid::new(
+        Vec3::new(0.0, 0.0, 0.0),
+        Vec3::new(1.0, 1.0, 1.0),
+        Material::Dielectric { ref_idx: 1.5 },
+    ));
+    let turned = Instance::new(cube, Mat4::rotate(Vec3::new(0.0, 1.0, 0.0), 45.0));
+    let mut bbox = Aabb::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 0.0));
+    assert!(turned.bounding_box(&mut bbox));
+    let h = (0.5f64).sqrt();
+    assert!((bbox.min - Vec3::new(0.0, 0.0, -h)).length() < 1e-9);
+    assert!((bbox.max - Vec3::new(2.0 * h, 1.0, h)).length() < 1e-9);
+}
@@ -0,0 +1,81 @@
+use std::sync::Arc;
+
+use hitable::Hitable;
+use rand::Rng;
+use vec3::Vec3;
+
+// The lights in the scene, sampled directly for next event estimation.
+// One light is picked uniformly for each sample, so the pdf of a
+// direction is the average of the lights' pdfs.
+pub struct LightList {
+    lights: Vec<Arc<dyn Hitable>>,
+}
+
+impl LightList {
+    pub fn new(lights: Vec<Arc<dyn Hitable>>) -> LightList {
+        LightList { lights }
+    }
+    pub fn push(&mut self, light: Arc<dyn Hitable>) {
+        self.lights.push(light);
+    }
+    pub fn is_empty(&self) -> bool {
+        self.lights.is_empty()
+    }
+    pub fn pdf_value(&self, o: &Vec3, v: &Vec3) -> f64 {
+        if self.lights.is_empty() {
+            return 0.0;
+        }
+        let sum: f64 = self.lights.iter().map(|l| l.pdf_value(o, v)).sum();
+        sum / self.lights.len() as f64
+    }
+    // a direction from o towards one of the lights
+    pub fn random<R: Rng>(&self, o: &Vec3, rng: &mut R) -> Vec3 {
+        let n = self.lights.len();
+        let i = ((rng.gen::<f64>() * n as f64) as usize).min(n - 1);
+        self.lights[i].random(o, (rng.gen::<f64>(), rng.gen::<f64>()))
+    }
+}
+
+// Weight for a sample from a strategy with pdf `a` when another with
+// pdf `b` could have made it too (Veach's power heuristic, beta = 2).
+pub fn power_heuristic(a: f64, b: f64) -> f64 {
+    let (a2, b2) = (a * a, b * b);
+    if a2 + b2 == 0.0 {
+        0.0
+    } else {
+        a2 / (a2 + b2)
+    }
+}
+
+// ======================================================================
+// Unit testing
+// ======================================================================
+#[test]
+fn test_light_list() {
+    use material::Material;
+    use rand::{SeedableRng, StdRng};
+    use rect::AaRect;
+    let seed: &[_] = &[7];
+    let mut rng: StdRng = SeedableRng::from_seed(seed);
+    let light = || Material::DiffuseLight {
+        emit: Vec3::new(1.0, 1.0, 1.0),
+        strength: 1.0,
+    };
+    let above: Arc<dyn Hitable> = Arc::new(AaRect::xz(-1.0, 1.0, -1.0, 1.0, 2.0, light()));
+    let below: Arc<dyn Hitable> = Arc::new(AaRect::xz(-1.0, 1.0, -1.0, 1.0, -2.0, light()));
+    let lights = LightList::new(vec![above.clone(), below]);
+    let o = Vec3::new(0.0, 0.0, 0.0);
+    let up = Vec3::new(0.0, 1.0, 0.0);
+    assert_eq!(lights.pdf_value(&o, &up), 0.5 * above.pdf_value(&o, &up));
+    let (mut ups, n) = (0, 1000);
+    for _ in 0..n {
+        let v = lights.random(&o, &mut rng);
+        assert!(lights.pdf_value(&o, &v) > 0.0);
+        if v.y() > 0.0 {
+            ups += 1;
+        }
+    }
+    assert!((ups as f64 / n as f64 - 0.5).abs() < 0.05);
+    assert_eq!(power_heuristic(1.0, 1.0), 0.5);
+    assert_eq!(power_heuristic(0.0, 0.0), 0.0);
+}
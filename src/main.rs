@@ -1,74 +1,303 @@
-// cargo run > out.ppm
+// cargo run --release -- --help
+extern crate flate2;
+extern crate getopts;
+extern crate jpeg_decoder;
+extern crate png;
 extern crate rand;
 extern crate rayon;
+extern crate serde;
+extern crate toml;
 
+mod aabb;
+mod aperture;
+mod background;
+mod bvh;
 mod camera;
+mod cuboid;
+mod environment;
+mod flip_normals;
 mod hitable;
 mod hitable_list;
+mod ies;
+mod image_reader;
+mod image_writer;
+mod instance;
+mod light;
 mod material;
+mod medium;
+mod mesh;
+mod microfacet;
+mod moving_sphere;
+mod onb;
+mod options;
+mod principled;
+mod punctual;
 mod ray;
+mod rect;
+mod scene;
+mod sky;
+mod spectrum;
 mod sphere;
+mod texture;
+mod triangle;
 mod vec3;
 
-use camera::Camera;
+use background::Background;
+use cuboid::Cuboid;
+use flip_normals::FlipNormals;
 use hitable::{HitRecord, Hitable};
 use hitable_list::HitableList;
-use material::{scatter, Material};
+use image_writer::write_image;
+use instance::Instance;
+use light::{power_heuristic, LightList};
+use material::{emitted, Material};
+use medium::ConstantMedium;
+use mesh::TriangleMesh;
+use options::SceneName;
+use punctual::PunctualLight;
 use rand::{Rng, SeedableRng, StdRng};
 use ray::Ray;
+use rect::AaRect;
+use scene::Scene;
+use spectrum::{Spectral, Wavelengths};
 use sphere::Sphere;
+use texture::solid;
+use std::collections::HashMap;
 use std::f64;
-use vec3::{unit_vector, Vec3};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use vec3::{dot, unit_vector, Mat4, Vec3};
 use rayon::prelude::*;
 use std::sync::{Arc, Mutex};
 use std::time::Instant;
 
+// Everything color() needs to know about the scene.
+struct Tracer<'a> {
+    world: &'a dyn Hitable,
+    lights: &'a LightList,
+    punctual: &'a [PunctualLight],
+    media: &'a [ConstantMedium],
+    background: &'a Background,  // what the camera sees
+    environment: &'a Background, // what lights the scene
+    max_depth: i32,
+}
+
+// bsdf_pdf is the pdf of the bounce that made r, if it could have been
+// made by sampling a light too, so what it hits can be weighted.  In
+// spectral mode lambda holds the path's wavelengths, and the Vec3s
+// color() deals in are radiance at them instead of RGB.
 fn color<R: Rng>(
     r: &Ray,
-    world: &HitableList,
+    tracer: &Tracer,
     depth: i32,
+    bsdf_pdf: Option<f64>,
+    lambda: Option<&Wavelengths>,
     rng: &mut R,
     ray_count: &mut i32,
 ) -> Vec3 {
+    let spectrum = |c: Vec3| lambda.map_or(c, |w| w.upsample(&c));
     let mut rec = HitRecord::new();
-    if world.hit(r, 0.001, f64::MAX, &mut rec) {
-        let mut scattered = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 0.0));
+    let hit = tracer.world.hit(r, 0.001, f64::MAX, &mut rec);
+
+    // scattering in a medium before the ray gets to the surface?
+    let mut t_medium = if hit { rec.t } else { f64::MAX };
+    let mut scattering_medium = None;
+    for medium in tracer.media {
+        if let Some(t) = medium.sample_distance(r, t_medium, rng) {
+            t_medium = t;
+            scattering_medium = Some(medium);
+        }
+    }
+    if let Some(medium) = scattering_medium {
         *ray_count += 1;
-        let mut attenuation = Vec3::new(0.0, 0.0, 0.0);
-        if depth < 50 && scatter(r, &rec, &mut attenuation, &mut scattered, rng) {
-            attenuation * color(&scattered, world, depth + 1, rng, ray_count)
+        if depth >= tracer.max_depth {
+            return Vec3::new(0.0, 0.0, 0.0);
+        }
+        let (attenuation, scattered) = medium.scatter(r, t_medium, rng);
+        return spectrum(attenuation)
+            * color(&scattered, tracer, depth + 1, None, lambda, rng, ray_count);
+    }
+
+    if hit {
+        *ray_count += 1;
+        let mut emitted = spectrum(emitted(&rec));
+        if let Some(bsdf_pdf) = bsdf_pdf {
+            if emitted != Vec3::new(0.0, 0.0, 0.0) {
+                // sampling the lights could have found this too
+                let light_pdf = tracer.lights.pdf_value(&r.origin, &r.direction);
+                emitted *= power_heuristic(bsdf_pdf, light_pdf);
+            }
+        }
+        if depth >= tracer.max_depth {
+            return emitted;
+        }
+        let dispersed = lambda.and_then(|w| rec.material.at_wavelength(w.hero()));
+        let material = dispersed.as_ref().unwrap_or(rec.material);
+        let hero = match lambda {
+            Some(w) if dispersed.is_some() => w.drop_secondary(),
+            _ => None,
+        };
+        let (lambda, hero_weight) = match hero {
+            Some((ref w, weight)) => (Some(w), Some(weight)),
+            None => (lambda, None),
+        };
+        let wo = -unit_vector(r.direction);
+        let sample = match material.sample(&rec, &wo, rng) {
+            Some(s) => s,
+            None => return emitted,
+        };
+        // Next event estimation: light the point directly as well, unless
+        // it is a mirror that only sees what the sample does.
+        let mut direct = Vec3::new(0.0, 0.0, 0.0);
+        if sample.pdf.is_some() {
+            if !tracer.lights.is_empty() {
+                direct += direct_light(r, &rec, material, &wo, tracer, lambda, rng, ray_count);
+            }
+            for light in tracer.punctual {
+                direct += punctual_light(
+                    r, &rec, material, &wo, light, tracer, lambda, rng, ray_count,
+                );
+            }
+        }
+        let mut weight = spectrum(sample.weight);
+        if let Some(hero_weight) = hero_weight {
+            direct *= hero_weight;
+            weight *= hero_weight;
+        }
+        let scattered = Ray::with_time(rec.p, sample.wi, r.time);
+        let incoming = color(
+            &scattered,
+            tracer,
+            depth + 1,
+            sample.pdf,
+            lambda,
+            rng,
+            ray_count,
+        );
+        emitted + direct + weight * incoming
+    } else {
+        let sky = if depth == 0 {
+            tracer.background
         } else {
-            Vec3::new(0.0, 0.0, 0.0)
+            tracer.environment
+        };
+        let mut sky = spectrum(sky.color(r));
+        if let Some(bsdf_pdf) = bsdf_pdf {
+            if sky != Vec3::new(0.0, 0.0, 0.0) {
+                // an environment map is sampled as a light too
+                let light_pdf = tracer.lights.pdf_value(&r.origin, &r.direction);
+                sky *= power_heuristic(bsdf_pdf, light_pdf);
+            }
         }
+        sky
+    }
+}
+
+// Light reaching the surface at rec straight from a sampled light and
+// leaving along wo.  The shadow ray's weight is the complement of the
+// one color() gives bounces that hit a light.
+#[allow(clippy::too_many_arguments)]
+fn direct_light<R: Rng>(
+    r: &Ray,
+    rec: &HitRecord,
+    material: &Material,
+    wo: &Vec3,
+    tracer: &Tracer,
+    lambda: Option<&Wavelengths>,
+    rng: &mut R,
+    ray_count: &mut i32,
+) -> Vec3 {
+    let black = Vec3::new(0.0, 0.0, 0.0);
+    let direction = tracer.lights.random(&rec.p, rng);
+    let wi = unit_vector(direction);
+    let f = material.eval(rec, &wi, wo);
+    let light_pdf = tracer.lights.pdf_value(&rec.p, &direction);
+    if f == black || light_pdf <= 0.0 {
+        return black;
+    }
+    let shadow = Ray::with_time(rec.p, direction, r.time);
+    *ray_count += 1;
+    let mut light_rec = HitRecord::new();
+    let hit = tracer.world.hit(&shadow, 0.001, f64::MAX, &mut light_rec);
+    // media can scatter the light away before it gets here
+    let t_max = if hit { light_rec.t } else { f64::MAX };
+    for medium in tracer.media {
+        if medium.sample_distance(&shadow, t_max, rng).is_some() {
+            return black;
+        }
+    }
+    // missing everything is seeing the environment
+    let emitted = if hit {
+        emitted(&light_rec)
     } else {
-        let unit_direction = unit_vector(r.direction);
-        let t = 0.5 * (unit_direction.y() + 1.0);
-        (1.0 - t) * Vec3::new(1.0, 1.0, 1.0) + t * Vec3::new(0.5, 0.7, 1.0)
+        tracer.environment.color(&shadow)
+    };
+    let cosine = dot(&wi, &rec.normal).abs();
+    let weight = power_heuristic(light_pdf, material.pdf(rec, &wi, wo));
+    let spectrum = |c: Vec3| lambda.map_or(c, |w| w.upsample(&c));
+    spectrum(f) * spectrum(emitted) * (cosine * weight / light_pdf)
+}
+
+// Light reaching the surface at rec from a punctual light and leaving
+// along wo.  Nothing else can find these lights, so there is no MIS.
+#[allow(clippy::too_many_arguments)]
+fn punctual_light<R: Rng>(
+    r: &Ray,
+    rec: &HitRecord,
+    material: &Material,
+    wo: &Vec3,
+    light: &PunctualLight,
+    tracer: &Tracer,
+    lambda: Option<&Wavelengths>,
+    rng: &mut R,
+    ray_count: &mut i32,
+) -> Vec3 {
+    let black = Vec3::new(0.0, 0.0, 0.0);
+    let (wi, distance, irradiance) = match light.illuminate(&rec.p) {
+        Some(l) => l,
+        None => return black,
+    };
+    let f = material.eval(rec, &wi, wo);
+    if f == black {
+        return black;
+    }
+    let shadow = Ray::with_time(rec.p, wi, r.time);
+    *ray_count += 1;
+    let mut shadow_rec = HitRecord::new();
+    if tracer.world.hit(&shadow, 0.001, distance, &mut shadow_rec) {
+        return black;
+    }
+    for medium in tracer.media {
+        if medium.sample_distance(&shadow, distance, rng).is_some() {
+            return black;
+        }
     }
+    let cosine = dot(&wi, &rec.normal).abs();
+    let spectrum = |c: Vec3| lambda.map_or(c, |w| w.upsample(&c));
+    spectrum(f) * spectrum(irradiance) * cosine
 }
 
-#[allow(dead_code)]
-fn original_scene(world: &mut HitableList) -> &HitableList {
+fn original_scene(world: &mut HitableList) {
     /* original world */
     world.push(Sphere::new(
         Vec3::new(0.0, 0.0, -1.0),
         0.5,
         Material::Lambertian {
-            albedo: Vec3::new(0.1, 0.3, 0.5),
+            albedo: solid(Vec3::new(0.1, 0.3, 0.5)),
         },
     ));
     world.push(Sphere::new(
         Vec3::new(0.0, -100.5, -1.0),
         100.0,
         Material::Lambertian {
-            albedo: Vec3::new(0.8, 0.8, 0.0),
+            albedo: solid(Vec3::new(0.8, 0.8, 0.0)),
         },
     ));
     world.push(Sphere::new(
         Vec3::new(1.0, 0.0, -1.0),
         0.5,
         Material::Metal {
-            albedo: Vec3::new(0.8, 0.6, 0.2),
+            albedo: solid(Vec3::new(0.8, 0.6, 0.2)),
             fuzz: 1.0,
         },
     ));
@@ -86,36 +315,68 @@ fn original_scene(world: &mut HitableList) -> &HitableList {
         -0.45, // makes a bubble inside sphere
         Material::Dielectric { ref_idx: 1.5 },
     ));
-    world
 }
 
-#[allow(dead_code)]
-fn redblue_scene(world: &mut HitableList) -> &HitableList {
+fn redblue_scene(world: &mut HitableList) {
     let r = (std::f64::consts::PI / 4.0).cos();
     world.push(Sphere::new(
         Vec3::new(-r, 0.0, -1.0),
         r,
         Material::Lambertian {
-            albedo: Vec3::new(0.0, 0.0, 1.0),
+            albedo: solid(Vec3::new(0.0, 0.0, 1.0)),
         },
     ));
     world.push(Sphere::new(
         Vec3::new(r, 0.0, -1.0),
         r,
         Material::Lambertian {
-            albedo: Vec3::new(1.0, 0.0, 0.0),
+            albedo: solid(Vec3::new(1.0, 0.0, 0.0)),
         },
     ));
-    world
 }
 
-#[allow(dead_code)]
-fn final_scene<'a, R: Rng>(world: &'a mut HitableList, rng: &mut R) -> &'a HitableList {
+// The model is built into the renderer, so this works wherever it is
+// run from or installed.
+fn mesh_scene(world: &mut HitableList) {
+    world.push(Sphere::new(
+        Vec3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        Material::Lambertian {
+            albedo: solid(Vec3::new(0.5, 0.5, 0.5)),
+        },
+    ));
+    let mut materials = HashMap::new();
+    materials.insert(
+        "base".to_string(),
+        Material::Lambertian {
+            albedo: solid(Vec3::new(0.4, 0.2, 0.1)),
+        },
+    );
+    materials.insert(
+        "sides".to_string(),
+        Material::Metal {
+            albedo: solid(Vec3::new(0.7, 0.6, 0.5)),
+            fuzz: 0.1,
+        },
+    );
+    let pyramid = TriangleMesh::parse_obj(
+        include_str!("../models/pyramid.obj").as_bytes(),
+        &materials,
+        Material::Lambertian {
+            albedo: solid(Vec3::new(0.5, 0.5, 0.5)),
+        },
+    )
+    .unwrap_or_else(|e| panic!("models/pyramid.obj: {}", e));
+    eprintln!("loaded {} triangles", pyramid.num_triangles());
+    world.push(pyramid);
+}
+
+fn final_scene<R: Rng>(world: &mut HitableList, rng: &mut R) {
     world.push(Sphere::new(
         Vec3::new(0., -1000., 0.),
         1000.,
         Material::Lambertian {
-            albedo: Vec3::new(0.5, 0.5, 0.5),
+            albedo: solid(Vec3::new(0.5, 0.5, 0.5)),
         },
     ));
 
@@ -133,11 +394,11 @@ fn final_scene<'a, R: Rng>(world: &'a mut HitableList, rng: &mut R) -> &'a Hitab
                         center,
                         0.2,
                         Material::Lambertian {
-                            albedo: Vec3::new(
+                            albedo: solid(Vec3::new(
                                 rng.gen::<f64>() * rng.gen::<f64>(),
                                 rng.gen::<f64>() * rng.gen::<f64>(),
                                 rng.gen::<f64>() * rng.gen::<f64>(),
-                            ),
+                            )),
                         },
                     ));
                 } else if choose_mat < 0.95 {
@@ -145,11 +406,11 @@ fn final_scene<'a, R: Rng>(world: &'a mut HitableList, rng: &mut R) -> &'a Hitab
                         center,
                         0.2,
                         Material::Metal {
-                            albedo: Vec3::new(
+                            albedo: solid(Vec3::new(
                                 0.5 * (1. + rng.gen::<f64>()),
                                 0.5 * (1. + rng.gen::<f64>()),
                                 0.5 * (1. + rng.gen::<f64>()),
-                            ),
+                            )),
                             fuzz: 0.5 * rng.gen::<f64>(),
                         },
                     ));
@@ -173,69 +434,193 @@ fn final_scene<'a, R: Rng>(world: &'a mut HitableList, rng: &mut R) -> &'a Hitab
         Vec3::new(-4., 1., 0.),
         1.,
         Material::Lambertian {
-            albedo: Vec3::new(0.4, 0.2, 0.1),
+            albedo: solid(Vec3::new(0.4, 0.2, 0.1)),
         },
     ));
     world.push(Sphere::new(
         Vec3::new(4., 1., 0.),
         1.,
         Material::Metal {
-            albedo: Vec3::new(0.7, 0.6, 0.5),
+            albedo: solid(Vec3::new(0.7, 0.6, 0.5)),
             fuzz: 0.0,
         },
     ));
+}
+
+// The Cornell box from "The Next Week", lit only by its ceiling light.
+fn cornell_box(scene: &mut Scene) {
+    let red = Material::Lambertian {
+        albedo: solid(Vec3::new(0.65, 0.05, 0.05)),
+    };
+    let white = Material::Lambertian {
+        albedo: solid(Vec3::new(0.73, 0.73, 0.73)),
+    };
+    let green = Material::Lambertian {
+        albedo: solid(Vec3::new(0.12, 0.45, 0.15)),
+    };
+    let light = Material::DiffuseLight {
+        emit: Vec3::new(1.0, 1.0, 1.0),
+        strength: 15.0,
+    };
+    let world = &mut scene.world;
+    world.push(FlipNormals::new(AaRect::yz(
+        0., 555., 0., 555., 555., green,
+    )));
+    world.push(AaRect::yz(0., 555., 0., 555., 0., red));
+    world.push(AaRect::xz(213., 343., 227., 332., 554., light));
+    world.push(FlipNormals::new(AaRect::xz(
+        0., 555., 0., 555., 555., white.clone(),
+    )));
+    world.push(AaRect::xz(0., 555., 0., 555., 0., white.clone()));
+    world.push(FlipNormals::new(AaRect::xy(
+        0., 555., 0., 555., 555., white.clone(),
+    )));
+    let up = Vec3::new(0., 1., 0.);
+    let tall: Arc<dyn Hitable> = Arc::new(Cuboid::new(
+        Vec3::new(0., 0., 0.),
+        Vec3::new(165., 330., 165.),
+        white.clone(),
+    ));
+    world.push(Instance::new(
+        tall,
+        Mat4::rotate(up, 15.0).then(&Mat4::translate(Vec3::new(265., 0., 295.))),
+    ));
+    let short: Arc<dyn Hitable> = Arc::new(Cuboid::new(
+        Vec3::new(0., 0., 0.),
+        Vec3::new(165., 165., 165.),
+        white,
+    ));
+    world.push(Instance::new(
+        short,
+        Mat4::rotate(up, -18.0).then(&Mat4::translate(Vec3::new(130., 0., 65.))),
+    ));
 
-    world
+    scene.image.width = 600;
+    scene.image.height = 600;
+    scene.image.samples = 256;
+    scene.camera.lookfrom = Vec3::new(278., 278., -800.);
+    scene.camera.lookat = Vec3::new(278., 278., 0.);
+    scene.camera.vfov = 40.0;
+    scene.camera.aperture = 0.0;
+    scene.background = Background::Constant(Vec3::new(0.0, 0.0, 0.0));
 }
 
 fn main() {
-    let seed: &[_] = &[1984];
+    let args: Vec<String> = std::env::args().collect();
+    let options = match options::parse_args(&args[1..]) {
+        Ok(o) => o,
+        Err(e) => {
+            eprintln!("{}: {}\nTry '{} --help' for more information.", args[0], e, args[0]);
+            std::process::exit(2);
+        }
+    };
+    if options.help {
+        print!("{}", options::usage(&args[0]));
+        return;
+    }
+
+    let seed: &[_] = &[options.seed];
     let mut rng: StdRng = SeedableRng::from_seed(seed);
 
-    const NX: usize = 1440;
-    const NY: usize = 720;
-    const NS: i32 = 32;
+    let mut scene = match options.scene_file {
+        Some(ref path) => match scene::load_scene(path) {
+            Ok(scene) => scene,
+            Err(e) => {
+                eprintln!("{}: {}: {}", args[0], path, e);
+                std::process::exit(1);
+            }
+        },
+        None => {
+            let mut scene = Scene::new(HitableList::new());
+            match options.scene {
+                SceneName::Original => original_scene(&mut scene.world),
+                SceneName::RedBlue => redblue_scene(&mut scene.world),
+                SceneName::Final => final_scene(&mut scene.world, &mut rng),
+                SceneName::Mesh => mesh_scene(&mut scene.world),
+                SceneName::CornellBox => cornell_box(&mut scene),
+            }
+            scene
+        }
+    };
+    options.apply(&mut scene);
 
-    eprintln!("rendering {}x{} image with {} samples/pixel", NX, NY, NS);
-    let mut the_world = HitableList::new();
-    //let world = original_scene(&mut the_world);
-    //let world = redblue_scene(&mut the_world);
-    let world = final_scene(&mut the_world, &mut rng);
+    let nx = scene.image.width;
+    let ny = scene.image.height;
+    let ns = scene.image.samples;
+    let max_depth = scene.image.max_depth;
 
-    let lookfrom = Vec3::new(13., 2., 3.);
-    let lookat = Vec3::new(0., 0., 0.);
-    let dist_to_focus = 10.0; //(lookfrom - lookat).length();
-    let aperture = 0.1;
-    let cam = Camera::new(
-        lookfrom,
-        lookat,
-        Vec3::new(0., 1., 0.),
-        30.0,
-        NX as f64 / NY as f64,
-        aperture,
-        dist_to_focus,
-    );
+    eprintln!("rendering {}x{} image with {} samples/pixel", nx, ny, ns);
+    let spectral = if scene.image.spectral {
+        Some(Spectral::new())
+    } else {
+        None
+    };
+    let cam = scene.camera();
+    let background = scene.background.clone();
+    let environment = scene
+        .environment
+        .clone()
+        .unwrap_or_else(|| background.clone());
+    let mut media = scene.fog_medium().into_iter().collect::<Vec<_>>();
+    media.extend(scene.media);
+    let mut lights = if options.no_light_sampling {
+        LightList::new(Vec::new())
+    } else {
+        scene.world.share_lights()
+    };
+    if !options.no_light_sampling {
+        for light in environment.lights() {
+            lights.push(light);
+        }
+    }
+    let world = &scene.world.into_bvh();
+    let tracer = Tracer {
+        world,
+        lights: &lights,
+        punctual: &scene.punctual_lights,
+        media: &media,
+        background: &background,
+        environment: &environment,
+        max_depth,
+    };
 
     let num_rays = Arc::new(Mutex::new(0));
     // use thread per row for concurrency.
-    let mut framebuffer = vec![[[0.0f64; 3]; NX]; NY];
+    let mut framebuffer = vec![vec![[0.0f64; 3]; nx]; ny];
     let render_start = Instant::now();
     framebuffer
         .par_iter_mut() // rayon speedup here
         .enumerate()
-        .map(|(j, framebuffer_row): (usize, &mut [[f64; 3]; NX])| {
-            let seed2: &[_] = &[1984 + j];
+        .map(|(j, framebuffer_row): (usize, &mut Vec<[f64; 3]>)| {
+            let seed2: &[_] = &[options.seed.wrapping_add(j)];
             let mut rng2: StdRng = SeedableRng::from_seed(seed2);
             let num_rays = Arc::clone(&num_rays);
             let mut row_rays = 0;
-            for i in 0..NX {
+            for i in 0..nx {
                 let mut col = Vec3::new(0.0, 0.0, 0.0);
-                for _s in 0..NS {
-                    let u = (i as f64 + rng2.gen::<f64>()) / (NX as f64);
-                    let v = (j as f64 + rng2.gen::<f64>()) / (NY as f64);
-                    let r = cam.get_ray(u, v, &mut rng2);
+                for _s in 0..ns {
+                    let u = (i as f64 + rng2.gen::<f64>()) / (nx as f64);
+                    let v = (j as f64 + rng2.gen::<f64>()) / (ny as f64);
+                    // a sample the lens blocks adds nothing
+                    let r = match cam.get_ray(u, v, &mut rng2) {
+                        Some(r) => r,
+                        None => continue,
+                    };
                     row_rays += 1;
-                    col += color(&r, world, 0, &mut rng2, &mut row_rays);
+                    let lambda = spectral.map(|s| s.wavelengths(rng2.gen::<f64>()));
+                    let radiance = color(
+                        &r,
+                        &tracer,
+                        0,
+                        None,
+                        lambda.as_ref(),
+                        &mut rng2,
+                        &mut row_rays,
+                    );
+                    col += match lambda {
+                        Some(ref w) => w.to_rgb(&radiance),
+                        None => radiance,
+                    };
                 }
                 (*framebuffer_row)[i][0] = col[0];
                 (*framebuffer_row)[i][1] = col[1];
@@ -256,14 +641,18 @@ fn main() {
         safe_num_rays, render_secs, rays_per_sec
     );
 
-    println!("P3\n{0} {1} 255", NX, NY);
-    for j in (0..NY).rev() {
-        for i in 0..NX {
-            // final div by samples & gamma correction
-            let ri = (255.99 * (framebuffer[j][i][0] / f64::from(NS)).sqrt()) as u8;
-            let gi = (255.99 * (framebuffer[j][i][1] / f64::from(NS)).sqrt()) as u8;
-            let bi = (255.99 * (framebuffer[j][i][2] / f64::from(NS)).sqrt()) as u8;
-            println!("{0} {1} {2}", ri, gi, bi);
-        }
+    let mut out: Box<dyn Write> = match options.output {
+        Some(ref path) => match File::create(path) {
+            Ok(f) => Box::new(BufWriter::new(f)),
+            Err(e) => {
+                eprintln!("{}: cannot create {}: {}", args[0], path, e);
+                std::process::exit(1);
+            }
+        },
+        None => Box::new(BufWriter::new(io::stdout())),
+    };
+    if let Err(e) = write_image(&mut out, options.format, &framebuffer, ns) {
+        eprintln!("{}: error writing image: {}", args[0], e);
+        std::process::exit(1);
     }
 }
@@ -1,78 +1,331 @@
 use hitable::HitRecord;
-use vec3::{dot, random_in_unit_sphere, reflect, refract, unit_vector, Vec3};
-use ray::Ray;
+use microfacet::{conductor_eval, conductor_pdf, conductor_sample, dielectric_eval, dielectric_pdf,
+                 dielectric_sample, Ggx};
+use onb::Onb;
+use principled::Principled;
+use spectrum::{Dispersion, D_LINE};
+use std::f64::consts::PI;
+use vec3::{dot, random_cosine_direction, random_in_unit_sphere, reflect, refract, unit_vector,
+           Vec3};
 use rand::Rng;
+use std::sync::Arc;
+use texture::Texture;
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Debug)]
 pub enum Material {
-    Lambertian { albedo: Vec3 },
-    Metal { albedo: Vec3, fuzz: f64 },
+    Lambertian { albedo: Arc<dyn Texture> },
+    Metal { albedo: Arc<dyn Texture>, fuzz: f64 },
     Dielectric { ref_idx: f64 },
+    // GGX microfacet metal with the complex index of refraction eta + ik
+    Conductor { eta: Vec3, k: Vec3, distribution: Ggx },
+    // GGX microfacet glass, like frosted or etched glass
+    RoughDielectric { ref_idx: f64, distribution: Ggx },
+    // smooth or GGX glass whose index of refraction depends on the
+    // wavelength, which splits light into colours in spectral mode
+    DispersiveDielectric {
+        dispersion: Dispersion,
+        distribution: Option<Ggx>,
+    },
+    // the Disney-style uber material, see principled.rs
+    Principled(Arc<Principled>),
+    DiffuseLight { emit: Vec3, strength: f64 },
 }
 
-pub fn scatter<R: Rng>(
-    r_in: &Ray,
-    rec: &HitRecord,
-    attenuation: &mut Vec3,
-    scattered: &mut Ray,
-    rng: &mut R,
-) -> bool {
-    match rec.material {
-        Material::Lambertian { ref albedo } => {
-            let target = rec.p + rec.normal + random_in_unit_sphere(rng);
-            *attenuation = *albedo;
-            *scattered = Ray::new(rec.p, target - rec.p);
-            true
-        }
+// light given off by the surface, independent of any scattering
+pub fn emitted(rec: &HitRecord) -> Vec3 {
+    match *rec.material {
+        Material::DiffuseLight { emit, strength } => strength * emit,
+        _ => Vec3::new(0.0, 0.0, 0.0),
+    }
+}
 
-        Material::Metal {
-            ref albedo,
-            ref fuzz,
-        } => {
-            let reflected = reflect(&unit_vector(r_in.direction), &rec.normal);
-            *attenuation = *albedo;
-            *scattered = Ray::new(rec.p, reflected + *fuzz * random_in_unit_sphere(rng));
-            dot(&scattered.direction, &rec.normal) > 0.0
-        }
+// A direction picked by Material::sample.  wi points away from the
+// surface, towards where the light comes from.
+#[derive(Clone, Copy, Debug)]
+pub struct BsdfSample {
+    pub wi: Vec3,
+    // f * |cos| / pdf, what the light arriving along wi is multiplied by
+    pub weight: Vec3,
+    // None for mirror-like (delta) scattering, which eval() & pdf() can't
+    // describe, so lights are not sampled there
+    pub pdf: Option<f64>,
+}
+
+// the normal on the same side of the surface as w
+fn facing(normal: &Vec3, w: &Vec3) -> Vec3 {
+    if dot(normal, w) < 0.0 {
+        -*normal
+    } else {
+        *normal
+    }
+}
+
+// the microfacet materials' frame, with wo above the surface
+pub fn shading_frame(rec: &HitRecord, wo: &Vec3) -> Onb {
+    Onb::build_from_w(&facing(&rec.normal, wo))
+}
 
-        Material::Dielectric { ref ref_idx } => {
-            // are we entering or exiting the material?
-            let entering = dot(&r_in.direction, &rec.normal) > 0.0;
-            let mut outward_normal = if entering {
-                -rec.normal
-            } else {
-                rec.normal
-            };
-            let mut ni_over_nt = if entering { *ref_idx } else { 1.0 / *ref_idx };
-            let mut cosine = if entering {
-                ref_idx * dot(&r_in.direction, &rec.normal) / r_in.direction.length()
-            } else {
-                -dot(&r_in.direction, &rec.normal) / r_in.direction.length()
-            };
-
-            let mut refracted = Vec3::new(0.0, 0.0, 0.0);
-            let mut reflect_prob =
-                if refract(&r_in.direction, &outward_normal, ni_over_nt, &mut refracted) {
+// glass index on the far side of the surface over the one on wo's side
+pub fn relative_eta(rec: &HitRecord, wo: &Vec3, ref_idx: f64) -> f64 {
+    if dot(wo, &rec.normal) >= 0.0 {
+        ref_idx
+    } else {
+        1.0 / ref_idx
+    }
+}
+
+// The BSDF of each material.  wo points away from the surface, back
+// along the ray that hit it; wi & wo are unit vectors.
+impl Material {
+    // None if the light is absorbed
+    pub fn sample<R: Rng>(&self, rec: &HitRecord, wo: &Vec3, rng: &mut R) -> Option<BsdfSample> {
+        match *self {
+            Material::Lambertian { ref albedo } => {
+                let normal = facing(&rec.normal, wo);
+                let wi = Onb::build_from_w(&normal).local(&random_cosine_direction(rng));
+                Some(BsdfSample {
+                    wi,
+                    weight: albedo.value(rec.u, rec.v, &rec.p),
+                    pdf: Some(dot(&wi, &normal) / PI),
+                })
+            }
+
+            Material::Metal {
+                ref albedo,
+                ref fuzz,
+            } => {
+                let reflected = reflect(&-*wo, &rec.normal);
+                let wi = reflected + *fuzz * random_in_unit_sphere(rng);
+                if dot(&wi, &rec.normal) <= 0.0 {
+                    return None;
+                }
+                Some(BsdfSample {
+                    wi: unit_vector(wi),
+                    weight: albedo.value(rec.u, rec.v, &rec.p),
+                    pdf: None,
+                })
+            }
+
+            Material::Dielectric { ref ref_idx } => {
+                let d = -*wo;
+                // are we entering or exiting the material?
+                let entering = dot(&d, &rec.normal) > 0.0;
+                let outward_normal = if entering {
+                    -rec.normal
+                } else {
+                    rec.normal
+                };
+                let ni_over_nt = if entering { *ref_idx } else { 1.0 / *ref_idx };
+                let cosine = if entering {
+                    ref_idx * dot(&d, &rec.normal)
+                } else {
+                    -dot(&d, &rec.normal)
+                };
+
+                let mut refracted = Vec3::new(0.0, 0.0, 0.0);
+                let reflect_prob = if refract(&d, &outward_normal, ni_over_nt, &mut refracted) {
                     schlick(cosine, *ref_idx)
                 } else {
                     1.0
                 };
 
-            *attenuation = Vec3::new(1.0, 1.0, 1.0);
-            *scattered = if rng.gen::<f64>() < reflect_prob {
-                let reflected = reflect(&r_in.direction, &rec.normal);
-                Ray::new(rec.p, reflected)
-            } else {
-                Ray::new(rec.p, refracted)
-            };
-            true
+                let wi = if rng.gen::<f64>() < reflect_prob {
+                    reflect(&d, &rec.normal)
+                } else {
+                    unit_vector(refracted)
+                };
+                Some(BsdfSample {
+                    wi,
+                    weight: Vec3::new(1.0, 1.0, 1.0),
+                    pdf: None,
+                })
+            }
+
+            Material::Conductor {
+                ref eta,
+                ref k,
+                ref distribution,
+            } => {
+                let frame = shading_frame(rec, wo);
+                let u = (rng.gen::<f64>(), rng.gen::<f64>());
+                let (wi, weight, pdf) =
+                    conductor_sample(distribution, eta, k, &frame.to_local(wo), u)?;
+                Some(BsdfSample {
+                    wi: frame.local(&wi),
+                    weight,
+                    pdf: Some(pdf),
+                })
+            }
+
+            Material::RoughDielectric {
+                ref_idx,
+                ref distribution,
+            } => {
+                let frame = shading_frame(rec, wo);
+                let eta = relative_eta(rec, wo, ref_idx);
+                let u = (rng.gen::<f64>(), rng.gen::<f64>());
+                let (wi, weight, pdf) = dielectric_sample(
+                    distribution,
+                    eta,
+                    &frame.to_local(wo),
+                    u,
+                    rng.gen::<f64>(),
+                )?;
+                Some(BsdfSample {
+                    wi: frame.local(&wi),
+                    weight,
+                    pdf: Some(pdf),
+                })
+            }
+
+            Material::DispersiveDielectric {
+                ref dispersion,
+                distribution,
+            } => fixed_index(dispersion, distribution, D_LINE).sample(rec, wo, rng),
+
+            Material::Principled(ref p) => p.sample(rec, wo, rng),
+
+            // lights only emit
+            Material::DiffuseLight { .. } => None,
+        }
+    }
+
+    // the BSDF's value for light arriving along wi & leaving along wo
+    pub fn eval(&self, rec: &HitRecord, wi: &Vec3, wo: &Vec3) -> Vec3 {
+        match *self {
+            Material::Lambertian { ref albedo } => {
+                if dot(wi, &rec.normal) * dot(wo, &rec.normal) <= 0.0 {
+                    return Vec3::new(0.0, 0.0, 0.0);
+                }
+                albedo.value(rec.u, rec.v, &rec.p) / PI
+            }
+            Material::Conductor {
+                ref eta,
+                ref k,
+                ref distribution,
+            } => {
+                let frame = shading_frame(rec, wo);
+                let (wo, wi) = (frame.to_local(wo), frame.to_local(wi));
+                conductor_eval(distribution, eta, k, &wo, &wi)
+            }
+            Material::RoughDielectric {
+                ref_idx,
+                ref distribution,
+            } => {
+                let frame = shading_frame(rec, wo);
+                let eta = relative_eta(rec, wo, ref_idx);
+                let (wo, wi) = (frame.to_local(wo), frame.to_local(wi));
+                let f = dielectric_eval(distribution, eta, &wo, &wi);
+                Vec3::new(f, f, f)
+            }
+            Material::DispersiveDielectric {
+                ref dispersion,
+                distribution,
+            } => fixed_index(dispersion, distribution, D_LINE).eval(rec, wi, wo),
+            Material::Principled(ref p) => p.eval(rec, wi, wo),
+            _ => Vec3::new(0.0, 0.0, 0.0),
+        }
+    }
+
+    // the solid angle pdf of sample() picking wi, given wo
+    pub fn pdf(&self, rec: &HitRecord, wi: &Vec3, wo: &Vec3) -> f64 {
+        match *self {
+            Material::Lambertian { .. } => {
+                let cosine = dot(wi, &facing(&rec.normal, wo));
+                if cosine <= 0.0 {
+                    0.0
+                } else {
+                    cosine / PI
+                }
+            }
+            Material::Conductor {
+                ref distribution, ..
+            } => {
+                let frame = shading_frame(rec, wo);
+                conductor_pdf(distribution, &frame.to_local(wo), &frame.to_local(wi))
+            }
+            Material::RoughDielectric {
+                ref_idx,
+                ref distribution,
+            } => {
+                let frame = shading_frame(rec, wo);
+                let eta = relative_eta(rec, wo, ref_idx);
+                dielectric_pdf(distribution, eta, &frame.to_local(wo), &frame.to_local(wi))
+            }
+            Material::DispersiveDielectric {
+                ref dispersion,
+                distribution,
+            } => fixed_index(dispersion, distribution, D_LINE).pdf(rec, wi, wo),
+            Material::Principled(ref p) => p.pdf(rec, wi, wo),
+            _ => 0.0,
+        }
+    }
+
+    // In spectral mode, the material as seen by light of wavelength
+    // lambda (nm), for materials where that makes a difference.  Without
+    // it they behave as at the d line.
+    pub fn at_wavelength(&self, lambda: f64) -> Option<Material> {
+        match *self {
+            Material::DispersiveDielectric {
+                ref dispersion,
+                distribution,
+            } => Some(fixed_index(dispersion, distribution, lambda)),
+            _ => None,
         }
     }
 }
 
+// a dispersive dielectric at one wavelength
+fn fixed_index(dispersion: &Dispersion, distribution: Option<Ggx>, lambda: f64) -> Material {
+    let ref_idx = dispersion.ior(lambda);
+    match distribution {
+        None => Material::Dielectric { ref_idx },
+        Some(distribution) => Material::RoughDielectric {
+            ref_idx,
+            distribution,
+        },
+    }
+}
+
 // for dielectric calc
 fn schlick(cosine: f64, ref_idx: f64) -> f64 {
     let mut r0 = (1.0 - ref_idx) / (1.0 + ref_idx);
     r0 = r0 * r0;
     r0 + (1.0 - r0) * (1.0 - cosine).powf(5.0)
 }
+
+// ======================================================================
+// Unit testing
+// ======================================================================
+#[test]
+fn test_lambertian_bsdf() {
+    use rand::{SeedableRng, StdRng};
+    use texture::solid;
+    let seed: &[_] = &[11];
+    let mut rng: StdRng = SeedableRng::from_seed(seed);
+    let material = Material::Lambertian {
+        albedo: solid(Vec3::new(0.5, 0.5, 0.5)),
+    };
+    let mut rec = HitRecord::new();
+    rec.normal = Vec3::new(0.0, 1.0, 0.0);
+    rec.material = &material;
+    let wo = unit_vector(Vec3::new(1.0, 1.0, 0.0));
+    let mut cos_sum = 0.0;
+    let n = 10000;
+    for _ in 0..n {
+        let s = material.sample(&rec, &wo, &mut rng).unwrap();
+        let pdf = s.pdf.unwrap();
+        assert!((s.wi.length() - 1.0).abs() < 1e-9);
+        assert!((pdf - material.pdf(&rec, &s.wi, &wo)).abs() < 1e-9);
+        // weight is f * cos / pdf
+        let f = material.eval(&rec, &s.wi, &wo);
+        assert!((f * s.wi.y() / pdf - s.weight).length() < 1e-9);
+        cos_sum += s.wi.y();
+    }
+    // cosine-weighted: the mean cosine is 2/3
+    assert!((cos_sum / n as f64 - 2.0 / 3.0).abs() < 0.01);
+    // nothing goes through the surface
+    let below = Vec3::new(0.0, -1.0, 0.0);
+    assert_eq!(material.eval(&rec, &below, &wo), Vec3::new(0.0, 0.0, 0.0));
+    assert_eq!(material.pdf(&rec, &below, &wo), 0.0);
+}
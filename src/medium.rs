@@ -0,0 +1,247 @@
+use std::f64;
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+use aabb::Aabb;
+use hitable::{HitRecord, Hitable};
+use instance::Instance;
+use rand::Rng;
+use ray::Ray;
+use texture::Texture;
+use vec3::{cross, random_in_unit_sphere, unit_vector, Mat4, Vec3};
+
+// How a medium redirects the light it scatters.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PhaseFunction {
+    Isotropic,
+    // g in (-1,1): > 0 scatters forward, < 0 back, 0 is isotropic
+    HenyeyGreenstein(f64),
+}
+
+impl PhaseFunction {
+    // a new direction for light travelling along the unit vector d
+    pub fn sample<R: Rng>(&self, d: &Vec3, rng: &mut R) -> Vec3 {
+        match *self {
+            PhaseFunction::Isotropic => unit_vector(random_in_unit_sphere(rng)),
+            PhaseFunction::HenyeyGreenstein(g) => {
+                let xi = rng.gen::<f64>();
+                let cos_theta = if g.abs() < 1e-3 {
+                    1.0 - 2.0 * xi
+                } else {
+                    let s = (1.0 - g * g) / (1.0 + g - 2.0 * g * xi);
+                    (1.0 + g * g - s * s) / (2.0 * g)
+                };
+                let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+                let phi = 2.0 * PI * rng.gen::<f64>();
+                // any frame around d will do
+                let a = if d.x().abs() > 0.9 {
+                    Vec3::new(0.0, 1.0, 0.0)
+                } else {
+                    Vec3::new(1.0, 0.0, 0.0)
+                };
+                let u = unit_vector(cross(&a, d));
+                let v = cross(d, &u);
+                sin_theta * phi.cos() * u + sin_theta * phi.sin() * v + cos_theta * *d
+            }
+        }
+    }
+}
+
+// A volume of constant density, such as smoke or mist, that scatters
+// rays at exponentially distributed distances.  The boundary can be any
+// closed shape, convex or not: a ray is inside it between every time
+// it crosses in & the next time it crosses out.
+//
+// Media are not Hitables.  color() asks each one where a ray scatters,
+// using the same random numbers as the rest of the path.
+#[derive(Clone)]
+pub struct ConstantMedium {
+    boundary: Arc<dyn Hitable>,
+    bbox: Option<Aabb>, // rays that miss it miss the medium
+    density: f64,
+    albedo: Arc<dyn Texture>,
+    phase: PhaseFunction,
+}
+
+impl ConstantMedium {
+    pub fn new(
+        boundary: Box<dyn Hitable>,
+        density: f64,
+        albedo: Arc<dyn Texture>,
+        phase: PhaseFunction,
+    ) -> ConstantMedium {
+        let mut bbox = Aabb::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 0.0));
+        let bbox = if boundary.bounding_box(&mut bbox) {
+            Some(bbox)
+        } else {
+            None
+        };
+        ConstantMedium {
+            boundary: Arc::from(boundary),
+            bbox,
+            density,
+            albedo,
+            phase,
+        }
+    }
+
+    // the same medium in its boundary moved by transform, for instances
+    pub fn transformed(&self, transform: Mat4) -> ConstantMedium {
+        ConstantMedium::new(
+            Box::new(Instance::new(self.boundary.clone(), transform)),
+            self.density,
+            self.albedo.clone(),
+            self.phase,
+        )
+    }
+
+    // The ray parameter where r scatters inside the medium, if that is
+    // before t_max.
+    pub fn sample_distance<R: Rng>(&self, r: &Ray, t_max: f64, rng: &mut R) -> Option<f64> {
+        if self.bbox.is_some_and(|bbox| !bbox.hit(r, 0.0, t_max)) {
+            return None;
+        }
+        let length = r.direction.length();
+        // how far the ray gets through the medium before scattering, only
+        // picked once it is known to go through some
+        let mut hit_distance = None;
+        // crossings alternate in & out, from the very start of the line
+        let mut rec = HitRecord::new();
+        let mut t = -f64::MAX;
+        let mut inside = false;
+        while t < t_max && self.boundary.hit(r, t + 0.0001, f64::MAX, &mut rec) {
+            if inside {
+                let (t_enter, t_exit) = (t.max(0.0), rec.t.min(t_max));
+                if t_enter < t_exit {
+                    let distance_inside = (t_exit - t_enter) * length;
+                    let distance = hit_distance
+                        .get_or_insert_with(|| -(1.0 - rng.gen::<f64>()).ln() / self.density);
+                    if *distance < distance_inside {
+                        return Some(t_enter + *distance / length);
+                    }
+                    *distance -= distance_inside;
+                }
+            }
+            inside = !inside;
+            t = rec.t;
+        }
+        None
+    }
+
+    // the scattered ray & its attenuation for r scattering at t
+    pub fn scatter<R: Rng>(&self, r: &Ray, t: f64, rng: &mut R) -> (Vec3, Ray) {
+        let p = r.point_at_parameter(t);
+        let direction = self.phase.sample(&unit_vector(r.direction), rng);
+        (
+            self.albedo.value(0.0, 0.0, &p),
+            Ray::with_time(p, direction, r.time),
+        )
+    }
+}
+
+// ======================================================================
+// Unit testing
+// ======================================================================
+#[test]
+fn test_medium_sample_distance() {
+    use material::Material;
+    use rand::{SeedableRng, StdRng};
+    use sphere::Sphere;
+    use texture::solid;
+    let seed: &[_] = &[3];
+    let mut rng: StdRng = SeedableRng::from_seed(seed);
+    let white = solid(Vec3::new(1.0, 1.0, 1.0));
+    let glass = Material::Dielectric { ref_idx: 1.5 };
+    let ball = ConstantMedium::new(
+        Box::new(Sphere::new(Vec3::new(0.0, 0.0, 0.0), 1.0, glass.clone())),
+        1000.0,
+        white.clone(),
+        PhaseFunction::Isotropic,
+    );
+    // very dense, so rays through it scatter just inside
+    let r = Ray::new(Vec3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+    let t = ball.sample_distance(&r, f64::MAX, &mut rng).unwrap();
+    assert!((4.0..4.1).contains(&t));
+    // not when a surface is in front of it, or when missing it
+    assert_eq!(ball.sample_distance(&r, 3.0, &mut rng), None);
+    let miss = Ray::new(Vec3::new(2.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+    assert_eq!(ball.sample_distance(&miss, f64::MAX, &mut rng), None);
+
+    // thin fog lets about exp(-density * distance) of the rays through
+    let fog = ConstantMedium::new(
+        Box::new(Sphere::new(Vec3::new(0.0, 0.0, 0.0), 100.0, glass.clone())),
+        0.1,
+        white.clone(),
+        PhaseFunction::Isotropic,
+    );
+    let through = (0..10000)
+        .filter(|_| fog.sample_distance(&r, 10.0, &mut rng).is_none())
+        .count();
+    assert!((through as f64 / 10000.0 - (-1.0f64).exp()).abs() < 0.02);
+}
+#[test]
+fn test_medium_non_convex() {
+    use hitable_list::HitableList;
+    use material::Material;
+    use rand::{SeedableRng, StdRng};
+    use sphere::Sphere;
+    use texture::solid;
+    let seed: &[_] = &[4];
+    let mut rng: StdRng = SeedableRng::from_seed(seed);
+    let glass = Material::Dielectric { ref_idx: 1.5 };
+    // two balls on the z axis with a gap of 2 between them
+    let mut balls = HitableList::new();
+    balls.push(Sphere::new(Vec3::new(0.0, 0.0, 0.0), 1.0, glass.clone()));
+    balls.push(Sphere::new(Vec3::new(0.0, 0.0, -4.0), 1.0, glass));
+    let medium = ConstantMedium::new(
+        Box::new(balls),
+        0.25,
+        solid(Vec3::new(1.0, 1.0, 1.0)),
+        PhaseFunction::Isotropic,
+    );
+    // only the 4 inside the balls counts, not the gap, & nothing
+    // scatters in the gap
+    let through = |r: &Ray, rng: &mut StdRng| {
+        let mut n = 0;
+        for _ in 0..10000 {
+            match medium.sample_distance(r, f64::MAX, rng) {
+                Some(t) => {
+                    let z = r.point_at_parameter(t).z();
+                    assert!(z > -5.0 && z < 1.0 && !(z > -3.0 && z < -1.0));
+                }
+                None => n += 1,
+            }
+        }
+        n as f64 / 10000.0
+    };
+    let r = Ray::new(Vec3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+    assert!((through(&r, &mut rng) - (-1.0f64).exp()).abs() < 0.02);
+    // starting inside the first ball, & in the gap
+    let r = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0));
+    assert!((through(&r, &mut rng) - (-0.75f64).exp()).abs() < 0.02);
+    let r = Ray::new(Vec3::new(0.0, 0.0, -2.0), Vec3::new(0.0, 0.0, -1.0));
+    assert!((through(&r, &mut rng) - (-0.5f64).exp()).abs() < 0.02);
+    // & beside both of them, outside the bounding box
+    let r = Ray::new(Vec3::new(2.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+    assert_eq!(through(&r, &mut rng), 1.0);
+}
+#[test]
+fn test_henyey_greenstein_mean_cosine() {
+    use rand::{SeedableRng, StdRng};
+    use vec3::dot;
+    let seed: &[_] = &[5];
+    let mut rng: StdRng = SeedableRng::from_seed(seed);
+    let d = Vec3::new(0.0, 0.0, 1.0);
+    // the mean cosine of the scattering angle is g
+    for &g in &[-0.5, 0.0, 0.8] {
+        let phase = PhaseFunction::HenyeyGreenstein(g);
+        let n = 20000;
+        let mut sum = 0.0;
+        for _ in 0..n {
+            let w = phase.sample(&d, &mut rng);
+            assert!((w.length() - 1.0).abs() < 1e-9);
+            sum += dot(&w, &d);
+        }
+        assert!((sum / n as f64 - g).abs() < 0.02);
+    }
+}
@@ -0,0 +1,316 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+
+use aabb::Aabb;
+use bvh::BvhNode;
+use hitable::{HitRecord, Hitable};
+use material::Material;
+use ray::Ray;
+#[cfg(test)]
+use texture::solid;
+use triangle::Triangle;
+use vec3::Vec3;
+
+#[derive(Debug)]
+pub enum ObjError {
+    Io(io::Error),
+    Parse { line: usize, message: String },
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ObjError::Io(ref e) => write!(f, "{}", e),
+            ObjError::Parse {
+                ref line,
+                ref message,
+            } => write!(f, "line {}: {}", line, message),
+        }
+    }
+}
+
+impl Error for ObjError {}
+
+impl From<io::Error> for ObjError {
+    fn from(e: io::Error) -> ObjError {
+        ObjError::Io(e)
+    }
+}
+
+// A triangle mesh loaded from a Wavefront OBJ file.  The triangles are
+// kept in their own BVH so a mesh is a single object in the world.
+pub struct TriangleMesh {
+    bvh: BvhNode,
+    num_triangles: usize,
+}
+
+impl TriangleMesh {
+    // `materials` maps `usemtl` names to materials.  Faces before any
+    // `usemtl` get `default_material`.
+    pub fn load_obj<P: AsRef<Path>>(
+        path: P,
+        materials: &HashMap<String, Material>,
+        default_material: Material,
+    ) -> Result<TriangleMesh, ObjError> {
+        let file = File::open(path)?;
+        TriangleMesh::parse_obj(BufReader::new(file), materials, default_material)
+    }
+
+    pub fn parse_obj<B: BufRead>(
+        reader: B,
+        materials: &HashMap<String, Material>,
+        default_material: Material,
+    ) -> Result<TriangleMesh, ObjError> {
+        let mut positions: Vec<Vec3> = Vec::new();
+        let mut normals: Vec<Vec3> = Vec::new();
+        let mut uvs: Vec<(f64, f64)> = Vec::new();
+        let mut triangles: Vec<Box<dyn Hitable>> = Vec::new();
+        let mut material = default_material;
+
+        for (line_index, line) in reader.lines().enumerate() {
+            let line_num = line_index + 1;
+            let line = line?;
+            let err = |message: String| ObjError::Parse {
+                line: line_num,
+                message,
+            };
+            let mut tokens = line.split_whitespace();
+            let keyword = match tokens.next() {
+                Some(k) => k,
+                None => continue,
+            };
+            let args: Vec<&str> = tokens.collect();
+            match keyword {
+                "v" => positions.push(parse_vec3(&args).map_err(err)?),
+                "vn" => normals.push(parse_vec3(&args).map_err(err)?),
+                "vt" => {
+                    if args.is_empty() {
+                        return Err(err("vt needs at least one coordinate".to_string()));
+                    }
+                    let u = parse_f64(args[0]).map_err(&err)?;
+                    let v = match args.get(1) {
+                        Some(s) => parse_f64(s).map_err(&err)?,
+                        None => 0.0,
+                    };
+                    uvs.push((u, v));
+                }
+                "f" => {
+                    if args.len() < 3 {
+                        return Err(err(format!(
+                            "face needs at least 3 vertices, found {}",
+                            args.len()
+                        )));
+                    }
+                    let mut verts = Vec::with_capacity(args.len());
+                    for arg in &args {
+                        verts.push(
+                            parse_face_vertex(arg, positions.len(), uvs.len(), normals.len())
+                                .map_err(&err)?,
+                        );
+                    }
+                    // only use normals & uvs if every vertex of the face has them
+                    let has_normals = verts.iter().all(|fv| fv.normal.is_some());
+                    let has_uvs = verts.iter().all(|fv| fv.uv.is_some());
+                    // fan triangulation for faces with more than three vertices
+                    for i in 1..verts.len() - 1 {
+                        let corners = [&verts[0], &verts[i], &verts[i + 1]];
+                        let p = [
+                            positions[corners[0].position],
+                            positions[corners[1].position],
+                            positions[corners[2].position],
+                        ];
+                        let n = if has_normals {
+                            Some([
+                                normals[corners[0].normal.unwrap()],
+                                normals[corners[1].normal.unwrap()],
+                                normals[corners[2].normal.unwrap()],
+                            ])
+                        } else {
+                            None
+                        };
+                        let uv = if has_uvs {
+                            Some([
+                                uvs[corners[0].uv.unwrap()],
+                                uvs[corners[1].uv.unwrap()],
+                                uvs[corners[2].uv.unwrap()],
+                            ])
+                        } else {
+                            None
+                        };
+                        triangles.push(Box::new(Triangle::new(p, n, uv, material.clone())));
+                    }
+                }
+                "usemtl" => {
+                    let name = args.join(" ");
+                    material = match materials.get(&name) {
+                        Some(m) => m.clone(),
+                        None => return Err(err(format!("unknown material '{}'", name))),
+                    };
+                }
+                // comments, groups, smoothing groups, material libraries,
+                // lines & curves do not add triangles.
+                _ => {}
+            }
+        }
+        if triangles.is_empty() {
+            return Err(ObjError::Parse {
+                line: 0,
+                message: "no faces found".to_string(),
+            });
+        }
+        let num_triangles = triangles.len();
+        Ok(TriangleMesh {
+            bvh: BvhNode::new(triangles),
+            num_triangles,
+        })
+    }
+
+    pub fn num_triangles(&self) -> usize {
+        self.num_triangles
+    }
+}
+
+impl Hitable for TriangleMesh {
+    fn hit<'a>(&'a self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord<'a>) -> bool {
+        self.bvh.hit(r, t_min, t_max, rec)
+    }
+    fn bounding_box(&self, output_box: &mut Aabb) -> bool {
+        self.bvh.bounding_box(output_box)
+    }
+}
+
+// zero-based indices of one `f` vertex
+struct FaceVertex {
+    position: usize,
+    uv: Option<usize>,
+    normal: Option<usize>,
+}
+
+fn parse_f64(s: &str) -> Result<f64, String> {
+    s.parse::<f64>()
+        .map_err(|_| format!("'{}' is not a number", s))
+}
+
+fn parse_vec3(args: &[&str]) -> Result<Vec3, String> {
+    if args.len() < 3 {
+        return Err(format!("expected 3 coordinates, found {}", args.len()));
+    }
+    Ok(Vec3::new(
+        parse_f64(args[0])?,
+        parse_f64(args[1])?,
+        parse_f64(args[2])?,
+    ))
+}
+
+// OBJ indices are 1-based, or negative to count back from the most
+// recently defined element.
+fn resolve_index(s: &str, count: usize, what: &str) -> Result<usize, String> {
+    let i = s
+        .parse::<i64>()
+        .map_err(|_| format!("'{}' is not a valid {} index", s, what))?;
+    let resolved = if i > 0 {
+        i - 1
+    } else if i < 0 {
+        count as i64 + i
+    } else {
+        return Err(format!("{} index cannot be 0", what));
+    };
+    if resolved < 0 || resolved >= count as i64 {
+        return Err(format!(
+            "{} index {} out of range ({} defined)",
+            what, i, count
+        ));
+    }
+    Ok(resolved as usize)
+}
+
+// v, v/vt, v//vn or v/vt/vn
+fn parse_face_vertex(
+    s: &str,
+    num_positions: usize,
+    num_uvs: usize,
+    num_normals: usize,
+) -> Result<FaceVertex, String> {
+    let mut parts = s.split('/');
+    let position = resolve_index(parts.next().unwrap_or(""), num_positions, "vertex")?;
+    let uv = match parts.next() {
+        Some(t) if !t.is_empty() => Some(resolve_index(t, num_uvs, "texture")?),
+        _ => None,
+    };
+    let normal = match parts.next() {
+        Some(n) if !n.is_empty() => Some(resolve_index(n, num_normals, "normal")?),
+        _ => None,
+    };
+    Ok(FaceVertex {
+        position,
+        uv,
+        normal,
+    })
+}
+
+// ======================================================================
+// Unit testing
+// ======================================================================
+#[test]
+fn test_parse_obj_quads_and_negative_indices() {
+    let obj = "# unit square as a quad, then a triangle using negative indices
+v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 0
+vn 0 0 1
+f 1//1 2//1 3//1 4//1
+v 0 0 -1
+f -1 -2 -3
+";
+    let gray = Material::Lambertian {
+        albedo: solid(Vec3::new(0.5, 0.5, 0.5)),
+    };
+    let mesh = TriangleMesh::parse_obj(obj.as_bytes(), &HashMap::new(), gray).unwrap();
+    assert_eq!(mesh.num_triangles(), 3);
+    let mut rec = HitRecord::new();
+    let r = Ray::new(Vec3::new(0.75, 0.25, 1.0), Vec3::new(0.0, 0.0, -1.0));
+    assert!(mesh.hit(&r, 0.001, f64::MAX, &mut rec));
+    assert_eq!(rec.t, 1.0);
+    assert_eq!(rec.normal, Vec3::new(0.0, 0.0, 1.0));
+}
+#[test]
+fn test_parse_obj_usemtl() {
+    let obj = "v 0 0 0\nv 1 0 0\nv 0 1 0\nusemtl glass\nf 1 2 3\n";
+    let gray = Material::Lambertian {
+        albedo: solid(Vec3::new(0.5, 0.5, 0.5)),
+    };
+    let mut materials = HashMap::new();
+    materials.insert("glass".to_string(), Material::Dielectric { ref_idx: 1.5 });
+    let mesh = TriangleMesh::parse_obj(obj.as_bytes(), &materials, gray).unwrap();
+    let mut rec = HitRecord::new();
+    let r = Ray::new(Vec3::new(0.25, 0.25, 1.0), Vec3::new(0.0, 0.0, -1.0));
+    assert!(mesh.hit(&r, 0.001, f64::MAX, &mut rec));
+    match *rec.material {
+        Material::Dielectric { ref_idx } => assert_eq!(ref_idx, 1.5),
+        _ => panic!("expected the usemtl material"),
+    }
+}
+#[test]
+fn test_parse_obj_errors() {
+    let gray = Material::Lambertian {
+        albedo: solid(Vec3::new(0.5, 0.5, 0.5)),
+    };
+    let bad_index = "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 4\n";
+    match TriangleMesh::parse_obj(bad_index.as_bytes(), &HashMap::new(), gray.clone()) {
+        Err(ObjError::Parse { line, .. }) => assert_eq!(line, 4),
+        _ => panic!("expected a parse error"),
+    }
+    let bad_material = "v 0 0 0\nusemtl chrome\n";
+    match TriangleMesh::parse_obj(bad_material.as_bytes(), &HashMap::new(), gray) {
+        Err(ObjError::Parse { line, message }) => {
+            assert_eq!(line, 2);
+            assert!(message.contains("chrome"));
+        }
+        _ => panic!("expected a parse error"),
+    }
+}
@@ -0,0 +1,402 @@
+use std::f64::consts::PI;
+use std::str::FromStr;
+
+use vec3::{cross, dot, reflect, unit_vector, Vec3};
+
+// Microfacet BSDFs with the GGX (Trowbridge-Reitz) distribution.  Every
+// vector here is in the shading frame: the normal is +z, the roughness
+// alpha_x runs along x and alpha_y along y, and wo is above the surface.
+
+// below this the distribution is too spiky to sample or evaluate
+const MIN_ALPHA: f64 = 1e-3;
+
+// The distribution of microfacet normals.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Ggx {
+    alpha_x: f64,
+    alpha_y: f64,
+}
+
+impl Ggx {
+    // roughness is the perceptual 0..1 value; alpha is its square
+    pub fn new(roughness_x: f64, roughness_y: f64) -> Ggx {
+        Ggx {
+            alpha_x: (roughness_x * roughness_x).max(MIN_ALPHA),
+            alpha_y: (roughness_y * roughness_y).max(MIN_ALPHA),
+        }
+    }
+
+    pub fn d(&self, h: &Vec3) -> f64 {
+        if h.z() <= 0.0 {
+            return 0.0;
+        }
+        let x = h.x() / self.alpha_x;
+        let y = h.y() / self.alpha_y;
+        let t = x * x + y * y + h.z() * h.z();
+        1.0 / (PI * self.alpha_x * self.alpha_y * t * t)
+    }
+
+    fn lambda(&self, w: &Vec3) -> f64 {
+        let z2 = w.z() * w.z();
+        if z2 == 0.0 {
+            return f64::INFINITY;
+        }
+        let a2 = self.alpha_x * self.alpha_x * w.x() * w.x()
+            + self.alpha_y * self.alpha_y * w.y() * w.y();
+        0.5 * (-1.0 + (1.0 + a2 / z2).sqrt())
+    }
+
+    // Smith masking of w
+    pub fn g1(&self, w: &Vec3) -> f64 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    // height-correlated masking & shadowing
+    pub fn g(&self, wo: &Vec3, wi: &Vec3) -> f64 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    // A normal visible from wo, picked in proportion to how much of wo's
+    // view it covers (Heitz 2018, "Sampling the GGX Distribution of
+    // Visible Normals").
+    pub fn sample_visible(&self, wo: &Vec3, sample: (f64, f64)) -> Vec3 {
+        // stretch to the hemisphere configuration
+        let vh = unit_vector(Vec3::new(
+            self.alpha_x * wo.x(),
+            self.alpha_y * wo.y(),
+            wo.z(),
+        ));
+        let len2 = vh.x() * vh.x() + vh.y() * vh.y();
+        let t1 = if len2 > 0.0 {
+            Vec3::new(-vh.y(), vh.x(), 0.0) / len2.sqrt()
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let t2 = cross(&vh, &t1);
+        let r = sample.0.sqrt();
+        let phi = 2.0 * PI * sample.1;
+        let p1 = r * phi.cos();
+        let s = 0.5 * (1.0 + vh.z());
+        let p2 = (1.0 - s) * (1.0 - p1 * p1).max(0.0).sqrt() + s * r * phi.sin();
+        let nh = p1 * t1 + p2 * t2 + (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt() * vh;
+        // and back
+        unit_vector(Vec3::new(
+            self.alpha_x * nh.x(),
+            self.alpha_y * nh.y(),
+            nh.z().max(1e-6),
+        ))
+    }
+
+    // the pdf of sample_visible() returning h
+    pub fn pdf_visible(&self, wo: &Vec3, h: &Vec3) -> f64 {
+        self.g1(wo) * dot(wo, h).max(0.0) * self.d(h) / wo.z()
+    }
+}
+
+// Fresnel reflectance of a dielectric, where eta is the index on the
+// far side over the one on cos_i's side.
+pub fn fresnel_dielectric(cos_i: f64, eta: f64) -> f64 {
+    let (cos_i, eta) = if cos_i < 0.0 {
+        (-cos_i, 1.0 / eta)
+    } else {
+        (cos_i, eta)
+    };
+    let cos_i = cos_i.min(1.0);
+    let sin2_t = (1.0 - cos_i * cos_i) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return 1.0; // total internal reflection
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    let r_parallel = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let r_perpendicular = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    0.5 * (r_parallel * r_parallel + r_perpendicular * r_perpendicular)
+}
+
+// Fresnel reflectance of a metal with complex index eta + ik, for each
+// of red, green & blue.
+pub fn fresnel_conductor(cos_i: f64, eta: &Vec3, k: &Vec3) -> Vec3 {
+    let cos_i = cos_i.clamp(0.0, 1.0);
+    let cos2 = cos_i * cos_i;
+    let sin2 = 1.0 - cos2;
+    let mut f = Vec3::new(0.0, 0.0, 0.0);
+    for c in 0..3 {
+        let (eta2, k2) = (eta[c] * eta[c], k[c] * k[c]);
+        let t0 = eta2 - k2 - sin2;
+        let a2_plus_b2 = (t0 * t0 + 4.0 * eta2 * k2).sqrt();
+        let t1 = a2_plus_b2 + cos2;
+        let a = (0.5 * (a2_plus_b2 + t0)).max(0.0).sqrt();
+        let t2 = 2.0 * cos_i * a;
+        let rs = (t1 - t2) / (t1 + t2);
+        let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+        let t4 = t2 * sin2;
+        let rp = rs * (t3 - t4) / (t3 + t4);
+        f[c] = 0.5 * (rp + rs);
+    }
+    f
+}
+
+// Measured metals, as (eta, k) at roughly 650, 550 & 450nm.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ConductorPreset {
+    Gold,
+    Copper,
+    Aluminium,
+    Silver,
+}
+
+impl ConductorPreset {
+    pub fn ior(&self) -> (Vec3, Vec3) {
+        match *self {
+            ConductorPreset::Gold => (
+                Vec3::new(0.143, 0.374, 1.442),
+                Vec3::new(3.983, 2.386, 1.603),
+            ),
+            ConductorPreset::Copper => (
+                Vec3::new(0.200, 0.924, 1.102),
+                Vec3::new(3.912, 2.452, 2.142),
+            ),
+            ConductorPreset::Aluminium => (
+                Vec3::new(1.657, 0.880, 0.521),
+                Vec3::new(9.224, 6.270, 4.837),
+            ),
+            ConductorPreset::Silver => (
+                Vec3::new(0.155, 0.117, 0.138),
+                Vec3::new(4.828, 3.122, 2.147),
+            ),
+        }
+    }
+}
+
+impl FromStr for ConductorPreset {
+    type Err = String;
+    fn from_str(s: &str) -> Result<ConductorPreset, String> {
+        match s {
+            "gold" => Ok(ConductorPreset::Gold),
+            "copper" => Ok(ConductorPreset::Copper),
+            "aluminium" | "aluminum" => Ok(ConductorPreset::Aluminium),
+            "silver" => Ok(ConductorPreset::Silver),
+            _ => Err(format!(
+                "unknown metal '{}' (expected gold, copper, aluminium or silver)",
+                s
+            )),
+        }
+    }
+}
+
+// What sampling a microfacet BSDF gives: wi, f * cos / pdf & the pdf.
+pub type Sample = (Vec3, Vec3, f64);
+
+// ----------------------------------------------------------------------
+// Conductor: reflection only
+// ----------------------------------------------------------------------
+pub fn conductor_eval(ggx: &Ggx, eta: &Vec3, k: &Vec3, wo: &Vec3, wi: &Vec3) -> Vec3 {
+    if wo.z() <= 0.0 || wi.z() <= 0.0 {
+        return Vec3::new(0.0, 0.0, 0.0);
+    }
+    let h = unit_vector(*wo + *wi);
+    let f = fresnel_conductor(dot(wo, &h), eta, k);
+    f * (ggx.d(&h) * ggx.g(wo, wi) / (4.0 * wo.z() * wi.z()))
+}
+
+pub fn conductor_pdf(ggx: &Ggx, wo: &Vec3, wi: &Vec3) -> f64 {
+    if wo.z() <= 0.0 || wi.z() <= 0.0 {
+        return 0.0;
+    }
+    let h = unit_vector(*wo + *wi);
+    ggx.pdf_visible(wo, &h) / (4.0 * dot(wo, &h))
+}
+
+pub fn conductor_sample(
+    ggx: &Ggx,
+    eta: &Vec3,
+    k: &Vec3,
+    wo: &Vec3,
+    sample: (f64, f64),
+) -> Option<Sample> {
+    let h = ggx.sample_visible(wo, sample);
+    let wi = reflect(&-*wo, &h);
+    if wi.z() <= 0.0 {
+        return None;
+    }
+    let f = fresnel_conductor(dot(wo, &h), eta, k);
+    let pdf = ggx.pdf_visible(wo, &h) / (4.0 * dot(wo, &h));
+    Some((wi, f * (ggx.g(wo, &wi) / ggx.g1(wo)), pdf))
+}
+
+// ----------------------------------------------------------------------
+// Rough dielectric: reflection & transmission (Walter et al. 2007).  eta
+// is the index below the surface over the one above, where wo is.
+//
+// Like Dielectric, transmission leaves out the 1/eta^2 scaling of
+// radiance, which cancels for paths that go in and come back out.
+// ----------------------------------------------------------------------
+
+// the microfacet normal that takes wo to wi, or None if there isn't one
+fn dielectric_half_vector(eta: f64, wo: &Vec3, wi: &Vec3) -> Option<Vec3> {
+    let reflected = wi.z() > 0.0;
+    let h = if reflected {
+        *wo + *wi
+    } else {
+        *wo + eta * *wi
+    };
+    if h.squared_length() == 0.0 {
+        return None;
+    }
+    let h = unit_vector(h);
+    let h = if h.z() < 0.0 { -h } else { h };
+    // wo & wi must be on the proper sides of the microfacet
+    if dot(wo, &h) <= 0.0 || (dot(wi, &h) > 0.0) != reflected {
+        return None;
+    }
+    Some(h)
+}
+
+pub fn dielectric_eval(ggx: &Ggx, eta: f64, wo: &Vec3, wi: &Vec3) -> f64 {
+    if wo.z() <= 0.0 || wi.z() == 0.0 {
+        return 0.0;
+    }
+    let h = match dielectric_half_vector(eta, wo, wi) {
+        Some(h) => h,
+        None => return 0.0,
+    };
+    let f = fresnel_dielectric(dot(wo, &h), eta);
+    let dg = ggx.d(&h) * ggx.g(wo, wi);
+    if wi.z() > 0.0 {
+        f * dg / (4.0 * wo.z() * wi.z())
+    } else {
+        let denom = dot(wi, &h) + dot(wo, &h) / eta;
+        (1.0 - f) * dg * (dot(wi, &h) * dot(wo, &h) / (denom * denom * wi.z() * wo.z())).abs()
+    }
+}
+
+pub fn dielectric_pdf(ggx: &Ggx, eta: f64, wo: &Vec3, wi: &Vec3) -> f64 {
+    if wo.z() <= 0.0 || wi.z() == 0.0 {
+        return 0.0;
+    }
+    let h = match dielectric_half_vector(eta, wo, wi) {
+        Some(h) => h,
+        None => return 0.0,
+    };
+    let f = fresnel_dielectric(dot(wo, &h), eta);
+    if wi.z() > 0.0 {
+        f * ggx.pdf_visible(wo, &h) / (4.0 * dot(wo, &h))
+    } else {
+        let denom = dot(wi, &h) + dot(wo, &h) / eta;
+        (1.0 - f) * ggx.pdf_visible(wo, &h) * dot(wi, &h).abs() / (denom * denom)
+    }
+}
+
+// choose is a third uniform number, to pick reflection or transmission
+pub fn dielectric_sample(
+    ggx: &Ggx,
+    eta: f64,
+    wo: &Vec3,
+    sample: (f64, f64),
+    choose: f64,
+) -> Option<Sample> {
+    let h = ggx.sample_visible(wo, sample);
+    let cos_o = dot(wo, &h);
+    let f = fresnel_dielectric(cos_o, eta);
+    let weight = ggx.g1(wo);
+    if choose < f {
+        let wi = reflect(&-*wo, &h);
+        if wi.z() <= 0.0 {
+            return None;
+        }
+        let pdf = f * ggx.pdf_visible(wo, &h) / (4.0 * cos_o);
+        let g = ggx.g(wo, &wi) / weight;
+        Some((wi, Vec3::new(g, g, g), pdf))
+    } else {
+        // Snell's law about h; f < 1 so this can't be total internal reflection
+        let sin2_t = (1.0 - cos_o * cos_o) / (eta * eta);
+        let cos_t = (1.0 - sin2_t).max(0.0).sqrt();
+        let wi = unit_vector(-*wo / eta + (cos_o / eta - cos_t) * h);
+        if wi.z() >= 0.0 {
+            return None;
+        }
+        let denom = dot(&wi, &h) + cos_o / eta;
+        let pdf = (1.0 - f) * ggx.pdf_visible(wo, &h) * dot(&wi, &h).abs() / (denom * denom);
+        let g = ggx.g(wo, &wi) / weight;
+        Some((wi, Vec3::new(g, g, g), pdf))
+    }
+}
+
+// ======================================================================
+// Unit testing
+// ======================================================================
+#[cfg(test)]
+fn integrate_over_sphere<F: Fn(&Vec3) -> f64>(f: F) -> f64 {
+    // midpoint rule in (cos theta, phi)
+    let n = 400;
+    let mut sum = 0.0;
+    for i in 0..n {
+        let z = -1.0 + 2.0 * (i as f64 + 0.5) / n as f64;
+        let r = (1.0 - z * z).sqrt();
+        for j in 0..n {
+            let phi = 2.0 * PI * (j as f64 + 0.5) / n as f64;
+            sum += f(&Vec3::new(r * phi.cos(), r * phi.sin(), z));
+        }
+    }
+    sum * 4.0 * PI / (n * n) as f64
+}
+#[test]
+fn test_ggx_normalized() {
+    // the projected area of the microfacets is that of the surface
+    for ggx in &[Ggx::new(0.5, 0.5), Ggx::new(0.3, 0.8)] {
+        let area = integrate_over_sphere(|h| ggx.d(h) * h.z().max(0.0));
+        assert!((area - 1.0).abs() < 0.01);
+        // and the visible normals' pdf integrates to 1
+        let wo = unit_vector(Vec3::new(0.3, -0.2, 1.0));
+        let total = integrate_over_sphere(|h| ggx.pdf_visible(&wo, h));
+        assert!((total - 1.0).abs() < 0.01);
+    }
+}
+#[test]
+fn test_microfacet_pdfs_integrate() {
+    let ggx = Ggx::new(0.6, 0.4);
+    let wo = unit_vector(Vec3::new(0.5, 0.1, 1.0));
+    // conductors only reflect, so their pdf integrates to (nearly) 1
+    let c = integrate_over_sphere(|wi| conductor_pdf(&ggx, &wo, wi));
+    assert!(c > 0.9 && c < 1.01);
+    // from either side of the glass
+    for &eta in &[1.5, 1.0 / 1.5] {
+        let d = integrate_over_sphere(|wi| dielectric_pdf(&ggx, eta, &wo, wi));
+        assert!(d > 0.9 && d < 1.01, "{} {}", eta, d);
+    }
+}
+#[test]
+fn test_microfacet_samples() {
+    use rand::{Rng, SeedableRng, StdRng};
+    let seed: &[_] = &[13];
+    let mut rng: StdRng = SeedableRng::from_seed(seed);
+    let ggx = Ggx::new(0.4, 0.7);
+    let (eta, k) = ConductorPreset::Gold.ior();
+    let wo = unit_vector(Vec3::new(-0.4, 0.3, 1.0));
+    for _ in 0..1000 {
+        let u = (rng.gen::<f64>(), rng.gen::<f64>());
+        if let Some((wi, weight, pdf)) = conductor_sample(&ggx, &eta, &k, &wo, u) {
+            assert!((pdf - conductor_pdf(&ggx, &wo, &wi)).abs() < 1e-6 * pdf.max(1.0));
+            let f = conductor_eval(&ggx, &eta, &k, &wo, &wi);
+            assert!((f * (wi.z() / pdf) - weight).length() < 1e-6);
+        }
+        let choose = rng.gen::<f64>();
+        if let Some((wi, weight, pdf)) = dielectric_sample(&ggx, 1.5, &wo, u, choose) {
+            assert!((pdf - dielectric_pdf(&ggx, 1.5, &wo, &wi)).abs() < 1e-6 * pdf.max(1.0));
+            let f = dielectric_eval(&ggx, 1.5, &wo, &wi);
+            assert!((f * wi.z().abs() / pdf - weight.x()).abs() < 1e-6);
+        }
+    }
+}
+#[test]
+fn test_fresnel() {
+    // normal incidence: ((n - 1) / (n + 1))^2
+    assert!((fresnel_dielectric(1.0, 1.5) - 0.04).abs() < 1e-12);
+    assert_eq!(fresnel_dielectric(0.1, 1.0 / 1.5), 1.0);
+    let f = fresnel_conductor(1.0, &Vec3::new(1.5, 1.5, 1.5), &Vec3::new(0.0, 0.0, 0.0));
+    assert!((f.x() - 0.04).abs() < 1e-12);
+    // metals reflect most light, gold more red than blue
+    let (eta, k) = ConductorPreset::Gold.ior();
+    let gold = fresnel_conductor(1.0, &eta, &k);
+    assert!(gold.x() > 0.9 && gold.x() > gold.z());
+    assert_eq!("aluminum".parse(), Ok(ConductorPreset::Aluminium));
+}
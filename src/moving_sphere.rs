@@ -0,0 +1,113 @@
+use aabb::{surrounding_box, Aabb};
+use hitable::{HitRecord, Hitable};
+use material::Material;
+use ray::Ray;
+use sphere::{hit_sphere, sphere_box};
+use vec3::Vec3;
+
+// A sphere whose center follows a path of (time, center) keyframes,
+// moving in a straight line between them.  Before the first and after
+// the last keyframe it stays put.
+#[derive(Debug)]
+pub struct MovingSphere {
+    keyframes: Vec<(f64, Vec3)>, // sorted by time
+    radius: f64,
+    material: Material,
+}
+
+impl MovingSphere {
+    // moves from center0 at time0 to center1 at time1
+    pub fn new(
+        center0: Vec3,
+        center1: Vec3,
+        time0: f64,
+        time1: f64,
+        radius: f64,
+        material: Material,
+    ) -> MovingSphere {
+        MovingSphere::with_keyframes(vec![(time0, center0), (time1, center1)], radius, material)
+    }
+
+    pub fn with_keyframes(
+        mut keyframes: Vec<(f64, Vec3)>,
+        radius: f64,
+        material: Material,
+    ) -> MovingSphere {
+        assert!(!keyframes.is_empty(), "a moving sphere needs keyframes");
+        keyframes.sort_by(|a, b| a.0.total_cmp(&b.0));
+        MovingSphere {
+            keyframes,
+            radius,
+            material,
+        }
+    }
+
+    pub fn center(&self, time: f64) -> Vec3 {
+        let keys = &self.keyframes;
+        // index of the first keyframe after time
+        let i = keys.iter().position(|k| k.0 > time).unwrap_or(keys.len());
+        if i == 0 {
+            return keys[0].1;
+        }
+        if i == keys.len() {
+            return keys[i - 1].1;
+        }
+        let (t0, c0) = keys[i - 1];
+        let (t1, c1) = keys[i];
+        c0 + ((time - t0) / (t1 - t0)) * (c1 - c0)
+    }
+}
+
+impl Hitable for MovingSphere {
+    fn hit<'a>(&'a self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord<'a>) -> bool {
+        let center = self.center(r.time);
+        hit_sphere(center, self.radius, &self.material, r, t_min, t_max, rec)
+    }
+    // The path is straight between keyframes, so the spheres at the
+    // keyframes bound all of it.
+    fn bounding_box(&self, output_box: &mut Aabb) -> bool {
+        let mut bbox = sphere_box(self.keyframes[0].1, self.radius);
+        for k in &self.keyframes[1..] {
+            bbox = surrounding_box(&bbox, &sphere_box(k.1, self.radius));
+        }
+        *output_box = bbox;
+        true
+    }
+}
+
+// ======================================================================
+// Unit testing
+// ======================================================================
+#[test]
+fn test_moving_sphere() {
+    use texture::solid;
+    let gray = Material::Lambertian {
+        albedo: solid(Vec3::new(0.5, 0.5, 0.5)),
+    };
+    let sphere = MovingSphere::with_keyframes(
+        vec![
+            (1.0, Vec3::new(2.0, 0.0, 0.0)),
+            (0.0, Vec3::new(0.0, 0.0, 0.0)),
+            (2.0, Vec3::new(2.0, 2.0, 0.0)),
+        ],
+        0.5,
+        gray,
+    );
+    assert_eq!(sphere.center(-1.0), Vec3::new(0.0, 0.0, 0.0));
+    assert_eq!(sphere.center(0.5), Vec3::new(1.0, 0.0, 0.0));
+    assert_eq!(sphere.center(1.5), Vec3::new(2.0, 1.0, 0.0));
+    assert_eq!(sphere.center(3.0), Vec3::new(2.0, 2.0, 0.0));
+
+    // hit only when the sphere is in the ray's way at the ray's time
+    let mut rec = HitRecord::new();
+    let early = Ray::with_time(Vec3::new(2.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0), 0.0);
+    assert!(!sphere.hit(&early, 0.001, f64::MAX, &mut rec));
+    let late = Ray::with_time(Vec3::new(2.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0), 1.0);
+    assert!(sphere.hit(&late, 0.001, f64::MAX, &mut rec));
+    assert_eq!(rec.t, 4.5);
+
+    let mut bbox = Aabb::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 0.0));
+    assert!(sphere.bounding_box(&mut bbox));
+    assert_eq!(bbox.min, Vec3::new(-0.5, -0.5, -0.5));
+    assert_eq!(bbox.max, Vec3::new(2.5, 2.5, 0.5));
+}
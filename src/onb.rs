@@ -0,0 +1,59 @@
+use vec3::{cross, dot, unit_vector, Vec3};
+
+// An orthonormal basis with w along a given direction, for working in
+// a frame around a normal or towards a light.
+pub struct Onb {
+    axis: [Vec3; 3],
+}
+
+impl Onb {
+    pub fn build_from_w(n: &Vec3) -> Onb {
+        let w = unit_vector(*n);
+        // any vector not parallel to w will do
+        let a = if w.x().abs() > 0.9 {
+            Vec3::new(0.0, 1.0, 0.0)
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let v = unit_vector(cross(&w, &a));
+        let u = cross(&w, &v);
+        Onb { axis: [u, v, w] }
+    }
+    pub fn u(&self) -> Vec3 {
+        self.axis[0]
+    }
+    pub fn v(&self) -> Vec3 {
+        self.axis[1]
+    }
+    pub fn w(&self) -> Vec3 {
+        self.axis[2]
+    }
+    // a vector given in this frame, in world coordinates
+    pub fn local(&self, a: &Vec3) -> Vec3 {
+        a.x() * self.u() + a.y() * self.v() + a.z() * self.w()
+    }
+    // the other way: a world vector's coordinates in this frame
+    pub fn to_local(&self, a: &Vec3) -> Vec3 {
+        Vec3::new(dot(a, &self.u()), dot(a, &self.v()), dot(a, &self.w()))
+    }
+}
+
+// ======================================================================
+// Unit testing
+// ======================================================================
+#[test]
+fn test_onb() {
+    for n in &[
+        Vec3::new(0.0, 0.0, 2.0),
+        Vec3::new(1.0, 0.0, 0.0),
+        Vec3::new(-1.0, 2.0, 3.0),
+    ] {
+        let uvw = Onb::build_from_w(n);
+        assert!((dot(&uvw.u(), &uvw.v())).abs() < 1e-12);
+        assert!((dot(&uvw.v(), &uvw.w())).abs() < 1e-12);
+        assert!((dot(&uvw.w(), &uvw.u())).abs() < 1e-12);
+        assert!((uvw.local(&Vec3::new(0.0, 0.0, 1.0)) - unit_vector(*n)).length() < 1e-12);
+        let a = Vec3::new(0.3, -2.0, 1.5);
+        assert!((uvw.local(&uvw.to_local(&a)) - a).length() < 1e-12);
+    }
+}
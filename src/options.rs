@@ -0,0 +1,626 @@
+use aperture::ApertureShape;
+use background::Background;
+use camera::{Projection, Stereo};
+use getopts;
+use image_writer::ImageFormat;
+use medium::PhaseFunction;
+use scene::{CameraSettings, FogSettings, ImageSettings, Scene};
+use std::str::FromStr;
+use texture::solid;
+use vec3::Vec3;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum SceneName {
+    Original,
+    RedBlue,
+    #[default]
+    Final,
+    Mesh,
+    CornellBox,
+}
+
+impl FromStr for SceneName {
+    type Err = String;
+    fn from_str(s: &str) -> Result<SceneName, String> {
+        match s {
+            "original" => Ok(SceneName::Original),
+            "redblue" => Ok(SceneName::RedBlue),
+            "final" => Ok(SceneName::Final),
+            "mesh" => Ok(SceneName::Mesh),
+            "cornell_box" => Ok(SceneName::CornellBox),
+            _ => Err(format!(
+                "unknown scene '{}' (expected original, redblue, final, mesh or cornell_box)",
+                s
+            )),
+        }
+    }
+}
+
+// Everything given on the command line.  Image & camera settings are
+// None unless given, so they can override the scene's own settings.
+#[derive(Debug, Default)]
+pub struct Options {
+    pub width: Option<usize>,
+    pub height: Option<usize>,
+    pub samples: Option<usize>,
+    pub max_depth: Option<i32>,
+    pub seed: usize,
+    pub scene: SceneName,
+    pub scene_file: Option<String>,
+    pub lookfrom: Option<Vec3>,
+    pub lookat: Option<Vec3>,
+    pub vup: Option<Vec3>,
+    pub vfov: Option<f64>,
+    pub aperture: Option<f64>,
+    pub focus_dist: Option<f64>,
+    pub focus_point: Option<Vec3>,
+    pub autofocus: bool,
+    pub blades: Option<(usize, f64)>, // and their rotation
+    pub cat_eye: Option<f64>,
+    pub squeeze: Option<f64>,
+    pub projection: Option<Projection>,
+    pub stereo: Option<Stereo>,
+    pub shutter: Option<(f64, f64)>, // open & close times
+    pub fog: Option<f64>,            // density, 0 for none
+    pub background: Option<Background>,
+    pub output: Option<String>, // None means stdout
+    pub format: ImageFormat,
+    pub no_light_sampling: bool, // only find lights by bouncing into them
+    pub spectral: bool,
+    pub help: bool,
+}
+
+const DEFAULT_SEED: usize = 1984;
+
+impl Options {
+    // override the scene's settings with any given on the command line
+    pub fn apply(&self, scene: &mut Scene) {
+        let image = &mut scene.image;
+        let camera = &mut scene.camera;
+        image.width = self.width.unwrap_or(image.width);
+        image.height = self.height.unwrap_or(image.height);
+        image.samples = self.samples.unwrap_or(image.samples);
+        image.max_depth = self.max_depth.unwrap_or(image.max_depth);
+        image.spectral |= self.spectral;
+        camera.lookfrom = self.lookfrom.unwrap_or(camera.lookfrom);
+        camera.lookat = self.lookat.unwrap_or(camera.lookat);
+        camera.vup = self.vup.unwrap_or(camera.vup);
+        camera.vfov = self.vfov.unwrap_or(camera.vfov);
+        camera.aperture = self.aperture.unwrap_or(camera.aperture);
+        camera.focus_dist = self.focus_dist.unwrap_or(camera.focus_dist);
+        // the last word on focus goes to the command line
+        if self.focus_dist.is_some() || self.focus_point.is_some() || self.autofocus {
+            camera.focus_point = self.focus_point;
+            camera.autofocus = self.autofocus;
+        }
+        if let Some((blades, rotation)) = self.blades {
+            camera.lens.shape = ApertureShape::Polygon { blades, rotation };
+        }
+        camera.lens.cat_eye = self.cat_eye.unwrap_or(camera.lens.cat_eye);
+        camera.lens.squeeze = self.squeeze.unwrap_or(camera.lens.squeeze);
+        if let Some((open, close)) = self.shutter {
+            camera.shutter_open = open;
+            camera.shutter_close = close;
+        }
+        camera.projection = self.projection.unwrap_or(camera.projection);
+        camera.stereo = self.stereo.or(camera.stereo);
+        if let Some(density) = self.fog {
+            scene.fog = if density > 0.0 {
+                Some(FogSettings {
+                    density,
+                    distance: None,
+                    albedo: solid(Vec3::new(1.0, 1.0, 1.0)),
+                    phase: PhaseFunction::Isotropic,
+                })
+            } else {
+                None
+            };
+        }
+        if let Some(ref background) = self.background {
+            scene.background = background.clone();
+        }
+    }
+}
+
+fn getopts_options() -> getopts::Options {
+    let d = ImageSettings::default();
+    let c = CameraSettings::default();
+    let mut opts = getopts::Options::new();
+    opts.optopt(
+        "W",
+        "width",
+        &format!("image width in pixels [{}]", d.width),
+        "N",
+    );
+    opts.optopt(
+        "H",
+        "height",
+        &format!("image height in pixels [{}]", d.height),
+        "N",
+    );
+    opts.optopt(
+        "s",
+        "samples",
+        &format!("samples per pixel [{}]", d.samples),
+        "N",
+    );
+    opts.optopt(
+        "d",
+        "depth",
+        &format!("maximum bounces per path [{}]", d.max_depth),
+        "N",
+    );
+    opts.optopt(
+        "",
+        "seed",
+        &format!("random number seed [{}]", DEFAULT_SEED),
+        "N",
+    );
+    opts.optopt(
+        "",
+        "scene",
+        "built-in scene: original, redblue, final, mesh or cornell_box [final]",
+        "NAME",
+    );
+    opts.optopt(
+        "f",
+        "scene-file",
+        "load the scene from a TOML scene file instead",
+        "FILE",
+    );
+    opts.optopt("", "lookfrom", "camera position [13,2,3]", "X,Y,Z");
+    opts.optopt("", "lookat", "point the camera looks at [0,0,0]", "X,Y,Z");
+    opts.optopt("", "vup", "camera up direction [0,1,0]", "X,Y,Z");
+    opts.optopt(
+        "",
+        "vfov",
+        &format!("vertical field of view in degrees [{}]", c.vfov),
+        "DEG",
+    );
+    opts.optopt(
+        "",
+        "aperture",
+        &format!("lens aperture [{}]", c.aperture),
+        "A",
+    );
+    opts.optopt(
+        "",
+        "focus-dist",
+        &format!("focus distance [{}]", c.focus_dist),
+        "D",
+    );
+    opts.optopt(
+        "",
+        "focus-point",
+        "focus at the distance of this point instead",
+        "X,Y,Z",
+    );
+    opts.optflag(
+        "",
+        "autofocus",
+        "focus on whatever is in the middle of the image",
+    );
+    opts.optopt(
+        "",
+        "blades",
+        "aperture blades, for polygonal bokeh, turned by ROT degrees [round]",
+        "N[,ROT]",
+    );
+    opts.optopt(
+        "",
+        "cat-eye",
+        "clip the aperture towards the edges of the frame, 0 to 1 [0]",
+        "K",
+    );
+    opts.optopt(
+        "",
+        "squeeze",
+        "anamorphic squeeze: how many times taller the aperture is than wide [1]",
+        "S",
+    );
+    opts.optopt(
+        "",
+        "shutter",
+        "shutter open & close times, for motion blur [0,0]",
+        "OPEN,CLOSE",
+    );
+    opts.optopt(
+        "",
+        "projection",
+        "perspective, orthographic,HEIGHT, equirectangular, fisheye[,FOV] or cubemap [perspective]",
+        "PROJ",
+    );
+    opts.optopt(
+        "",
+        "stereo",
+        "render both eyes, packed top_bottom or side_by_side, 0.065 apart & converging at the focus distance by default [none]",
+        "LAYOUT[,IOD[,CONV]]",
+    );
+    opts.optopt(
+        "",
+        "fog",
+        "density of white fog filling the scene, 0 for none [none]",
+        "DENSITY",
+    );
+    opts.optflag(
+        "",
+        "no-light-sampling",
+        "don't sample lights directly, only find them by chance",
+    );
+    opts.optflag(
+        "",
+        "spectral",
+        "trace wavelengths rather than RGB, so glass can disperse light",
+    );
+    opts.optopt(
+        "",
+        "background",
+        "what rays that miss everything see: gradient, black or R,G,B [gradient]",
+        "BG",
+    );
+    opts.optopt("o", "output", "output file [stdout]", "FILE");
+    opts.optopt(
+        "",
+        "format",
+        "image format: p3, p6, png, png16, exr, exr32, hdr or pfm [from the output file's extension, p3 for stdout]",
+        "FMT",
+    );
+    opts.optflag("h", "help", "print this help");
+    opts
+}
+
+pub fn usage(program: &str) -> String {
+    let brief = format!("Usage: {} [options]", program);
+    getopts_options().usage(&brief)
+}
+
+// parse a value, reporting which option it came from on failure
+fn parse_value<T: FromStr>(
+    matches: &getopts::Matches,
+    name: &str,
+    expected: &str,
+) -> Result<Option<T>, String> {
+    match matches.opt_str(name) {
+        Some(s) => s.parse::<T>().map(Some).map_err(|_| {
+            format!(
+                "invalid value for --{}: '{}' (expected {})",
+                name, s, expected
+            )
+        }),
+        None => Ok(None),
+    }
+}
+
+fn parse_positive(matches: &getopts::Matches, name: &str) -> Result<Option<usize>, String> {
+    let n = parse_value(matches, name, "a positive integer")?;
+    if n == Some(0) {
+        return Err(format!(
+            "invalid value for --{}: must be greater than 0",
+            name
+        ));
+    }
+    Ok(n)
+}
+
+fn parse_vec3(matches: &getopts::Matches, name: &str) -> Result<Option<Vec3>, String> {
+    match matches.opt_str(name) {
+        Some(s) => {
+            let v: Vec<f64> = s
+                .split(',')
+                .map(|c| c.trim().parse::<f64>())
+                .collect::<Result<_, _>>()
+                .map_err(|_| format!("invalid value for --{}: '{}' (expected X,Y,Z)", name, s))?;
+            if v.len() != 3 || v.iter().any(|c| !c.is_finite()) {
+                return Err(format!(
+                    "invalid value for --{}: '{}' (expected X,Y,Z)",
+                    name, s
+                ));
+            }
+            Ok(Some(Vec3::new(v[0], v[1], v[2])))
+        }
+        None => Ok(None),
+    }
+}
+
+fn parse_blades(matches: &getopts::Matches) -> Result<Option<(usize, f64)>, String> {
+    match matches.opt_str("blades") {
+        Some(s) => {
+            let bad = || format!("invalid value for --blades: '{}' (expected N[,ROT])", s);
+            let parts: Vec<&str> = s.split(',').map(|p| p.trim()).collect();
+            if parts.len() > 2 {
+                return Err(bad());
+            }
+            let blades = parts[0].parse::<usize>().map_err(|_| bad())?;
+            let rotation = match parts.get(1) {
+                Some(r) => r.parse::<f64>().map_err(|_| bad())?,
+                None => 0.0,
+            };
+            if !rotation.is_finite() {
+                return Err(bad());
+            }
+            if blades < 3 {
+                return Err("invalid value for --blades: must be at least 3".to_string());
+            }
+            Ok(Some((blades, rotation)))
+        }
+        None => Ok(None),
+    }
+}
+
+fn parse_shutter(matches: &getopts::Matches) -> Result<Option<(f64, f64)>, String> {
+    match matches.opt_str("shutter") {
+        Some(s) => {
+            let t: Vec<f64> = s
+                .split(',')
+                .map(|c| c.trim().parse::<f64>())
+                .collect::<Result<_, _>>()
+                .map_err(|_| {
+                    format!("invalid value for --shutter: '{}' (expected OPEN,CLOSE)", s)
+                })?;
+            if t.len() != 2 || t.iter().any(|c| !c.is_finite()) {
+                return Err(format!(
+                    "invalid value for --shutter: '{}' (expected OPEN,CLOSE)",
+                    s
+                ));
+            }
+            if t[1] < t[0] {
+                return Err(
+                    "invalid value for --shutter: close must not be before open".to_string()
+                );
+            }
+            Ok(Some((t[0], t[1])))
+        }
+        None => Ok(None),
+    }
+}
+
+// args does not include the program name
+pub fn parse_args(args: &[String]) -> Result<Options, String> {
+    let matches = getopts_options().parse(args).map_err(|e| e.to_string())?;
+    if matches.opt_present("help") {
+        return Ok(Options {
+            help: true,
+            ..Default::default()
+        });
+    }
+    if !matches.free.is_empty() {
+        return Err(format!("unexpected argument '{}'", matches.free[0]));
+    }
+    let options = Options {
+        width: parse_positive(&matches, "width")?,
+        height: parse_positive(&matches, "height")?,
+        samples: parse_positive(&matches, "samples")?,
+        max_depth: parse_value(&matches, "depth", "a non-negative integer")?,
+        seed: parse_value(&matches, "seed", "a non-negative integer")?.unwrap_or(DEFAULT_SEED),
+        scene: match matches.opt_str("scene") {
+            Some(s) => s.parse::<SceneName>()?,
+            None => SceneName::default(),
+        },
+        scene_file: matches.opt_str("scene-file"),
+        lookfrom: parse_vec3(&matches, "lookfrom")?,
+        lookat: parse_vec3(&matches, "lookat")?,
+        vup: parse_vec3(&matches, "vup")?,
+        vfov: parse_value(&matches, "vfov", "a number")?,
+        aperture: parse_value(&matches, "aperture", "a number")?,
+        focus_dist: parse_value(&matches, "focus-dist", "a number")?,
+        focus_point: parse_vec3(&matches, "focus-point")?,
+        autofocus: matches.opt_present("autofocus"),
+        blades: parse_blades(&matches)?,
+        cat_eye: parse_value(&matches, "cat-eye", "a number")?,
+        squeeze: parse_value(&matches, "squeeze", "a number")?,
+        shutter: parse_shutter(&matches)?,
+        projection: match matches.opt_str("projection") {
+            Some(s) => Some(s.parse::<Projection>()?),
+            None => None,
+        },
+        stereo: match matches.opt_str("stereo") {
+            Some(s) => Some(s.parse::<Stereo>()?),
+            None => None,
+        },
+        fog: parse_value(&matches, "fog", "a number")?,
+        background: match matches.opt_str("background") {
+            Some(s) => Some(s.parse::<Background>()?),
+            None => None,
+        },
+        no_light_sampling: matches.opt_present("no-light-sampling"),
+        spectral: matches.opt_present("spectral"),
+        output: matches.opt_str("output"),
+        format: ImageFormat::PpmAscii,
+        help: false,
+    };
+    if options.scene_file.is_some() && matches.opt_present("scene") {
+        return Err("--scene and --scene-file cannot be used together".to_string());
+    }
+    if options.max_depth.is_some_and(|d| d < 0) {
+        return Err("invalid value for --depth: must not be negative".to_string());
+    }
+    if options.vfov.is_some_and(|v| !v.is_finite() || v <= 0.0 || v >= 180.0) {
+        return Err("invalid value for --vfov: must be between 0 and 180 degrees".to_string());
+    }
+    if options.aperture.is_some_and(|a| !a.is_finite() || a < 0.0) {
+        return Err("invalid value for --aperture: must not be negative".to_string());
+    }
+    if options.focus_dist.is_some_and(|f| !f.is_finite() || f <= 0.0) {
+        return Err("invalid value for --focus-dist: must be greater than 0".to_string());
+    }
+    let focus = [
+        options.focus_dist.is_some(),
+        options.focus_point.is_some(),
+        options.autofocus,
+    ];
+    if focus.iter().filter(|&&f| f).count() > 1 {
+        return Err(
+            "--focus-dist, --focus-point and --autofocus cannot be used together".to_string(),
+        );
+    }
+    if options.cat_eye.is_some_and(|k| !(0.0..=1.0).contains(&k)) {
+        return Err("invalid value for --cat-eye: must be between 0 and 1".to_string());
+    }
+    if options.squeeze.is_some_and(|s| !s.is_finite() || s <= 0.0) {
+        return Err("invalid value for --squeeze: must be greater than 0".to_string());
+    }
+    if options.fog.is_some_and(|d| !d.is_finite() || d < 0.0) {
+        return Err("invalid value for --fog: must not be negative".to_string());
+    }
+    if let (Some(lookfrom), Some(lookat)) = (options.lookfrom, options.lookat) {
+        if (lookfrom - lookat).length() == 0.0 {
+            return Err("--lookfrom and --lookat must be different points".to_string());
+        }
+    }
+    Ok(Options {
+        format: output_format(&matches, &options.output)?,
+        ..options
+    })
+}
+
+// --format wins, then the output file's extension.  Stdout stays P3 so
+// `rustrt > out.ppm` works like it always has.
+fn output_format(
+    matches: &getopts::Matches,
+    output: &Option<String>,
+) -> Result<ImageFormat, String> {
+    if let Some(s) = matches.opt_str("format") {
+        return s.parse::<ImageFormat>();
+    }
+    match *output {
+        Some(ref path) => ImageFormat::from_path(path).ok_or_else(|| {
+            format!(
+                "cannot tell the image format of '{}', use --format to choose one",
+                path
+            )
+        }),
+        None => Ok(ImageFormat::PpmAscii),
+    }
+}
+
+// ======================================================================
+// Unit testing
+// ======================================================================
+#[cfg(test)]
+use hitable_list::HitableList;
+#[cfg(test)]
+fn to_args(s: &str) -> Vec<String> {
+    s.split_whitespace().map(|a| a.to_string()).collect()
+}
+#[test]
+fn test_parse_defaults() {
+    let o = parse_args(&[]).unwrap();
+    assert_eq!(o.width, None);
+    assert_eq!(o.seed, 1984);
+    assert_eq!(o.scene, SceneName::Final);
+    assert_eq!(o.scene_file, None);
+    assert_eq!(o.output, None);
+    assert_eq!(o.format, ImageFormat::PpmAscii);
+    let mut scene = Scene::new(HitableList::new());
+    o.apply(&mut scene);
+    assert_eq!(scene.image.width, 1440);
+    assert_eq!(scene.image.max_depth, 50);
+    assert_eq!(scene.camera.vfov, 30.0);
+    assert_eq!(scene.background, Background::Gradient);
+}
+#[test]
+fn test_parse_options() {
+    let o = parse_args(&to_args(
+        "-W 320 --height 200 -s 4 --depth 8 --seed 7 --scene redblue \
+         --lookfrom 0,0,1 --lookat 0,0,-1 --vfov 90 --shutter 0,0.5 --projection fisheye,200 --background black --spectral -o out.ppm",
+    ))
+    .unwrap();
+    let mut scene = Scene::new(HitableList::new());
+    o.apply(&mut scene);
+    let image = scene.image;
+    let camera = scene.camera;
+    assert_eq!(image.width, 320);
+    assert_eq!(image.height, 200);
+    assert_eq!(image.samples, 4);
+    assert_eq!(image.max_depth, 8);
+    assert!(image.spectral);
+    assert_eq!(
+        scene.background,
+        Background::Constant(Vec3::new(0.0, 0.0, 0.0))
+    );
+    assert_eq!(o.seed, 7);
+    assert_eq!(o.scene, SceneName::RedBlue);
+    assert_eq!(camera.lookfrom, Vec3::new(0.0, 0.0, 1.0));
+    assert_eq!(camera.lookat, Vec3::new(0.0, 0.0, -1.0));
+    assert_eq!(camera.vfov, 90.0);
+    assert_eq!(camera.aperture, 0.1);
+    assert_eq!((camera.shutter_open, camera.shutter_close), (0.0, 0.5));
+    assert_eq!(camera.projection, Projection::Fisheye { fov: 200.0 });
+    assert_eq!(camera.stereo, None);
+    assert_eq!(o.output, Some("out.ppm".to_string()));
+    assert_eq!(o.format, ImageFormat::PpmBinary);
+}
+#[test]
+fn test_parse_lens_options() {
+    let mut scene = Scene::new(HitableList::new());
+    scene.camera.autofocus = true;
+    parse_args(&to_args(
+        "--focus-point 1,2,3 --blades 6,30 --cat-eye 0.4 --squeeze 1.33",
+    ))
+    .unwrap()
+    .apply(&mut scene);
+    let camera = scene.camera;
+    assert_eq!(camera.focus_point, Some(Vec3::new(1.0, 2.0, 3.0)));
+    assert!(!camera.autofocus);
+    assert_eq!(
+        camera.lens.shape,
+        ApertureShape::Polygon {
+            blades: 6,
+            rotation: 30.0
+        }
+    );
+    assert_eq!((camera.lens.cat_eye, camera.lens.squeeze), (0.4, 1.33));
+}
+#[test]
+fn test_parse_errors() {
+    assert!(parse_args(&to_args("--width abc"))
+        .unwrap_err()
+        .contains("--width"));
+    assert!(parse_args(&to_args("--samples 0")).is_err());
+    assert!(parse_args(&to_args("--scene nope"))
+        .unwrap_err()
+        .contains("nope"));
+    assert!(parse_args(&to_args("--lookat 1,2")).is_err());
+    assert!(parse_args(&to_args("--lookfrom 1,inf,2")).is_err());
+    assert!(parse_args(&to_args("--vfov NaN")).is_err());
+    assert!(parse_args(&to_args("--aperture NaN")).is_err());
+    assert!(parse_args(&to_args("--focus-dist inf")).is_err());
+    assert!(parse_args(&to_args("--shutter 1,0")).is_err());
+    assert!(parse_args(&to_args("--shutter 0,inf")).is_err());
+    assert!(parse_args(&to_args("--projection orthographic"))
+        .unwrap_err()
+        .contains("orthographic,HEIGHT"));
+    assert!(parse_args(&to_args("--projection fisheye,inf")).is_err());
+    let stereo = parse_args(&to_args("--stereo side_by_side,0.1"))
+        .unwrap()
+        .stereo
+        .unwrap();
+    assert_eq!((stereo.interocular, stereo.convergence), (0.1, None));
+    assert!(parse_args(&to_args("--stereo side_by_side,0"))
+        .unwrap_err()
+        .contains("interocular"));
+    assert!(parse_args(&to_args("--stereo anaglyph")).is_err());
+    assert!(parse_args(&to_args("--stereo top_bottom,0.1,inf")).is_err());
+    assert!(parse_args(&to_args("--focus-dist 4 --autofocus"))
+        .unwrap_err()
+        .contains("cannot be used together"));
+    assert!(parse_args(&to_args("--blades 2")).is_err());
+    assert!(parse_args(&to_args("--blades 6,1,2")).is_err());
+    assert!(parse_args(&to_args("--blades 6,NaN")).is_err());
+    assert!(parse_args(&to_args("--cat-eye 2")).is_err());
+    assert!(parse_args(&to_args("--squeeze 0")).is_err());
+    assert!(parse_args(&to_args("--squeeze inf")).is_err());
+    assert!(parse_args(&to_args("--cat-eye NaN")).is_err());
+    assert!(parse_args(&to_args("--fog inf")).is_err());
+    assert!(parse_args(&to_args("--bogus")).is_err());
+    assert!(parse_args(&to_args("--scene final -f final.toml")).is_err());
+    assert!(parse_args(&to_args("-o out.tga")).is_err());
+    assert!(parse_args(&to_args("--format gif")).is_err());
+    assert_eq!(
+        parse_args(&to_args("-o out.tga --format png16"))
+            .unwrap()
+            .format,
+        ImageFormat::Png16
+    );
+    assert!(parse_args(&to_args("-h")).unwrap().help);
+}
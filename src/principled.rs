@@ -0,0 +1,269 @@
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+use hitable::HitRecord;
+use material::{relative_eta, shading_frame, BsdfSample};
+use microfacet::{dielectric_eval, dielectric_pdf, dielectric_sample, Ggx};
+use onb::Onb;
+use rand::Rng;
+use texture::{solid, Texture};
+use vec3::{dot, random_cosine_direction, reflect, unit_vector, Vec3};
+
+// A Disney-style "principled" material (Burley 2012 & 2015): one set of
+// artist-friendly parameters, each 0..1 and textureable, that covers
+// everything from rubber to metal to glass.  Scalar parameters read the
+// average of their texture's channels.
+//
+// It is a mix of lobes: a diffuse base with sheen, a GGX specular
+// reflection whose Fresnel goes from dielectric to metallic, a GGX glass
+// lobe for transmission and a thin clearcoat on top.
+#[derive(Clone, Debug)]
+pub struct Principled {
+    pub base_color: Arc<dyn Texture>,
+    pub metallic: Arc<dyn Texture>,
+    pub roughness: Arc<dyn Texture>,
+    pub specular: Arc<dyn Texture>, // 0.5 is an index of refraction of 1.5
+    pub specular_tint: Arc<dyn Texture>,
+    pub sheen: Arc<dyn Texture>,
+    pub sheen_tint: Arc<dyn Texture>,
+    pub clearcoat: Arc<dyn Texture>,
+    pub clearcoat_gloss: Arc<dyn Texture>,
+    pub transmission: Arc<dyn Texture>,
+    pub ior: f64, // of the transmission lobe
+}
+
+fn constant(x: f64) -> Arc<dyn Texture> {
+    solid(Vec3::new(x, x, x))
+}
+
+impl Principled {
+    // a rough, white-ish plastic until the parameters are changed
+    pub fn new(base_color: Arc<dyn Texture>) -> Principled {
+        Principled {
+            base_color,
+            metallic: constant(0.0),
+            roughness: constant(0.5),
+            specular: constant(0.5),
+            specular_tint: constant(0.0),
+            sheen: constant(0.0),
+            sheen_tint: constant(0.5),
+            clearcoat: constant(0.0),
+            clearcoat_gloss: constant(1.0),
+            transmission: constant(0.0),
+            ior: 1.5,
+        }
+    }
+
+    // the parameters at rec
+    fn lobes(&self, rec: &HitRecord, wo: &Vec3) -> Lobes {
+        let scalar = |t: &Arc<dyn Texture>| {
+            let c = t.value(rec.u, rec.v, &rec.p);
+            ((c.x() + c.y() + c.z()) / 3.0).clamp(0.0, 1.0)
+        };
+        let base = self.base_color.value(rec.u, rec.v, &rec.p);
+        let metallic = scalar(&self.metallic);
+        let roughness = scalar(&self.roughness);
+        let transmission = scalar(&self.transmission);
+        let luminance = 0.2126 * base.x() + 0.7152 * base.y() + 0.0722 * base.z();
+        let tint = if luminance > 0.0 {
+            base / luminance
+        } else {
+            Vec3::new(1.0, 1.0, 1.0)
+        };
+        let white = Vec3::new(1.0, 1.0, 1.0);
+        let specular_tint = scalar(&self.specular_tint);
+        let dielectric_f0 =
+            0.08 * scalar(&self.specular) * ((1.0 - specular_tint) * white + specular_tint * tint);
+        let sheen_tint = scalar(&self.sheen_tint);
+        let clearcoat_alpha = 0.1 + (0.001 - 0.1) * scalar(&self.clearcoat_gloss);
+        Lobes {
+            frame: shading_frame(rec, wo),
+            eta: relative_eta(rec, wo, self.ior),
+            base,
+            roughness,
+            diffuse_weight: (1.0 - metallic) * (1.0 - transmission),
+            specular_weight: 1.0 - (1.0 - metallic) * transmission,
+            transmission_weight: (1.0 - metallic) * transmission,
+            clearcoat_weight: 0.25 * scalar(&self.clearcoat),
+            f0: (1.0 - metallic) * dielectric_f0 + metallic * base,
+            sheen: scalar(&self.sheen) * ((1.0 - sheen_tint) * white + sheen_tint * tint),
+            specular: Ggx::new(roughness, roughness),
+            clearcoat: Ggx::new(clearcoat_alpha.sqrt(), clearcoat_alpha.sqrt()),
+        }
+    }
+
+    pub fn sample<R: Rng>(&self, rec: &HitRecord, wo: &Vec3, rng: &mut R) -> Option<BsdfSample> {
+        let lobes = self.lobes(rec, wo);
+        let wo_local = lobes.frame.to_local(wo);
+        let u = (rng.gen::<f64>(), rng.gen::<f64>());
+        // pick a lobe to sample, then weight by all of them together
+        let mut choose = rng.gen::<f64>() * lobes.total_weight();
+        let wi = if choose < lobes.diffuse_weight {
+            lobes.frame.local(&random_cosine_direction(rng))
+        } else {
+            choose -= lobes.diffuse_weight;
+            let h = if choose < lobes.specular_weight {
+                lobes.specular.sample_visible(&wo_local, u)
+            } else {
+                choose -= lobes.specular_weight;
+                if choose < lobes.transmission_weight {
+                    let (wi, _, _) = dielectric_sample(
+                        &lobes.specular,
+                        lobes.eta,
+                        &wo_local,
+                        u,
+                        rng.gen::<f64>(),
+                    )?;
+                    return lobes.weighted(wo, lobes.frame.local(&wi));
+                }
+                lobes.clearcoat.sample_visible(&wo_local, u)
+            };
+            lobes.frame.local(&reflect(&-wo_local, &h))
+        };
+        lobes.weighted(wo, wi)
+    }
+
+    pub fn eval(&self, rec: &HitRecord, wi: &Vec3, wo: &Vec3) -> Vec3 {
+        let lobes = self.lobes(rec, wo);
+        lobes.eval(&lobes.frame.to_local(wo), &lobes.frame.to_local(wi))
+    }
+
+    pub fn pdf(&self, rec: &HitRecord, wi: &Vec3, wo: &Vec3) -> f64 {
+        let lobes = self.lobes(rec, wo);
+        lobes.pdf(&lobes.frame.to_local(wo), &lobes.frame.to_local(wi))
+    }
+}
+
+// The parameters at one point, in the shading frame where wo is above
+// the surface.
+struct Lobes {
+    frame: Onb,
+    eta: f64,
+    base: Vec3,
+    roughness: f64,
+    diffuse_weight: f64,
+    specular_weight: f64,
+    transmission_weight: f64,
+    clearcoat_weight: f64,
+    f0: Vec3, // specular reflectance at normal incidence
+    sheen: Vec3,
+    specular: Ggx,
+    clearcoat: Ggx,
+}
+
+fn schlick_weight(cosine: f64) -> f64 {
+    (1.0 - cosine).clamp(0.0, 1.0).powi(5)
+}
+
+impl Lobes {
+    fn total_weight(&self) -> f64 {
+        self.diffuse_weight
+            + self.specular_weight
+            + self.transmission_weight
+            + self.clearcoat_weight
+    }
+
+    // a direction sampled from one lobe, weighted as if from the mix
+    fn weighted(&self, wo: &Vec3, wi: Vec3) -> Option<BsdfSample> {
+        let (wo_local, wi_local) = (self.frame.to_local(wo), self.frame.to_local(&wi));
+        let pdf = self.pdf(&wo_local, &wi_local);
+        if pdf <= 0.0 {
+            return None;
+        }
+        let f = self.eval(&wo_local, &wi_local);
+        Some(BsdfSample {
+            wi,
+            weight: f * (wi_local.z().abs() / pdf),
+            pdf: Some(pdf),
+        })
+    }
+
+    fn eval(&self, wo: &Vec3, wi: &Vec3) -> Vec3 {
+        let mut f = Vec3::new(0.0, 0.0, 0.0);
+        if self.transmission_weight > 0.0 {
+            let glass = dielectric_eval(&self.specular, self.eta, wo, wi);
+            f += self.transmission_weight * glass * self.base;
+        }
+        if wo.z() <= 0.0 || wi.z() <= 0.0 {
+            return f;
+        }
+        let h = unit_vector(*wo + *wi);
+        let cos_d = dot(wi, &h);
+        if self.diffuse_weight > 0.0 {
+            let fd90 = 0.5 + 2.0 * self.roughness * cos_d * cos_d;
+            let fl = 1.0 + (fd90 - 1.0) * schlick_weight(wi.z());
+            let fv = 1.0 + (fd90 - 1.0) * schlick_weight(wo.z());
+            let diffuse = self.base * (fl * fv / PI);
+            let sheen = self.sheen * schlick_weight(cos_d);
+            f += self.diffuse_weight * (diffuse + sheen);
+        }
+        let fresnel = schlick_weight(dot(wo, &h));
+        let white = Vec3::new(1.0, 1.0, 1.0);
+        let spec_f = self.f0 + fresnel * (white - self.f0);
+        let d = self.specular.d(&h) * self.specular.g(wo, wi) / (4.0 * wo.z() * wi.z());
+        f += self.specular_weight * d * spec_f;
+        if self.clearcoat_weight > 0.0 {
+            let coat_f = 0.04 + 0.96 * fresnel;
+            let d = self.clearcoat.d(&h) * self.clearcoat.g(wo, wi) / (4.0 * wo.z() * wi.z());
+            f += Vec3::new(1.0, 1.0, 1.0) * (self.clearcoat_weight * coat_f * d);
+        }
+        f
+    }
+
+    // each lobe's pdf, in proportion to how often it is picked
+    fn pdf(&self, wo: &Vec3, wi: &Vec3) -> f64 {
+        let mut pdf = 0.0;
+        if self.transmission_weight > 0.0 {
+            pdf += self.transmission_weight * dielectric_pdf(&self.specular, self.eta, wo, wi);
+        }
+        if wo.z() > 0.0 && wi.z() > 0.0 {
+            let h = unit_vector(*wo + *wi);
+            let reflection = 4.0 * dot(wo, &h);
+            pdf += self.diffuse_weight * wi.z() / PI;
+            pdf += self.specular_weight * self.specular.pdf_visible(wo, &h) / reflection;
+            pdf += self.clearcoat_weight * self.clearcoat.pdf_visible(wo, &h) / reflection;
+        }
+        pdf / self.total_weight()
+    }
+}
+
+// ======================================================================
+// Unit testing
+// ======================================================================
+#[test]
+fn test_principled_samples() {
+    use material::Material;
+    use rand::{SeedableRng, StdRng};
+    let seed: &[_] = &[17];
+    let mut rng: StdRng = SeedableRng::from_seed(seed);
+    let mut p = Principled::new(solid(Vec3::new(0.8, 0.3, 0.2)));
+    p.metallic = constant(0.3);
+    p.sheen = constant(0.5);
+    p.clearcoat = constant(1.0);
+    p.transmission = constant(0.4);
+    let material = Material::Principled(Arc::new(p));
+    let mut rec = HitRecord::new();
+    rec.normal = Vec3::new(0.0, 0.0, 1.0);
+    rec.material = &material;
+    // from outside & inside
+    for wo in &[
+        unit_vector(Vec3::new(0.3, 0.2, 1.0)),
+        unit_vector(Vec3::new(0.3, 0.2, -1.0)),
+    ] {
+        let (mut n, mut transmitted) = (0, 0);
+        for _ in 0..2000 {
+            if let Some(s) = material.sample(&rec, wo, &mut rng) {
+                let pdf = s.pdf.unwrap();
+                assert!((pdf - material.pdf(&rec, &s.wi, wo)).abs() < 1e-6 * pdf.max(1.0));
+                let f = material.eval(&rec, &s.wi, wo);
+                let cosine = dot(&s.wi, &rec.normal).abs();
+                assert!((f * (cosine / pdf) - s.weight).length() < 1e-6);
+                n += 1;
+                if dot(&s.wi, &rec.normal) * dot(wo, &rec.normal) < 0.0 {
+                    transmitted += 1;
+                }
+            }
+        }
+        assert!(n > 1500 && transmitted > 100);
+    }
+}
@@ -0,0 +1,217 @@
+use std::sync::Arc;
+
+use ies::IesProfile;
+use vec3::{cross, dot, unit_vector, Vec3};
+
+// Lights with no size, for blocking out lighting.  Rays can never hit
+// them, so they are only found by sending shadow rays towards them.
+// The light from each is its color times its intensity: the power per
+// steradian for point & spot lights, falling off with the square of the
+// distance, and the irradiance straight on for directional lights.
+#[derive(Clone, Debug, PartialEq)]
+pub enum PunctualLight {
+    Point {
+        position: Vec3,
+        intensity: Vec3,
+    },
+    // brightest within cos_inner of direction, fading smoothly to
+    // nothing at cos_outer
+    Spot {
+        position: Vec3,
+        direction: Vec3,
+        cos_inner: f64,
+        cos_outer: f64,
+        intensity: Vec3,
+    },
+    // like the sun: parallel light travelling along direction
+    Directional {
+        direction: Vec3,
+        irradiance: Vec3,
+    },
+    // A point or spot light scaled by a fixture's measured profile,
+    // relative to its brightest direction.  The profile's vertical
+    // angle 0 is along nadir, and horizontal angle 0 along u, then
+    // round towards v.
+    Goniometric {
+        light: Box<PunctualLight>,
+        profile: Arc<IesProfile>,
+        nadir: Vec3,
+        u: Vec3,
+        v: Vec3,
+    },
+}
+
+impl PunctualLight {
+    pub fn point(position: Vec3, color: Vec3, intensity: f64) -> PunctualLight {
+        PunctualLight::Point {
+            position,
+            intensity: color * intensity,
+        }
+    }
+
+    // the cone's angles are from its axis, in degrees
+    pub fn spot(
+        position: Vec3,
+        direction: Vec3,
+        inner_angle: f64,
+        outer_angle: f64,
+        color: Vec3,
+        intensity: f64,
+    ) -> PunctualLight {
+        PunctualLight::Spot {
+            position,
+            direction: unit_vector(direction),
+            cos_inner: inner_angle.to_radians().cos(),
+            cos_outer: outer_angle.to_radians().cos(),
+            intensity: color * intensity,
+        }
+    }
+
+    pub fn directional(direction: Vec3, color: Vec3, intensity: f64) -> PunctualLight {
+        PunctualLight::Directional {
+            direction: unit_vector(direction),
+            irradiance: color * intensity,
+        }
+    }
+
+    // Shapes a point or spot light with a profile pointing along nadir,
+    // turned by rotation degrees about it.  Unturned, horizontal angle
+    // 0 is as near +x as it can be (or +z, if nadir is near x).
+    pub fn with_profile(
+        self,
+        profile: Arc<IesProfile>,
+        nadir: Vec3,
+        rotation: f64,
+    ) -> PunctualLight {
+        let nadir = unit_vector(nadir);
+        let reference = if nadir.x().abs() < 0.9 {
+            Vec3::new(1.0, 0.0, 0.0)
+        } else {
+            Vec3::new(0.0, 0.0, 1.0)
+        };
+        let u0 = unit_vector(reference - dot(&reference, &nadir) * nadir);
+        // anticlockwise seen from above when nadir is straight down
+        let v0 = cross(&u0, &nadir);
+        let (sin_r, cos_r) = rotation.to_radians().sin_cos();
+        PunctualLight::Goniometric {
+            light: Box::new(self),
+            profile,
+            nadir,
+            u: cos_r * u0 + sin_r * v0,
+            v: -sin_r * u0 + cos_r * v0,
+        }
+    }
+
+    // The unit direction from p towards the light, how far away it is &
+    // the irradiance it gives a surface at p facing it, if any.
+    pub fn illuminate(&self, p: &Vec3) -> Option<(Vec3, f64, Vec3)> {
+        let (position, intensity, falloff) = match *self {
+            PunctualLight::Point {
+                position,
+                intensity,
+            } => (position, intensity, 1.0),
+            PunctualLight::Spot {
+                position,
+                direction,
+                cos_inner,
+                cos_outer,
+                intensity,
+            } => {
+                let cosine = dot(&unit_vector(*p - position), &direction);
+                (
+                    position,
+                    intensity,
+                    smoothstep(cos_outer, cos_inner, cosine),
+                )
+            }
+            PunctualLight::Directional {
+                direction,
+                irradiance,
+            } => return Some((-direction, f64::MAX, irradiance)),
+            PunctualLight::Goniometric {
+                ref light,
+                ref profile,
+                nadir,
+                u,
+                v,
+            } => {
+                let (wi, distance, irradiance) = light.illuminate(p)?;
+                let out = -wi;
+                let vertical = dot(&out, &nadir).clamp(-1.0, 1.0).acos().to_degrees();
+                let horizontal = dot(&out, &v).atan2(dot(&out, &u)).to_degrees();
+                let candela = profile.candela(vertical, horizontal);
+                if candela <= 0.0 {
+                    return None;
+                }
+                return Some((wi, distance, irradiance * (candela / profile.max_candela)));
+            }
+        };
+        let to_light = position - *p;
+        let distance_squared = to_light.squared_length();
+        if falloff <= 0.0 || distance_squared == 0.0 {
+            return None;
+        }
+        let distance = distance_squared.sqrt();
+        Some((
+            to_light / distance,
+            distance,
+            intensity * (falloff / distance_squared),
+        ))
+    }
+}
+
+// 0 at edge0, 1 at edge1 & a smooth cubic in between
+fn smoothstep(edge0: f64, edge1: f64, x: f64) -> f64 {
+    if edge1 <= edge0 {
+        return if x >= edge1 { 1.0 } else { 0.0 };
+    }
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+// ======================================================================
+// Unit testing
+// ======================================================================
+#[test]
+fn test_punctual_lights() {
+    let white = Vec3::new(1.0, 1.0, 1.0);
+    let origin = Vec3::new(0.0, 0.0, 0.0);
+    // inverse square
+    let point = PunctualLight::point(Vec3::new(0.0, 2.0, 0.0), white, 8.0);
+    let (wi, distance, e) = point.illuminate(&origin).unwrap();
+    assert_eq!(
+        (wi, distance, e),
+        (Vec3::new(0.0, 1.0, 0.0), 2.0, 2.0 * white)
+    );
+    let (_, _, far) = point.illuminate(&Vec3::new(0.0, -2.0, 0.0)).unwrap();
+    assert_eq!(far, 0.5 * white);
+    // full inside the inner cone, none outside the outer, smooth between
+    let down = Vec3::new(0.0, -1.0, 0.0);
+    let spot = PunctualLight::spot(Vec3::new(0.0, 1.0, 0.0), down, 20.0, 40.0, white, 1.0);
+    let at = |degrees: f64| {
+        let p = Vec3::new(degrees.to_radians().tan(), 0.0, 0.0);
+        spot.illuminate(&p).map_or(0.0, |(_, d, e)| e.x() * d * d)
+    };
+    assert!((at(10.0) - 1.0).abs() < 1e-12);
+    assert_eq!(at(45.0), 0.0);
+    assert!(at(25.0) < 1.0 && at(25.0) > at(30.0) && at(30.0) > at(35.0) && at(35.0) > 0.0);
+    // the same everywhere, from the opposite way it shines
+    let sun = PunctualLight::directional(Vec3::new(0.0, -2.0, 0.0), white, 3.0);
+    let (wi, distance, e) = sun.illuminate(&Vec3::new(5.0, 1.0, 5.0)).unwrap();
+    assert_eq!(
+        (wi, distance, e),
+        (Vec3::new(0.0, 1.0, 0.0), f64::MAX, 3.0 * white)
+    );
+    // a profile brightest straight down & dark sideways, with more
+    // light towards horizontal angle 90 than 0
+    let text = "TILT=NONE\n1 -1 1 3 2 1 1 0 0 0\n1 1 10\n0 45 90\n0 90\n100 50 0\n100 80 0\n";
+    let profile = Arc::new(IesProfile::parse(text).unwrap());
+    let light =
+        PunctualLight::point(Vec3::new(0.0, 1.0, 0.0), white, 1.0).with_profile(profile, down, 0.0);
+    let at = |p: Vec3| light.illuminate(&p).map_or(0.0, |(_, d, e)| e.x() * d * d);
+    assert!((at(origin) - 1.0).abs() < 1e-12);
+    assert!((at(Vec3::new(1.0, 0.0, 0.0)) - 0.5).abs() < 1e-12);
+    // anticlockwise from +x seen from above is towards -z
+    assert!((at(Vec3::new(0.0, 0.0, -1.0)) - 0.8).abs() < 1e-12);
+    assert_eq!(at(Vec3::new(1.0, 1.0, 0.0)), 0.0);
+}
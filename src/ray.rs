@@ -4,11 +4,19 @@ use vec3::Vec3;
 pub struct Ray {
     pub origin: Vec3,
     pub direction: Vec3,
+    pub time: f64, // when the ray was sent, within the camera's shutter interval
 }
 
 impl Ray {
     pub fn new(origin: Vec3, direction: Vec3) -> Ray {
-        Ray { origin, direction }
+        Ray::with_time(origin, direction, 0.0)
+    }
+    pub fn with_time(origin: Vec3, direction: Vec3, time: f64) -> Ray {
+        Ray {
+            origin,
+            direction,
+            time,
+        }
     }
     pub fn point_at_parameter(&self, t: f64) -> Vec3 {
         self.origin + t * self.direction
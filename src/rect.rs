@@ -0,0 +1,169 @@
+use aabb::Aabb;
+use hitable::{HitRecord, Hitable};
+use material::Material;
+use ray::Ray;
+#[cfg(test)]
+use texture::solid;
+use vec3::Vec3;
+
+// Axis-aligned rectangle at k along one axis, spanning [a0,a1] x [b0,b1]
+// on the other two.  Use AaRect::xy, ::xz & ::yz to make one.  The
+// normal points along +k; wrap it in FlipNormals to face the other way.
+#[derive(Debug)]
+pub struct AaRect {
+    a_axis: usize,
+    b_axis: usize,
+    k_axis: usize,
+    a0: f64,
+    a1: f64,
+    b0: f64,
+    b1: f64,
+    k: f64,
+    material: Material,
+}
+
+impl AaRect {
+    pub fn xy(x0: f64, x1: f64, y0: f64, y1: f64, k: f64, material: Material) -> AaRect {
+        AaRect::new(0, 1, 2, x0, x1, y0, y1, k, material)
+    }
+    pub fn xz(x0: f64, x1: f64, z0: f64, z1: f64, k: f64, material: Material) -> AaRect {
+        AaRect::new(0, 2, 1, x0, x1, z0, z1, k, material)
+    }
+    pub fn yz(y0: f64, y1: f64, z0: f64, z1: f64, k: f64, material: Material) -> AaRect {
+        AaRect::new(1, 2, 0, y0, y1, z0, z1, k, material)
+    }
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        a_axis: usize,
+        b_axis: usize,
+        k_axis: usize,
+        a0: f64,
+        a1: f64,
+        b0: f64,
+        b1: f64,
+        k: f64,
+        material: Material,
+    ) -> AaRect {
+        AaRect {
+            a_axis,
+            b_axis,
+            k_axis,
+            a0,
+            a1,
+            b0,
+            b1,
+            k,
+            material,
+        }
+    }
+}
+
+impl Hitable for AaRect {
+    fn hit<'a>(&'a self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord<'a>) -> bool {
+        let t = (self.k - r.origin[self.k_axis]) / r.direction[self.k_axis];
+        if !(t > t_min && t < t_max) {
+            return false;
+        }
+        let a = r.origin[self.a_axis] + t * r.direction[self.a_axis];
+        let b = r.origin[self.b_axis] + t * r.direction[self.b_axis];
+        if a < self.a0 || a > self.a1 || b < self.b0 || b > self.b1 {
+            return false;
+        }
+        rec.t = t;
+        rec.p = r.point_at_parameter(t);
+        rec.normal = Vec3::new(0.0, 0.0, 0.0);
+        rec.normal[self.k_axis] = 1.0;
+        rec.u = (a - self.a0) / (self.a1 - self.a0);
+        rec.v = (b - self.b0) / (self.b1 - self.b0);
+        rec.material = &self.material;
+        true
+    }
+    fn bounding_box(&self, output_box: &mut Aabb) -> bool {
+        // pad the flat dimension so the slab test works
+        let mut min = Vec3::new(0.0, 0.0, 0.0);
+        let mut max = Vec3::new(0.0, 0.0, 0.0);
+        min[self.a_axis] = self.a0;
+        max[self.a_axis] = self.a1;
+        min[self.b_axis] = self.b0;
+        max[self.b_axis] = self.b1;
+        min[self.k_axis] = self.k - 0.0001;
+        max[self.k_axis] = self.k + 0.0001;
+        *output_box = Aabb::new(min, max);
+        true
+    }
+    fn is_light(&self) -> bool {
+        matches!(self.material, Material::DiffuseLight { .. })
+    }
+    // uniform over the area, converted to solid angle as seen from o
+    fn pdf_value(&self, o: &Vec3, v: &Vec3) -> f64 {
+        let mut rec = HitRecord::new();
+        if !self.hit(&Ray::new(*o, *v), 0.001, f64::MAX, &mut rec) {
+            return 0.0;
+        }
+        let area = (self.a1 - self.a0) * (self.b1 - self.b0);
+        let distance_squared = rec.t * rec.t * v.squared_length();
+        let cosine = v[self.k_axis].abs() / v.length();
+        distance_squared / (cosine * area)
+    }
+    fn random(&self, o: &Vec3, sample: (f64, f64)) -> Vec3 {
+        let mut p = Vec3::new(0.0, 0.0, 0.0);
+        p[self.a_axis] = self.a0 + sample.0 * (self.a1 - self.a0);
+        p[self.b_axis] = self.b0 + sample.1 * (self.b1 - self.b0);
+        p[self.k_axis] = self.k;
+        p - *o
+    }
+}
+
+// ======================================================================
+// Unit testing
+// ======================================================================
+#[test]
+fn test_rect_hit() {
+    let white = Material::Lambertian {
+        albedo: solid(Vec3::new(0.73, 0.73, 0.73)),
+    };
+    let floor = AaRect::xz(0.0, 2.0, 0.0, 4.0, 1.0, white);
+    let mut rec = HitRecord::new();
+    let down = Ray::new(Vec3::new(1.0, 3.0, 1.0), Vec3::new(0.0, -1.0, 0.0));
+    assert!(floor.hit(&down, 0.001, f64::MAX, &mut rec));
+    assert_eq!(rec.t, 2.0);
+    assert_eq!(rec.normal, Vec3::new(0.0, 1.0, 0.0));
+    assert_eq!((rec.u, rec.v), (0.5, 0.25));
+    let outside = Ray::new(Vec3::new(3.0, 3.0, 1.0), Vec3::new(0.0, -1.0, 0.0));
+    assert!(!floor.hit(&outside, 0.001, f64::MAX, &mut rec));
+    let parallel = Ray::new(Vec3::new(1.0, 3.0, 1.0), Vec3::new(1.0, 0.0, 0.0));
+    assert!(!floor.hit(&parallel, 0.001, f64::MAX, &mut rec));
+}
+#[test]
+fn test_rect_light_pdf() {
+    let light = AaRect::xz(
+        -1.0,
+        1.0,
+        -1.0,
+        1.0,
+        2.0,
+        Material::DiffuseLight {
+            emit: Vec3::new(1.0, 1.0, 1.0),
+            strength: 4.0,
+        },
+    );
+    assert!(light.is_light());
+    // straight up at a 2x2 light 2 away: d^2 / (cos * area) = 4 / 4
+    let o = Vec3::new(0.0, 0.0, 0.0);
+    assert!((light.pdf_value(&o, &Vec3::new(0.0, 1.0, 0.0)) - 1.0).abs() < 1e-12);
+    assert_eq!(light.pdf_value(&o, &Vec3::new(0.0, -1.0, 0.0)), 0.0);
+    let v = light.random(&o, (0.25, 0.75));
+    assert_eq!(v, Vec3::new(-0.5, 2.0, 0.5));
+    // over the whole light, the mean of 1 / pdf is its solid angle
+    let n = 200;
+    let mut sum = 0.0;
+    for i in 0..n {
+        for j in 0..n {
+            let sample = ((i as f64 + 0.5) / n as f64, (j as f64 + 0.5) / n as f64);
+            let v = light.random(&o, sample);
+            sum += 1.0 / light.pdf_value(&o, &v);
+        }
+    }
+    let solid_angle = 4.0 * (1.0 / (2.0 * 6.0f64.sqrt())).atan();
+    assert!((sum / (n * n) as f64 - solid_angle).abs() < 1e-3);
+}
@@ -0,0 +1,2154 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::ops::Range;
+use std::path::Path;
+use std::sync::Arc;
+
+use rand::{SeedableRng, StdRng};
+use serde::de::{self, IgnoredAny, MapAccess, SeqAccess, Visitor};
+use serde::{Deserialize, Deserializer};
+use toml;
+use toml::Spanned;
+
+use aperture::{Aperture, ApertureMask, ApertureShape};
+use background::Background;
+use camera::{Camera, Projection, Stereo};
+use cuboid::Cuboid;
+use environment::EnvironmentMap;
+use flip_normals::FlipNormals;
+use hitable::{HitRecord, Hitable};
+use hitable_list::HitableList;
+use ies::IesProfile;
+use instance::Instance;
+use material::Material;
+use medium::{ConstantMedium, PhaseFunction};
+use mesh::TriangleMesh;
+use microfacet::{ConductorPreset, Ggx};
+use moving_sphere::MovingSphere;
+use principled::Principled;
+use punctual::PunctualLight;
+use ray::Ray;
+use rect::AaRect;
+use sky::Sky;
+use spectrum::{Dispersion, LAMBDA_MAX, LAMBDA_MIN};
+use sphere::Sphere;
+use texture::{solid, CheckerTexture, ImageTexture, NoiseKind, NoiseTexture, Texture, WrapMode};
+use vec3::{cross, dot, unit_vector, Mat4, Vec3};
+
+#[derive(Clone, Copy, Debug)]
+pub struct ImageSettings {
+    pub width: usize,
+    pub height: usize,
+    pub samples: usize,
+    pub max_depth: i32,
+    pub spectral: bool, // trace wavelengths rather than RGB
+}
+
+impl Default for ImageSettings {
+    fn default() -> ImageSettings {
+        ImageSettings {
+            width: 1440,
+            height: 720,
+            samples: 32,
+            max_depth: 50,
+            spectral: false,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct CameraSettings {
+    pub lookfrom: Vec3,
+    pub lookat: Vec3,
+    pub vup: Vec3,
+    pub vfov: f64,
+    pub aperture: f64,
+    pub lens: Aperture, // the opening's shape, cat-eye & squeeze
+    pub focus_dist: f64,
+    // focus on this point, or on whatever is in the middle of the image,
+    // rather than at focus_dist
+    pub focus_point: Option<Vec3>,
+    pub autofocus: bool,
+    pub shutter_open: f64, // equal for a still image
+    pub shutter_close: f64,
+    pub projection: Projection,
+    pub stereo: Option<Stereo>, // both eyes in one image
+}
+
+impl Default for CameraSettings {
+    fn default() -> CameraSettings {
+        CameraSettings {
+            lookfrom: Vec3::new(13., 2., 3.),
+            lookat: Vec3::new(0., 0., 0.),
+            vup: Vec3::new(0., 1., 0.),
+            vfov: 30.0,
+            aperture: 0.1,
+            lens: Aperture::default(),
+            focus_dist: 10.0, // focus_point = lookat for (lookfrom - lookat).length()
+            focus_point: None,
+            autofocus: false,
+            shutter_open: 0.0,
+            shutter_close: 0.0,
+            projection: Projection::Perspective,
+            stereo: None,
+        }
+    }
+}
+
+// Fog fills a sphere around the camera, so light from the background
+// can still get into it.  Things further away than `distance` are
+// outside the fog.
+#[derive(Clone, Debug)]
+pub struct FogSettings {
+    pub density: f64,
+    pub distance: Option<f64>, // None for twice the lookfrom to lookat distance
+    pub albedo: Arc<dyn Texture>,
+    pub phase: PhaseFunction,
+}
+
+// Everything needed to render: the objects plus image & camera settings.
+pub struct Scene {
+    pub world: HitableList,
+    pub media: Vec<ConstantMedium>, // smoke, mist...
+    pub punctual_lights: Vec<PunctualLight>,
+    pub fog: Option<FogSettings>,
+    pub image: ImageSettings,
+    pub camera: CameraSettings,
+    pub background: Background,
+    // what lights the scene from outside, if not the background
+    pub environment: Option<Background>,
+}
+
+impl Scene {
+    pub fn new(world: HitableList) -> Scene {
+        Scene {
+            world,
+            media: Vec::new(),
+            punctual_lights: Vec::new(),
+            fog: None,
+            image: ImageSettings::default(),
+            camera: CameraSettings::default(),
+            background: Background::default(),
+            environment: None,
+        }
+    }
+    pub fn camera(&self) -> Camera {
+        let aspect = self.image.width as f64 / self.image.height as f64;
+        let camera = Camera::new(
+            self.camera.lookfrom,
+            self.camera.lookat,
+            self.camera.vup,
+            self.camera.vfov,
+            self.camera
+                .stereo
+                .map_or(aspect, |stereo| stereo.eye_aspect(aspect)),
+            self.camera.aperture,
+            self.focus_distance(),
+        )
+        .with_shutter(self.camera.shutter_open, self.camera.shutter_close)
+        .with_projection(self.camera.projection)
+        .with_aperture(self.camera.lens.clone());
+        match self.camera.stereo {
+            Some(stereo) => camera.with_stereo(stereo),
+            None => camera,
+        }
+    }
+    // How far along the view the focus point is, or what is in the middle
+    // of the image (as the shutter opens) for autofocus.  Otherwise, or
+    // if the point is behind the camera or the middle shows nothing, it
+    // is focus_dist.
+    pub fn focus_distance(&self) -> f64 {
+        let forward = unit_vector(self.camera.lookat - self.camera.lookfrom);
+        if let Some(point) = self.camera.focus_point {
+            let distance = dot(&(point - self.camera.lookfrom), &forward);
+            if distance > 0.0 {
+                return distance;
+            }
+        } else if self.camera.autofocus {
+            let r = Ray::with_time(self.camera.lookfrom, forward, self.camera.shutter_open);
+            let mut rec = HitRecord::new();
+            if self.world.hit(&r, 0.001, f64::MAX, &mut rec) {
+                return rec.t;
+            }
+        }
+        self.camera.focus_dist
+    }
+    pub fn fog_medium(&self) -> Option<ConstantMedium> {
+        self.fog.as_ref().map(|fog| {
+            let distance = fog
+                .distance
+                .unwrap_or(2.0 * (self.camera.lookfrom - self.camera.lookat).length());
+            let unused = Material::Dielectric { ref_idx: 1.0 };
+            let boundary = Sphere::new(self.camera.lookfrom, distance, unused);
+            ConstantMedium::new(
+                Box::new(boundary),
+                fog.density,
+                fog.albedo.clone(),
+                fog.phase,
+            )
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct SceneError {
+    line: Option<usize>,
+    message: String,
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "line {}: {}", line, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+impl Error for SceneError {}
+
+// ======================================================================
+// The file format.  See scenes/*.toml for examples.
+// ======================================================================
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SceneDef {
+    image: Option<Spanned<ImageDef>>,
+    camera: Option<Spanned<CameraDef>>,
+    background: Option<Spanned<BackgroundDef>>,
+    environment: Option<Spanned<BackgroundDef>>,
+    fog: Option<Spanned<FogDef>>,
+    #[serde(default)]
+    textures: HashMap<String, Spanned<TextureDef>>,
+    #[serde(default)]
+    materials: HashMap<String, Spanned<MaterialDef>>,
+    #[serde(default)]
+    shapes: HashMap<String, Spanned<ObjectDef>>,
+    #[serde(default)]
+    objects: Vec<Spanned<ObjectDef>>,
+    #[serde(default)]
+    lights: Vec<Spanned<LightDef>>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ImageDef {
+    width: Option<usize>,
+    height: Option<usize>,
+    samples: Option<usize>,
+    max_depth: Option<i32>,
+    spectral: Option<bool>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CameraDef {
+    lookfrom: Option<[f64; 3]>,
+    lookat: Option<[f64; 3]>,
+    vup: Option<[f64; 3]>,
+    vfov: Option<f64>,
+    aperture: Option<f64>,
+    focus_dist: Option<f64>,
+    shutter_open: Option<f64>,
+    shutter_close: Option<f64>,
+    // perspective, orthographic, equirectangular, fisheye or cubemap
+    projection: Option<String>,
+    view_height: Option<f64>, // orthographic
+    fisheye_fov: Option<f64>, // degrees, 180 by default
+    // top_bottom or side_by_side, for a pair of eyes
+    stereo: Option<String>,
+    interocular: Option<f64>,
+    convergence: Option<f64>,
+    // a polygon with this many blades, or an image of the opening;
+    // round if neither
+    aperture_blades: Option<usize>,
+    aperture_rotation: Option<f64>, // degrees
+    aperture_mask: Option<String>,
+    cat_eye: Option<f64>,            // 0 to 1
+    anamorphic_squeeze: Option<f64>, // 1 for none
+    // instead of focus_dist
+    focus_point: Option<[f64; 3]>,
+    autofocus: Option<bool>,
+}
+
+// albedo defaults to white, g (Henyey-Greenstein) to 0 for isotropic
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FogDef {
+    density: f64,
+    distance: Option<f64>,
+    albedo: Option<ColorDef>,
+    g: Option<f64>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum BackgroundDef {
+    Gradient,
+    Black,
+    Constant {
+        color: [f64; 3],
+    },
+    // an equirectangular .hdr or .exr (or PNG/JPEG) image, turned by
+    // rotation degrees about y
+    Image {
+        file: String,
+        rotation: Option<f64>,
+        intensity: Option<f64>,
+    },
+    // daylight; the sun's elevation & azimuth are in degrees
+    Sky {
+        elevation: f64,
+        azimuth: Option<f64>,
+        turbidity: Option<f64>,
+        intensity: Option<f64>,
+        sun: Option<bool>,
+    },
+}
+
+// color defaults to white & intensity to 1; angles are in degrees.
+// Point & spot lights can be shaped by an IES file, pointing down (or
+// along the spot's direction) & turned by rotation.
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum LightDef {
+    Point {
+        position: [f64; 3],
+        color: Option<[f64; 3]>,
+        intensity: Option<f64>,
+        ies: Option<String>,
+        direction: Option<[f64; 3]>,
+        rotation: Option<f64>,
+    },
+    Spot {
+        position: [f64; 3],
+        direction: [f64; 3],
+        inner_angle: Option<f64>,
+        outer_angle: f64,
+        color: Option<[f64; 3]>,
+        intensity: Option<f64>,
+        ies: Option<String>,
+        rotation: Option<f64>,
+    },
+    Directional {
+        direction: [f64; 3],
+        color: Option<[f64; 3]>,
+        intensity: Option<f64>,
+    },
+}
+
+// a plain colour or the name of a texture
+#[derive(Clone, Deserialize)]
+#[serde(untagged, expecting = "an [r, g, b] colour or a texture name")]
+enum ColorDef {
+    Rgb([f64; 3]),
+    Texture(String),
+}
+
+// a number, or the name of a texture whose channels are averaged
+#[derive(Deserialize)]
+#[serde(untagged, expecting = "a number or a texture name")]
+enum ScalarDef {
+    Value(f64),
+    Texture(String),
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum TextureDef {
+    Constant {
+        color: [f64; 3],
+    },
+    Checker {
+        odd: ColorDef,
+        even: ColorDef,
+        scale: Option<f64>,
+    },
+    Image {
+        file: String,
+        wrap: Option<String>,
+    },
+    // Perlin noise; textures with the same seed share a noise pattern
+    Noise {
+        scale: Option<f64>,
+        color: Option<[f64; 3]>,
+        seed: Option<usize>,
+    },
+    Turbulence {
+        scale: Option<f64>,
+        color: Option<[f64; 3]>,
+        seed: Option<usize>,
+    },
+    Marble {
+        scale: Option<f64>,
+        color: Option<[f64; 3]>,
+        seed: Option<usize>,
+    },
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum MaterialDef {
+    Lambertian {
+        albedo: ColorDef,
+    },
+    Metal {
+        albedo: ColorDef,
+        fuzz: f64,
+    },
+    // give one of ref_idx, cauchy or sellmeier; the last two disperse
+    // light into colours in spectral mode
+    Dielectric {
+        ref_idx: Option<f64>,
+        cauchy: Option<[f64; 2]>,
+        sellmeier: Option<SellmeierDef>,
+    },
+    DiffuseLight {
+        emit: [f64; 3],
+        strength: Option<f64>,
+    },
+    // give either a preset (gold, copper, aluminium or silver) or eta & k
+    Conductor {
+        preset: Option<String>,
+        eta: Option<[f64; 3]>,
+        k: Option<[f64; 3]>,
+        roughness: Option<RoughnessDef>,
+    },
+    RoughDielectric {
+        ref_idx: Option<f64>,
+        cauchy: Option<[f64; 2]>,
+        sellmeier: Option<SellmeierDef>,
+        roughness: Option<RoughnessDef>,
+    },
+    // Disney-style; the parameters other than ior are 0 to 1
+    Principled {
+        base_color: Option<ColorDef>,
+        metallic: Option<ScalarDef>,
+        roughness: Option<ScalarDef>,
+        specular: Option<ScalarDef>,
+        specular_tint: Option<ScalarDef>,
+        sheen: Option<ScalarDef>,
+        sheen_tint: Option<ScalarDef>,
+        clearcoat: Option<ScalarDef>,
+        clearcoat_gloss: Option<ScalarDef>,
+        transmission: Option<ScalarDef>,
+        ior: Option<f64>,
+    },
+}
+
+// coefficients for wavelengths in µm, as glass makers list them
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SellmeierDef {
+    b: [f64; 3],
+    c: [f64; 3],
+}
+
+enum RefractiveIndex {
+    Constant(f64),
+    Dispersive(Dispersion),
+}
+
+fn refractive_index(
+    ref_idx: Option<f64>,
+    cauchy: Option<[f64; 2]>,
+    sellmeier: Option<SellmeierDef>,
+) -> Result<RefractiveIndex, String> {
+    let dispersion = match (ref_idx, cauchy, sellmeier) {
+        (Some(ref_idx), None, None) => {
+            if ref_idx <= 0.0 {
+                return Err("ref_idx must be greater than 0".to_string());
+            }
+            return Ok(RefractiveIndex::Constant(ref_idx));
+        }
+        (None, Some([a, b]), None) => Dispersion::Cauchy { a, b },
+        (None, None, Some(SellmeierDef { b, c })) => Dispersion::Sellmeier { b, c },
+        _ => return Err("give one of ref_idx, cauchy or sellmeier".to_string()),
+    };
+    let mut lambda = LAMBDA_MIN;
+    while lambda <= LAMBDA_MAX {
+        let n = dispersion.ior(lambda);
+        if !n.is_finite() || n <= 0.0 {
+            return Err(format!(
+                "the index of refraction is not a positive number at {}nm",
+                lambda
+            ));
+        }
+        lambda += 10.0;
+    }
+    Ok(RefractiveIndex::Dispersive(dispersion))
+}
+
+// 0 is smooth & 1 very rough.  Two values are anisotropic: rougher
+// along one direction across the surface than the other.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RoughnessDef {
+    Isotropic(f64),
+    Anisotropic([f64; 2]),
+}
+
+fn distribution(roughness: Option<RoughnessDef>) -> Result<Ggx, String> {
+    let (x, y) = match roughness {
+        None => (0.0, 0.0),
+        Some(RoughnessDef::Isotropic(r)) => (r, r),
+        Some(RoughnessDef::Anisotropic([x, y])) => (x, y),
+    };
+    if !(0.0..=1.0).contains(&x) || !(0.0..=1.0).contains(&y) {
+        return Err("roughness must be between 0 and 1".to_string());
+    }
+    Ok(Ggx::new(x, y))
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum ObjectDef {
+    Sphere {
+        center: [f64; 3],
+        radius: f64,
+        material: String,
+    },
+    // give either center0 & center1 (at time0 & time1) or keyframes
+    MovingSphere {
+        center0: Option<[f64; 3]>,
+        center1: Option<[f64; 3]>,
+        time0: Option<f64>,
+        time1: Option<f64>,
+        keyframes: Option<Vec<KeyframeDef>>,
+        radius: f64,
+        material: String,
+    },
+    ConstantMedium {
+        boundary: BoundaryDef,
+        density: f64,
+        albedo: Option<ColorDef>,
+        g: Option<f64>,
+    },
+    Mesh {
+        file: String,
+        material: String, // for faces before any usemtl
+        #[serde(default)]
+        materials: HashMap<String, String>, // usemtl name -> material name
+    },
+    // rectangles face +z, +y & +x respectively unless flipped
+    XyRect {
+        x0: f64,
+        x1: f64,
+        y0: f64,
+        y1: f64,
+        k: f64,
+        material: String,
+        #[serde(default)]
+        flip: bool,
+    },
+    XzRect {
+        x0: f64,
+        x1: f64,
+        z0: f64,
+        z1: f64,
+        k: f64,
+        material: String,
+        #[serde(default)]
+        flip: bool,
+    },
+    YzRect {
+        y0: f64,
+        y1: f64,
+        z0: f64,
+        z1: f64,
+        k: f64,
+        material: String,
+        #[serde(default)]
+        flip: bool,
+    },
+    Box {
+        min: [f64; 3],
+        max: [f64; 3],
+        material: String,
+    },
+    // a shape from the [shapes] table, transformed by each step in turn
+    Instance {
+        shape: String,
+        #[serde(default)]
+        transform: Vec<TransformDef>,
+    },
+}
+
+// e.g. { rotate = { axis = [0, 1, 0], angle = 15 } }
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+enum TransformDef {
+    Translate([f64; 3]),
+    Scale(ScaleDef),
+    Rotate {
+        axis: [f64; 3],
+        angle: f64, // degrees
+    },
+    // points the shape's -z axis from `from` at `to`
+    LookAt {
+        from: [f64; 3],
+        to: [f64; 3],
+        vup: Option<[f64; 3]>,
+    },
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ScaleDef {
+    Uniform(f64),
+    PerAxis([f64; 3]),
+}
+
+// the shape of a constant_medium: a sphere or box, or any surface from
+// the [shapes] table, such as a mesh, placed like an instance
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum BoundaryDef {
+    Sphere {
+        center: [f64; 3],
+        radius: f64,
+    },
+    Box {
+        min: [f64; 3],
+        max: [f64; 3],
+    },
+    Instance {
+        shape: String,
+        #[serde(default)]
+        transform: Vec<TransformDef>,
+    },
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct KeyframeDef {
+    time: f64,
+    center: [f64; 3],
+}
+
+fn phase_function(g: Option<f64>) -> Result<PhaseFunction, String> {
+    match g {
+        None => Ok(PhaseFunction::Isotropic),
+        Some(g) if g > -1.0 && g < 1.0 => Ok(PhaseFunction::HenyeyGreenstein(g)),
+        Some(_) => Err("g must be between -1 and 1".to_string()),
+    }
+}
+
+fn rect_object(rect: AaRect, flip: bool) -> Box<dyn Hitable> {
+    if flip {
+        Box::new(FlipNormals::new(rect))
+    } else {
+        Box::new(rect)
+    }
+}
+
+// a step of an instance's transform
+fn transform_matrix(def: TransformDef) -> Result<Mat4, String> {
+    match def {
+        TransformDef::Translate(offset) => Ok(Mat4::translate(vec3(offset))),
+        TransformDef::Scale(ScaleDef::Uniform(s)) => Ok(Mat4::scale(Vec3::new(s, s, s))),
+        TransformDef::Scale(ScaleDef::PerAxis(s)) => Ok(Mat4::scale(vec3(s))),
+        TransformDef::Rotate { axis, angle } => {
+            if vec3(axis).length() == 0.0 {
+                return Err("rotate axis must not be [0, 0, 0]".to_string());
+            }
+            Ok(Mat4::rotate(vec3(axis), angle))
+        }
+        TransformDef::LookAt { from, to, vup } => {
+            let vup = vup.map(vec3).unwrap_or_else(|| Vec3::new(0.0, 1.0, 0.0));
+            let w = vec3(from) - vec3(to);
+            if w.length() == 0.0 || cross(&vup, &w).length() == 0.0 {
+                return Err("look_at from and to must differ and not line up with vup".to_string());
+            }
+            Ok(Mat4::look_at(vec3(from), vec3(to), vup))
+        }
+    }
+}
+
+fn vec3(a: [f64; 3]) -> Vec3 {
+    Vec3::new(a[0], a[1], a[2])
+}
+
+// Builds the named textures, following checkers to the textures they
+// are made of.
+struct Textures<'a> {
+    src: &'a Source<'a>,
+    base_dir: &'a Path,
+    defs: HashMap<String, Spanned<TextureDef>>,
+    built: HashMap<String, Arc<dyn Texture>>,
+    pending: Vec<String>, // being built, to catch cycles
+}
+
+impl<'a> Textures<'a> {
+    fn color(&mut self, c: ColorDef, span: Range<usize>) -> Result<Arc<dyn Texture>, SceneError> {
+        match c {
+            ColorDef::Rgb(rgb) => Ok(solid(vec3(rgb))),
+            ColorDef::Texture(name) => self.get(&name, span),
+        }
+    }
+
+    // param names the value in errors
+    fn scalar(
+        &mut self,
+        s: ScalarDef,
+        param: &str,
+        span: Range<usize>,
+    ) -> Result<Arc<dyn Texture>, SceneError> {
+        match s {
+            ScalarDef::Value(x) if (0.0..=1.0).contains(&x) => Ok(solid(Vec3::new(x, x, x))),
+            ScalarDef::Value(_) => Err(self
+                .src
+                .error(span, format!("{} must be between 0 and 1", param))),
+            ScalarDef::Texture(name) => self.get(&name, span),
+        }
+    }
+
+    // span is where the texture is used, for unknown names
+    fn get(&mut self, name: &str, span: Range<usize>) -> Result<Arc<dyn Texture>, SceneError> {
+        if let Some(t) = self.built.get(name) {
+            return Ok(t.clone());
+        }
+        let (def_span, def) = match self.defs.get(name) {
+            Some(d) => (d.span(), d.get_ref().clone()),
+            None => return Err(self.src.error(span, format!("unknown texture '{}'", name))),
+        };
+        if self.pending.iter().any(|p| p == name) {
+            return Err(self
+                .src
+                .error(def_span, format!("texture '{}' refers to itself", name)));
+        }
+        self.pending.push(name.to_string());
+        let texture = self.build(name, def, def_span)?;
+        self.pending.pop();
+        self.built.insert(name.to_string(), texture.clone());
+        Ok(texture)
+    }
+
+    fn build(
+        &mut self,
+        name: &str,
+        def: TextureDef,
+        span: Range<usize>,
+    ) -> Result<Arc<dyn Texture>, SceneError> {
+        let noise = |kind, scale: Option<f64>, color: Option<[f64; 3]>, seed: Option<usize>| {
+            let seed: &[_] = &[seed.unwrap_or(0)];
+            let mut rng: StdRng = SeedableRng::from_seed(seed);
+            let color = color.map(vec3).unwrap_or_else(|| Vec3::new(1.0, 1.0, 1.0));
+            Arc::new(NoiseTexture::new(
+                kind,
+                scale.unwrap_or(1.0),
+                color,
+                &mut rng,
+            ))
+        };
+        let texture: Arc<dyn Texture> = match def {
+            TextureDef::Constant { color } => solid(vec3(color)),
+            TextureDef::Checker { odd, even, scale } => {
+                let odd = self.color(odd, span.clone())?;
+                let even = self.color(even, span)?;
+                Arc::new(CheckerTexture::new(odd, even, scale.unwrap_or(10.0)))
+            }
+            TextureDef::Image { file, wrap } => {
+                let wrap = match wrap {
+                    Some(w) => w
+                        .parse::<WrapMode>()
+                        .map_err(|e| self.src.error(span.clone(), e))?,
+                    None => WrapMode::default(),
+                };
+                let image = ImageTexture::load(self.base_dir.join(&file), wrap).map_err(|e| {
+                    self.src
+                        .error(span, format!("texture '{}': {}: {}", name, file, e))
+                })?;
+                Arc::new(image)
+            }
+            TextureDef::Noise { scale, color, seed } => noise(NoiseKind::Noise, scale, color, seed),
+            TextureDef::Turbulence { scale, color, seed } => {
+                noise(NoiseKind::Turbulence, scale, color, seed)
+            }
+            TextureDef::Marble { scale, color, seed } => {
+                noise(NoiseKind::Marble, scale, color, seed)
+            }
+        };
+        Ok(texture)
+    }
+}
+
+// What an object in the file becomes.
+enum Built {
+    Surface(Box<dyn Hitable>),
+    Medium(ConstantMedium),
+}
+
+// A [shapes] entry, for instances to place.
+enum Shape {
+    Surface(Arc<dyn Hitable>),
+    Medium(ConstantMedium),
+}
+
+// Builds objects from their definitions, with the named materials,
+// textures and shapes they use.
+struct Objects<'a> {
+    textures: Textures<'a>,
+    materials: HashMap<String, Material>,
+    shapes: HashMap<String, Shape>,
+}
+
+impl<'a> Objects<'a> {
+    fn material(&self, name: &str, span: Range<usize>) -> Result<Material, SceneError> {
+        match self.materials.get(name) {
+            Some(m) => Ok(m.clone()),
+            None => Err(self
+                .textures
+                .src
+                .error(span, format!("unknown material '{}'", name))),
+        }
+    }
+
+    fn shape(&self, name: &str, span: Range<usize>) -> Result<&Shape, SceneError> {
+        match self.shapes.get(name) {
+            Some(s) => Ok(s),
+            None => Err(self
+                .textures
+                .src
+                .error(span, format!("unknown shape '{}'", name))),
+        }
+    }
+
+    // an instance's steps, one after the other
+    fn transform(
+        &self,
+        transform: Vec<TransformDef>,
+        span: Range<usize>,
+    ) -> Result<Mat4, SceneError> {
+        let src = self.textures.src;
+        let mut matrix = Mat4::identity();
+        for step in transform {
+            let step = transform_matrix(step)
+                .map_err(|e| src.error(span.clone(), format!("instance {}", e)))?;
+            matrix = matrix.then(&step);
+        }
+        if !matrix.is_finite() {
+            return Err(src.error(span, "instance transform is too large".to_string()));
+        }
+        if matrix.inverse().is_none() {
+            return Err(src.error(
+                span,
+                "instance transform flattens the shape to nothing".to_string(),
+            ));
+        }
+        Ok(matrix)
+    }
+
+    fn build(&mut self, def: ObjectDef, span: Range<usize>) -> Result<Built, SceneError> {
+        let (src, base_dir) = (self.textures.src, self.textures.base_dir);
+        let built = match def {
+            ObjectDef::Sphere {
+                center,
+                radius,
+                material,
+            } => {
+                if radius == 0.0 {
+                    return Err(src.error(span, "sphere radius must not be 0".to_string()));
+                }
+                let material = self.material(&material, span)?;
+                Built::Surface(Box::new(Sphere::new(vec3(center), radius, material)))
+            }
+            ObjectDef::MovingSphere {
+                center0,
+                center1,
+                time0,
+                time1,
+                keyframes,
+                radius,
+                material,
+            } => {
+                if radius == 0.0 {
+                    return Err(src.error(span, "sphere radius must not be 0".to_string()));
+                }
+                let material = self.material(&material, span.clone())?;
+                let sphere = match (center0, center1, keyframes) {
+                    (Some(c0), Some(c1), None) => {
+                        let (t0, t1) = (time0.unwrap_or(0.0), time1.unwrap_or(1.0));
+                        if t1 <= t0 {
+                            return Err(src.error(
+                                span,
+                                "moving_sphere time1 must be after time0".to_string(),
+                            ));
+                        }
+                        MovingSphere::new(vec3(c0), vec3(c1), t0, t1, radius, material)
+                    }
+                    (None, None, Some(keys)) if !keys.is_empty() => {
+                        if time0.is_some() || time1.is_some() {
+                            return Err(src.error(
+                                span,
+                                "moving_sphere keyframes have their own times".to_string(),
+                            ));
+                        }
+                        let keys = keys.iter().map(|k| (k.time, vec3(k.center))).collect();
+                        MovingSphere::with_keyframes(keys, radius, material)
+                    }
+                    _ => {
+                        return Err(src.error(
+                            span,
+                            "moving_sphere needs center0 & center1, or a list of keyframes"
+                                .to_string(),
+                        ))
+                    }
+                };
+                Built::Surface(Box::new(sphere))
+            }
+            ObjectDef::ConstantMedium {
+                boundary,
+                density,
+                albedo,
+                g,
+            } => {
+                if density <= 0.0 {
+                    return Err(src.error(
+                        span,
+                        "constant_medium density must be greater than 0".to_string(),
+                    ));
+                }
+                let phase = phase_function(g)
+                    .map_err(|e| src.error(span.clone(), format!("constant_medium {}", e)))?;
+                let albedo = match albedo {
+                    Some(c) => self.textures.color(c, span.clone())?,
+                    None => solid(Vec3::new(1.0, 1.0, 1.0)),
+                };
+                // only the boundary's shape matters, not its material
+                let unused = Material::Dielectric { ref_idx: 1.0 };
+                let medium = match boundary {
+                    BoundaryDef::Sphere { center, radius } => {
+                        if radius <= 0.0 {
+                            return Err(src.error(
+                                span,
+                                "constant_medium sphere radius must be greater than 0".to_string(),
+                            ));
+                        }
+                        let sphere = Sphere::new(vec3(center), radius, unused);
+                        ConstantMedium::new(Box::new(sphere), density, albedo, phase)
+                    }
+                    BoundaryDef::Box { min, max } => {
+                        if (0..3).any(|a| min[a] >= max[a]) {
+                            return Err(
+                                src.error(span, "box min must be less than max".to_string())
+                            );
+                        }
+                        let cuboid = Cuboid::new(vec3(min), vec3(max), unused);
+                        ConstantMedium::new(Box::new(cuboid), density, albedo, phase)
+                    }
+                    BoundaryDef::Instance { shape, transform } => {
+                        let matrix = self.transform(transform, span.clone())?;
+                        let object = match *self.shape(&shape, span.clone())? {
+                            Shape::Surface(ref s) => s.clone(),
+                            Shape::Medium(_) => {
+                                return Err(src.error(
+                                    span,
+                                    format!(
+                                        "constant_medium boundary: shape '{}' is a constant_medium",
+                                        shape
+                                    ),
+                                ))
+                            }
+                        };
+                        let instance = Instance::new(object, matrix);
+                        ConstantMedium::new(Box::new(instance), density, albedo, phase)
+                    }
+                };
+                Built::Medium(medium)
+            }
+            ObjectDef::Mesh {
+                file,
+                material,
+                materials: mesh_materials,
+            } => {
+                let default_material = self.material(&material, span.clone())?;
+                let mut usemtl = HashMap::new();
+                for (obj_name, scene_name) in mesh_materials {
+                    usemtl.insert(obj_name, self.material(&scene_name, span.clone())?);
+                }
+                let mesh = TriangleMesh::load_obj(base_dir.join(&file), &usemtl, default_material)
+                    .map_err(|e| src.error(span, format!("mesh '{}': {}", file, e)))?;
+                Built::Surface(Box::new(mesh))
+            }
+            ObjectDef::XyRect {
+                x0,
+                x1,
+                y0,
+                y1,
+                k,
+                material,
+                flip,
+            } => {
+                if x0 >= x1 || y0 >= y1 {
+                    return Err(src.error(span, "xy_rect needs x0 < x1 and y0 < y1".to_string()));
+                }
+                let rect = AaRect::xy(x0, x1, y0, y1, k, self.material(&material, span)?);
+                Built::Surface(rect_object(rect, flip))
+            }
+            ObjectDef::XzRect {
+                x0,
+                x1,
+                z0,
+                z1,
+                k,
+                material,
+                flip,
+            } => {
+                if x0 >= x1 || z0 >= z1 {
+                    return Err(src.error(span, "xz_rect needs x0 < x1 and z0 < z1".to_string()));
+                }
+                let rect = AaRect::xz(x0, x1, z0, z1, k, self.material(&material, span)?);
+                Built::Surface(rect_object(rect, flip))
+            }
+            ObjectDef::YzRect {
+                y0,
+                y1,
+                z0,
+                z1,
+                k,
+                material,
+                flip,
+            } => {
+                if y0 >= y1 || z0 >= z1 {
+                    return Err(src.error(span, "yz_rect needs y0 < y1 and z0 < z1".to_string()));
+                }
+                let rect = AaRect::yz(y0, y1, z0, z1, k, self.material(&material, span)?);
+                Built::Surface(rect_object(rect, flip))
+            }
+            ObjectDef::Box { min, max, material } => {
+                if (0..3).any(|a| min[a] >= max[a]) {
+                    return Err(src.error(span, "box min must be less than max".to_string()));
+                }
+                let material = self.material(&material, span)?;
+                Built::Surface(Box::new(Cuboid::new(vec3(min), vec3(max), material)))
+            }
+            ObjectDef::Instance { shape, transform } => {
+                let matrix = self.transform(transform, span.clone())?;
+                match *self.shape(&shape, span)? {
+                    Shape::Surface(ref s) => {
+                        Built::Surface(Box::new(Instance::new(s.clone(), matrix)))
+                    }
+                    Shape::Medium(ref m) => Built::Medium(m.transformed(matrix)),
+                }
+            }
+        };
+        Ok(built)
+    }
+}
+
+// Converts byte offsets in the source into line numbers for errors.
+struct Source<'a> {
+    text: &'a str,
+}
+
+impl<'a> Source<'a> {
+    fn error(&self, span: Range<usize>, message: String) -> SceneError {
+        let start = span.start.min(self.text.len());
+        SceneError {
+            line: Some(self.text[..start].matches('\n').count() + 1),
+            message,
+        }
+    }
+}
+
+// Reads a whole scene file only to check that every number in it is
+// finite.  TOML allows inf & nan, which nothing in a scene can use, and
+// rejecting them here, with the line they are on, saves checking each
+// value on its own.
+struct FiniteNumbers;
+
+impl<'de> Deserialize<'de> for FiniteNumbers {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<FiniteNumbers, D::Error> {
+        deserializer.deserialize_any(FiniteNumbers)
+    }
+}
+
+impl<'de> Visitor<'de> for FiniteNumbers {
+    type Value = FiniteNumbers;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "any value")
+    }
+    fn visit_bool<E: de::Error>(self, _: bool) -> Result<FiniteNumbers, E> {
+        Ok(FiniteNumbers)
+    }
+    fn visit_i64<E: de::Error>(self, _: i64) -> Result<FiniteNumbers, E> {
+        Ok(FiniteNumbers)
+    }
+    fn visit_u64<E: de::Error>(self, _: u64) -> Result<FiniteNumbers, E> {
+        Ok(FiniteNumbers)
+    }
+    fn visit_f64<E: de::Error>(self, v: f64) -> Result<FiniteNumbers, E> {
+        if v.is_finite() {
+            Ok(FiniteNumbers)
+        } else {
+            Err(E::custom(format!("expected a finite number, found {}", v)))
+        }
+    }
+    fn visit_str<E: de::Error>(self, _: &str) -> Result<FiniteNumbers, E> {
+        Ok(FiniteNumbers)
+    }
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<FiniteNumbers, A::Error> {
+        while seq.next_element::<FiniteNumbers>()?.is_some() {}
+        Ok(FiniteNumbers)
+    }
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<FiniteNumbers, A::Error> {
+        while map.next_entry::<IgnoredAny, FiniteNumbers>()?.is_some() {}
+        Ok(FiniteNumbers)
+    }
+}
+
+pub fn load_scene<P: AsRef<Path>>(path: P) -> Result<Scene, SceneError> {
+    let path = path.as_ref();
+    let text = fs::read_to_string(path).map_err(|e| SceneError {
+        line: None,
+        message: format!("cannot read {}: {}", path.display(), e),
+    })?;
+    // mesh files are relative to the scene file
+    let base_dir = path.parent().unwrap_or_else(|| Path::new(""));
+    parse_scene(&text, base_dir)
+}
+
+pub fn parse_scene(text: &str, base_dir: &Path) -> Result<Scene, SceneError> {
+    let src = Source { text };
+    let toml_error = |e: toml::de::Error| match e.span() {
+        Some(span) => src.error(span, e.message().to_string()),
+        None => SceneError {
+            line: None,
+            message: e.message().to_string(),
+        },
+    };
+    toml::from_str::<FiniteNumbers>(text).map_err(toml_error)?;
+    let def: SceneDef = toml::from_str(text).map_err(toml_error)?;
+
+    let mut image = ImageSettings::default();
+    if let Some(image_def) = def.image {
+        let span = image_def.span();
+        let d = image_def.into_inner();
+        image.width = d.width.unwrap_or(image.width);
+        image.height = d.height.unwrap_or(image.height);
+        image.samples = d.samples.unwrap_or(image.samples);
+        image.max_depth = d.max_depth.unwrap_or(image.max_depth);
+        image.spectral = d.spectral.unwrap_or(image.spectral);
+        if image.width == 0 || image.height == 0 || image.samples == 0 {
+            return Err(src.error(
+                span,
+                "image width, height and samples must be greater than 0".to_string(),
+            ));
+        }
+        if image.max_depth < 0 {
+            return Err(src.error(span, "image max_depth must not be negative".to_string()));
+        }
+    }
+
+    let mut camera = CameraSettings::default();
+    if let Some(camera_def) = def.camera {
+        let span = camera_def.span();
+        let d = camera_def.into_inner();
+        camera.lookfrom = d.lookfrom.map(vec3).unwrap_or(camera.lookfrom);
+        camera.lookat = d.lookat.map(vec3).unwrap_or(camera.lookat);
+        camera.vup = d.vup.map(vec3).unwrap_or(camera.vup);
+        camera.vfov = d.vfov.unwrap_or(camera.vfov);
+        camera.aperture = d.aperture.unwrap_or(camera.aperture);
+        camera.focus_dist = d.focus_dist.unwrap_or(camera.focus_dist);
+        camera.shutter_open = d.shutter_open.unwrap_or(camera.shutter_open);
+        camera.shutter_close = d.shutter_close.unwrap_or(camera.shutter_open);
+        if camera.vfov <= 0.0 || camera.vfov >= 180.0 {
+            return Err(src.error(
+                span,
+                "camera vfov must be between 0 and 180 degrees".to_string(),
+            ));
+        }
+        if camera.aperture < 0.0 {
+            return Err(src.error(span, "camera aperture must not be negative".to_string()));
+        }
+        if camera.focus_dist <= 0.0 {
+            return Err(src.error(span, "camera focus_dist must be greater than 0".to_string()));
+        }
+        if camera.shutter_close < camera.shutter_open {
+            return Err(src.error(
+                span,
+                "camera shutter_close must not be before shutter_open".to_string(),
+            ));
+        }
+        if (camera.lookfrom - camera.lookat).length() == 0.0 {
+            return Err(src.error(span, "camera lookfrom and lookat must differ".to_string()));
+        }
+        camera.projection = match d.projection.as_deref().unwrap_or("perspective") {
+            "perspective" => Projection::Perspective,
+            "orthographic" => match d.view_height {
+                Some(height) if height > 0.0 => Projection::Orthographic { height },
+                _ => {
+                    return Err(src.error(
+                        span,
+                        "orthographic camera needs a view_height greater than 0".to_string(),
+                    ))
+                }
+            },
+            "equirectangular" => Projection::Equirectangular,
+            "fisheye" => {
+                let fov = d.fisheye_fov.unwrap_or(180.0);
+                if fov <= 0.0 || fov > 360.0 {
+                    return Err(src.error(
+                        span,
+                        "camera fisheye_fov must be between 0 and 360 degrees".to_string(),
+                    ));
+                }
+                Projection::Fisheye { fov }
+            }
+            "cubemap" => Projection::Cubemap,
+            other => {
+                return Err(src.error(
+                    span,
+                    format!(
+                        "unknown camera projection '{}' (expected perspective, orthographic, equirectangular, fisheye or cubemap)",
+                        other
+                    ),
+                ))
+            }
+        };
+        if let Some(layout) = d.stereo {
+            let mut stereo = Stereo::new(layout.parse().map_err(|e| src.error(span.clone(), e))?);
+            stereo.interocular = d.interocular.unwrap_or(stereo.interocular);
+            stereo.convergence = d.convergence;
+            if stereo.interocular <= 0.0 {
+                return Err(src.error(
+                    span,
+                    "camera interocular must be greater than 0".to_string(),
+                ));
+            }
+            if stereo.convergence.is_some_and(|c| c <= 0.0) {
+                return Err(src.error(
+                    span,
+                    "camera convergence must be greater than 0".to_string(),
+                ));
+            }
+            camera.stereo = Some(stereo);
+        } else if d.interocular.is_some() || d.convergence.is_some() {
+            return Err(src.error(
+                span,
+                "camera interocular and convergence need stereo".to_string(),
+            ));
+        }
+        camera.lens.shape = match (d.aperture_blades, d.aperture_mask) {
+            (Some(_), Some(_)) => {
+                return Err(src.error(
+                    span,
+                    "camera aperture_blades and aperture_mask cannot be used together".to_string(),
+                ))
+            }
+            (Some(blades), None) if blades < 3 => {
+                return Err(src.error(
+                    span,
+                    "camera aperture_blades must be at least 3".to_string(),
+                ))
+            }
+            (Some(blades), None) => ApertureShape::Polygon {
+                blades,
+                rotation: d.aperture_rotation.unwrap_or(0.0),
+            },
+            (None, Some(file)) => {
+                let mask = ApertureMask::load(base_dir.join(&file))
+                    .map_err(|e| src.error(span.clone(), format!("{}: {}", file, e)))?;
+                ApertureShape::Mask(Arc::new(mask))
+            }
+            (None, None) => ApertureShape::Disk,
+        };
+        camera.lens.cat_eye = d.cat_eye.unwrap_or(camera.lens.cat_eye);
+        camera.lens.squeeze = d.anamorphic_squeeze.unwrap_or(camera.lens.squeeze);
+        if !(0.0..=1.0).contains(&camera.lens.cat_eye) {
+            return Err(src.error(span, "camera cat_eye must be between 0 and 1".to_string()));
+        }
+        if camera.lens.squeeze <= 0.0 {
+            return Err(src.error(
+                span,
+                "camera anamorphic_squeeze must be greater than 0".to_string(),
+            ));
+        }
+        camera.focus_point = d.focus_point.map(vec3);
+        camera.autofocus = d.autofocus.unwrap_or(false);
+        let focus_settings = [
+            d.focus_dist.is_some(),
+            d.focus_point.is_some(),
+            camera.autofocus,
+        ];
+        if focus_settings.iter().filter(|&&f| f).count() > 1 {
+            return Err(src.error(
+                span,
+                "camera focus_dist, focus_point and autofocus cannot be used together".to_string(),
+            ));
+        }
+        if let Some(point) = camera.focus_point {
+            if dot(
+                &(point - camera.lookfrom),
+                &(camera.lookat - camera.lookfrom),
+            ) <= 0.0
+            {
+                return Err(src.error(
+                    span,
+                    "camera focus_point must be in front of the camera".to_string(),
+                ));
+            }
+        }
+    }
+
+    let background = match def.background {
+        Some(b) => build_background(b, &src, base_dir)?,
+        None => Background::Gradient,
+    };
+    let environment = match def.environment {
+        Some(e) => Some(build_background(e, &src, base_dir)?),
+        None => None,
+    };
+
+    let mut textures = Textures {
+        src: &src,
+        base_dir,
+        defs: def.textures,
+        built: HashMap::new(),
+        pending: Vec::new(),
+    };
+    // build them all, in a fixed order, so unused ones are still checked
+    let mut texture_names: Vec<String> = textures.defs.keys().cloned().collect();
+    texture_names.sort();
+    for name in texture_names {
+        let span = textures.defs[&name].span();
+        textures.get(&name, span)?;
+    }
+
+    let fog = match def.fog {
+        Some(fog_def) => {
+            let span = fog_def.span();
+            let d = fog_def.into_inner();
+            if d.density <= 0.0 {
+                return Err(src.error(span, "fog density must be greater than 0".to_string()));
+            }
+            if d.distance.is_some_and(|r| r <= 0.0) {
+                return Err(src.error(span, "fog distance must be greater than 0".to_string()));
+            }
+            let phase =
+                phase_function(d.g).map_err(|e| src.error(span.clone(), format!("fog {}", e)))?;
+            let albedo = match d.albedo {
+                Some(c) => textures.color(c, span)?,
+                None => solid(Vec3::new(1.0, 1.0, 1.0)),
+            };
+            Some(FogSettings {
+                density: d.density,
+                distance: d.distance,
+                albedo,
+                phase,
+            })
+        }
+        None => None,
+    };
+
+    let mut materials = HashMap::new();
+    for (name, material_def) in def.materials {
+        let span = material_def.span();
+        let material = match material_def.into_inner() {
+            MaterialDef::Lambertian { albedo } => Material::Lambertian {
+                albedo: textures.color(albedo, span)?,
+            },
+            MaterialDef::Metal { albedo, fuzz } => {
+                if fuzz < 0.0 {
+                    return Err(src.error(
+                        span,
+                        format!("material '{}': fuzz must not be negative", name),
+                    ));
+                }
+                Material::Metal {
+                    albedo: textures.color(albedo, span)?,
+                    fuzz,
+                }
+            }
+            MaterialDef::Dielectric {
+                ref_idx,
+                cauchy,
+                sellmeier,
+            } => match refractive_index(ref_idx, cauchy, sellmeier)
+                .map_err(|e| src.error(span, format!("material '{}': {}", name, e)))?
+            {
+                RefractiveIndex::Constant(ref_idx) => Material::Dielectric { ref_idx },
+                RefractiveIndex::Dispersive(dispersion) => Material::DispersiveDielectric {
+                    dispersion,
+                    distribution: None,
+                },
+            },
+            MaterialDef::DiffuseLight { emit, strength } => {
+                let strength = strength.unwrap_or(1.0);
+                if strength < 0.0 {
+                    return Err(src.error(
+                        span,
+                        format!("material '{}': strength must not be negative", name),
+                    ));
+                }
+                Material::DiffuseLight {
+                    emit: vec3(emit),
+                    strength,
+                }
+            }
+            MaterialDef::Conductor {
+                preset,
+                eta,
+                k,
+                roughness,
+            } => {
+                let err =
+                    |e: String| src.error(span.clone(), format!("material '{}': {}", name, e));
+                let (eta, k) = match (preset, eta, k) {
+                    (Some(p), None, None) => p.parse::<ConductorPreset>().map_err(err)?.ior(),
+                    (None, Some(eta), Some(k)) => {
+                        if eta.iter().chain(k.iter()).any(|&x| x < 0.0) {
+                            return Err(err("eta and k must not be negative".to_string()));
+                        }
+                        (vec3(eta), vec3(k))
+                    }
+                    _ => return Err(err("conductor needs a preset, or eta and k".to_string())),
+                };
+                Material::Conductor {
+                    eta,
+                    k,
+                    distribution: distribution(roughness).map_err(err)?,
+                }
+            }
+            MaterialDef::RoughDielectric {
+                ref_idx,
+                cauchy,
+                sellmeier,
+                roughness,
+            } => {
+                let err =
+                    |e: String| src.error(span.clone(), format!("material '{}': {}", name, e));
+                let distribution = distribution(roughness).map_err(err)?;
+                match refractive_index(ref_idx, cauchy, sellmeier).map_err(err)? {
+                    RefractiveIndex::Constant(ref_idx) => Material::RoughDielectric {
+                        ref_idx,
+                        distribution,
+                    },
+                    RefractiveIndex::Dispersive(dispersion) => Material::DispersiveDielectric {
+                        dispersion,
+                        distribution: Some(distribution),
+                    },
+                }
+            }
+            MaterialDef::Principled {
+                base_color,
+                metallic,
+                roughness,
+                specular,
+                specular_tint,
+                sheen,
+                sheen_tint,
+                clearcoat,
+                clearcoat_gloss,
+                transmission,
+                ior,
+            } => {
+                let base_color = match base_color {
+                    Some(c) => textures.color(c, span.clone())?,
+                    None => solid(Vec3::new(0.8, 0.8, 0.8)),
+                };
+                let mut p = Principled::new(base_color);
+                let params = [
+                    (metallic, &mut p.metallic, "metallic"),
+                    (roughness, &mut p.roughness, "roughness"),
+                    (specular, &mut p.specular, "specular"),
+                    (specular_tint, &mut p.specular_tint, "specular_tint"),
+                    (sheen, &mut p.sheen, "sheen"),
+                    (sheen_tint, &mut p.sheen_tint, "sheen_tint"),
+                    (clearcoat, &mut p.clearcoat, "clearcoat"),
+                    (clearcoat_gloss, &mut p.clearcoat_gloss, "clearcoat_gloss"),
+                    (transmission, &mut p.transmission, "transmission"),
+                ];
+                for (def, texture, param) in params {
+                    if let Some(d) = def {
+                        let param = format!("material '{}': {}", name, param);
+                        *texture = textures.scalar(d, &param, span.clone())?;
+                    }
+                }
+                if let Some(ior) = ior {
+                    if ior <= 0.0 {
+                        return Err(src.error(
+                            span,
+                            format!("material '{}': ior must be greater than 0", name),
+                        ));
+                    }
+                    p.ior = ior;
+                }
+                Material::Principled(Arc::new(p))
+            }
+        };
+        materials.insert(name, material);
+    }
+    let mut objects = Objects {
+        textures,
+        materials,
+        shapes: HashMap::new(),
+    };
+    // shapes are only placed by instances, which can't place each other,
+    // but media can fill the other shapes, so they come last
+    let mut shape_defs: Vec<(String, Spanned<ObjectDef>)> = def.shapes.into_iter().collect();
+    shape_defs.sort_by_key(|(name, d)| {
+        let medium = matches!(d.get_ref(), ObjectDef::ConstantMedium { .. });
+        (medium, name.clone())
+    });
+    for (name, shape_def) in shape_defs {
+        let span = shape_def.span();
+        let shape = match shape_def.into_inner() {
+            ObjectDef::Instance { .. } => {
+                return Err(src.error(
+                    span,
+                    format!("shape '{}': an instance cannot be a shape", name),
+                ))
+            }
+            d => match objects.build(d, span)? {
+                Built::Surface(surface) => Shape::Surface(Arc::from(surface)),
+                Built::Medium(medium) => Shape::Medium(medium),
+            },
+        };
+        objects.shapes.insert(name, shape);
+    }
+
+    let mut world = HitableList::new();
+    let mut media = Vec::new();
+    for object_def in def.objects {
+        let span = object_def.span();
+        match objects.build(object_def.into_inner(), span)? {
+            Built::Surface(surface) => world.push_boxed(surface),
+            Built::Medium(medium) => media.push(medium),
+        }
+    }
+    let punctual_lights = def
+        .lights
+        .into_iter()
+        .map(|light| build_light(light, &src, base_dir))
+        .collect::<Result<_, _>>()?;
+
+    Ok(Scene {
+        world,
+        media,
+        punctual_lights,
+        fog,
+        image,
+        camera,
+        background,
+        environment,
+    })
+}
+
+fn build_light(
+    def: Spanned<LightDef>,
+    src: &Source,
+    base_dir: &Path,
+) -> Result<PunctualLight, SceneError> {
+    let span = def.span();
+    let white = [1.0, 1.0, 1.0];
+    let (color, intensity) = match def.get_ref() {
+        LightDef::Point {
+            color, intensity, ..
+        }
+        | LightDef::Spot {
+            color, intensity, ..
+        }
+        | LightDef::Directional {
+            color, intensity, ..
+        } => (vec3(color.unwrap_or(white)), intensity.unwrap_or(1.0)),
+    };
+    if intensity < 0.0 {
+        return Err(src.error(span, "light intensity must not be negative".to_string()));
+    }
+    let nonzero = |d: [f64; 3], span: Range<usize>| {
+        if vec3(d).length() == 0.0 {
+            Err(src.error(span, "light direction must not be zero".to_string()))
+        } else {
+            Ok(vec3(d))
+        }
+    };
+    let profiled =
+        |light: PunctualLight, ies: Option<String>, nadir: Vec3, rotation: Option<f64>| {
+            let file = match ies {
+                Some(file) => file,
+                None => return Ok(light),
+            };
+            let profile = IesProfile::load(base_dir.join(&file))
+                .map_err(|e| src.error(span.clone(), format!("{}: {}", file, e)))?;
+            Ok(light.with_profile(Arc::new(profile), nadir, rotation.unwrap_or(0.0)))
+        };
+    Ok(match def.into_inner() {
+        LightDef::Point {
+            position,
+            ies,
+            direction,
+            rotation,
+            ..
+        } => {
+            let nadir = match direction {
+                Some(d) => nonzero(d, span.clone())?,
+                None => Vec3::new(0.0, -1.0, 0.0),
+            };
+            let light = PunctualLight::point(vec3(position), color, intensity);
+            profiled(light, ies, nadir, rotation)?
+        }
+        LightDef::Spot {
+            position,
+            direction,
+            inner_angle,
+            outer_angle,
+            ies,
+            rotation,
+            ..
+        } => {
+            let inner_angle = inner_angle.unwrap_or(outer_angle);
+            if !(0.0 < outer_angle && outer_angle <= 180.0) {
+                return Err(src.error(
+                    span,
+                    "spot outer_angle must be between 0 and 180 degrees".to_string(),
+                ));
+            }
+            if !(0.0..=outer_angle).contains(&inner_angle) {
+                return Err(src.error(
+                    span,
+                    "spot inner_angle must be between 0 and outer_angle".to_string(),
+                ));
+            }
+            let direction = nonzero(direction, span.clone())?;
+            let light = PunctualLight::spot(
+                vec3(position),
+                direction,
+                inner_angle,
+                outer_angle,
+                color,
+                intensity,
+            );
+            profiled(light, ies, direction, rotation)?
+        }
+        LightDef::Directional { direction, .. } => {
+            PunctualLight::directional(nonzero(direction, span)?, color, intensity)
+        }
+    })
+}
+
+fn build_background(
+    def: Spanned<BackgroundDef>,
+    src: &Source,
+    base_dir: &Path,
+) -> Result<Background, SceneError> {
+    let span = def.span();
+    Ok(match def.into_inner() {
+        BackgroundDef::Gradient => Background::Gradient,
+        BackgroundDef::Black => Background::Constant(Vec3::new(0.0, 0.0, 0.0)),
+        BackgroundDef::Constant { color } => Background::Constant(vec3(color)),
+        BackgroundDef::Image {
+            file,
+            rotation,
+            intensity,
+        } => {
+            let intensity = intensity.unwrap_or(1.0);
+            if intensity < 0.0 {
+                return Err(src.error(span, "image intensity must not be negative".to_string()));
+            }
+            let map =
+                EnvironmentMap::load(base_dir.join(&file), rotation.unwrap_or(0.0), intensity)
+                    .map_err(|e| src.error(span, format!("{}: {}", file, e)))?;
+            Background::Environment(Arc::new(map))
+        }
+        BackgroundDef::Sky {
+            elevation,
+            azimuth,
+            turbidity,
+            intensity,
+            sun,
+        } => {
+            let turbidity = turbidity.unwrap_or(3.0);
+            let intensity = intensity.unwrap_or(1.0);
+            if !(0.0..=90.0).contains(&elevation) {
+                return Err(src.error(
+                    span,
+                    "sky elevation must be between 0 and 90 degrees".to_string(),
+                ));
+            }
+            if !(1.7..=10.0).contains(&turbidity) {
+                return Err(src.error(span, "sky turbidity must be between 1.7 and 10".to_string()));
+            }
+            if intensity < 0.0 {
+                return Err(src.error(span, "sky intensity must not be negative".to_string()));
+            }
+            Background::Sky(Arc::new(Sky::new(
+                elevation,
+                azimuth.unwrap_or(0.0),
+                turbidity,
+                intensity,
+                sun.unwrap_or(true),
+            )))
+        }
+    })
+}
+
+// ======================================================================
+// Unit testing
+// ======================================================================
+#[cfg(test)]
+use aabb::Aabb;
+#[cfg(test)]
+use camera::StereoLayout;
+
+#[test]
+fn test_parse_scene() {
+    let text = r#"
+[image]
+width = 320
+samples = 4
+
+[camera]
+lookfrom = [0, 0, 1]
+lookat = [0, 0, -1]
+vfov = 90
+
+[background]
+type = "black"
+
+[fog]
+density = 0.05
+g = 0.5
+
+[materials]
+blue = { type = "lambertian", albedo = [0.0, 0.0, 1.0] }
+glass = { type = "dielectric", ref_idx = 1.5 }
+light = { type = "diffuse_light", emit = [1.0, 0.9, 0.8], strength = 4.0 }
+
+[[objects]]
+type = "sphere"
+center = [0, 0, -1]
+radius = 0.5
+material = "blue"
+
+[[objects]]
+type = "sphere"
+center = [1, 0, -1]
+radius = 0.5
+material = "glass"
+
+[[objects]]
+type = "constant_medium"
+boundary = { type = "sphere", center = [-1, 0, -1], radius = 0.5 }
+density = 2.0
+"#;
+    let scene = parse_scene(text, Path::new("")).unwrap();
+    assert_eq!(scene.image.width, 320);
+    assert_eq!(scene.image.height, 720);
+    assert_eq!(scene.image.samples, 4);
+    assert_eq!(scene.camera.lookfrom, Vec3::new(0.0, 0.0, 1.0));
+    assert_eq!(scene.camera.vfov, 90.0);
+    assert_eq!(scene.camera.aperture, 0.1);
+    assert_eq!(
+        scene.background,
+        Background::Constant(Vec3::new(0.0, 0.0, 0.0))
+    );
+    assert_eq!(scene.media.len(), 1);
+    let fog = scene.fog.unwrap();
+    assert_eq!(fog.density, 0.05);
+    assert_eq!(fog.phase, PhaseFunction::HenyeyGreenstein(0.5));
+}
+#[test]
+fn test_parse_scene_microfacet_materials() {
+    let text = r#"
+[materials]
+gold = { type = "conductor", preset = "gold", roughness = 0.3 }
+brushed = { type = "conductor", eta = [1.5, 1.0, 0.5], k = [9, 6, 5], roughness = [0.1, 0.6] }
+frosted = { type = "rough_dielectric", ref_idx = 1.5, roughness = 0.2 }
+"#;
+    assert!(parse_scene(text, Path::new("")).is_ok());
+
+    let both = "[materials]\nm = { type = \"conductor\", preset = \"gold\", eta = [1, 1, 1] }\n";
+    let e = parse_scene(both, Path::new("")).err().unwrap();
+    assert_eq!(e.line, Some(2));
+    assert!(e.message.contains("preset"));
+
+    let tin = "[materials]\nm = { type = \"conductor\", preset = \"tin\" }\n";
+    let e = parse_scene(tin, Path::new("")).err().unwrap();
+    assert!(e.message.contains("unknown metal 'tin'"));
+
+    let rough = "[materials]\nm = { type = \"rough_dielectric\", ref_idx = 1.5, roughness = 2 }\n";
+    let e = parse_scene(rough, Path::new("")).err().unwrap();
+    assert!(e.message.contains("roughness"));
+}
+#[test]
+fn test_parse_scene_dispersion() {
+    let text = r#"
+[image]
+spectral = true
+
+[materials]
+crown = { type = "dielectric", cauchy = [1.5046, 0.0042] }
+bk7 = { type = "rough_dielectric", sellmeier = { b = [1.04, 0.23, 1.01], c = [0.006, 0.02, 103.6] }, roughness = 0.1 }
+"#;
+    let scene = parse_scene(text, Path::new("")).unwrap();
+    assert!(scene.image.spectral);
+
+    let both = "[materials]\nm = { type = \"dielectric\", ref_idx = 1.5, cauchy = [1.5, 0.01] }\n";
+    let e = parse_scene(both, Path::new("")).err().unwrap();
+    assert_eq!(e.line, Some(2));
+    assert!(e.message.contains("one of ref_idx, cauchy or sellmeier"));
+
+    let negative = "[materials]\nm = { type = \"dielectric\", cauchy = [-1.5, 0.01] }\n";
+    let e = parse_scene(negative, Path::new("")).err().unwrap();
+    assert!(e.message.contains("not a positive number at 380nm"));
+}
+#[test]
+fn test_parse_scene_principled() {
+    let text = r#"
+[textures]
+scratches = { type = "turbulence", scale = 4 }
+
+[materials]
+plastic = { type = "principled" }
+paint = { type = "principled", base_color = [0.6, 0.1, 0.1], clearcoat = 1, roughness = "scratches" }
+glass = { type = "principled", transmission = 1, roughness = 0.1, ior = 1.45 }
+"#;
+    assert!(parse_scene(text, Path::new("")).is_ok());
+
+    let metallic = "[materials]\nm = { type = \"principled\", metallic = 1.5 }\n";
+    let e = parse_scene(metallic, Path::new("")).err().unwrap();
+    assert_eq!(e.line, Some(2));
+    assert!(e.message.contains("metallic must be between 0 and 1"));
+
+    let unknown = "[materials]\nm = { type = \"principled\", sheen = \"velvet\" }\n";
+    let e = parse_scene(unknown, Path::new("")).err().unwrap();
+    assert!(e.message.contains("unknown texture 'velvet'"));
+}
+#[test]
+fn test_parse_scene_environment() {
+    let scene = load_scene("scenes/environment.toml").unwrap();
+    assert_eq!(
+        scene.background,
+        Background::Constant(Vec3::new(0.9, 0.9, 0.9))
+    );
+    match scene.environment {
+        Some(Background::Environment(_)) => {}
+        _ => panic!("expected an environment map"),
+    }
+
+    let missing = "[environment]\ntype = \"image\"\nfile = \"nowhere.hdr\"\n";
+    let e = parse_scene(missing, Path::new("")).err().unwrap();
+    assert_eq!(e.line, Some(1));
+    assert!(e.message.contains("nowhere.hdr"));
+
+    let sky = "[background]\ntype = \"sky\"\nelevation = 30\nazimuth = 120\n";
+    let scene = parse_scene(sky, Path::new("")).unwrap();
+    match scene.background {
+        Background::Sky(_) => {}
+        _ => panic!("expected a sky"),
+    }
+    let night = "[background]\ntype = \"sky\"\nelevation = -10\n";
+    let e = parse_scene(night, Path::new("")).err().unwrap();
+    assert!(e.message.contains("elevation must be between 0 and 90"));
+}
+#[test]
+fn test_parse_scene_lights() {
+    let text = r#"
+[[lights]]
+type = "point"
+position = [0, 4, 0]
+intensity = 10
+
+[[lights]]
+type = "spot"
+position = [0, 4, 0]
+direction = [0, -1, 0]
+inner_angle = 10
+outer_angle = 20
+color = [1, 0.5, 0.2]
+
+[[lights]]
+type = "directional"
+direction = [1, -1, 0]
+"#;
+    let scene = parse_scene(text, Path::new("")).unwrap();
+    assert_eq!(scene.punctual_lights.len(), 3);
+    assert_eq!(
+        scene.punctual_lights[0],
+        PunctualLight::point(Vec3::new(0.0, 4.0, 0.0), Vec3::new(1.0, 1.0, 1.0), 10.0)
+    );
+    // nothing but lights, so nothing in the world to hit
+    let mut bbox = Aabb::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 0.0));
+    assert!(!scene.world.into_bvh().bounding_box(&mut bbox));
+
+    let cone = "[[lights]]\ntype = \"spot\"\nposition = [0, 1, 0]\ndirection = [0, -1, 0]\ninner_angle = 30\nouter_angle = 20\n";
+    let e = parse_scene(cone, Path::new("")).err().unwrap();
+    assert_eq!(e.line, Some(1));
+    assert!(e
+        .message
+        .contains("inner_angle must be between 0 and outer_angle"));
+
+    let profiled = "[[lights]]\ntype = \"point\"\nposition = [0, 1, 0]\nies = \"wallwash.ies\"\n";
+    let scene = parse_scene(profiled, Path::new("scenes")).unwrap();
+    match scene.punctual_lights[0] {
+        PunctualLight::Goniometric { .. } => {}
+        _ => panic!("expected a goniometric light"),
+    }
+    let e = parse_scene(profiled, Path::new("")).err().unwrap();
+    assert!(e.message.contains("wallwash.ies"));
+
+    let zero = "[[lights]]\ntype = \"directional\"\ndirection = [0, 0, 0]\n";
+    let e = parse_scene(zero, Path::new("")).err().unwrap();
+    assert!(e.message.contains("direction must not be zero"));
+}
+#[test]
+fn test_parse_scene_projections() {
+    let projection = |camera: &str| {
+        parse_scene(&format!("[camera]\n{}\n", camera), Path::new(""))
+            .map(|scene| scene.camera.projection)
+    };
+    assert_eq!(projection("vfov = 40").unwrap(), Projection::Perspective);
+    assert_eq!(
+        projection("projection = \"orthographic\"\nview_height = 3").unwrap(),
+        Projection::Orthographic { height: 3.0 }
+    );
+    assert_eq!(
+        projection("projection = \"fisheye\"").unwrap(),
+        Projection::Fisheye { fov: 180.0 }
+    );
+    assert_eq!(
+        projection("projection = \"cubemap\"").unwrap(),
+        Projection::Cubemap
+    );
+    let e = projection("projection = \"orthographic\"").err().unwrap();
+    assert!(e.message.contains("view_height"));
+    let e = projection("projection = \"fisheye\"\nfisheye_fov = 400")
+        .err()
+        .unwrap();
+    assert!(e.message.contains("fisheye_fov"));
+    let e = projection("projection = \"spherical\"").err().unwrap();
+    assert!(e.message.contains("unknown camera projection 'spherical'"));
+
+    let text = "[image]\nwidth = 400\nheight = 400\n\n[camera]\nstereo = \"top_bottom\"\nconvergence = 5\n";
+    let scene = parse_scene(text, Path::new("")).unwrap();
+    assert_eq!(
+        scene.camera.stereo,
+        Some(Stereo {
+            layout: StereoLayout::TopBottom,
+            interocular: 0.065,
+            convergence: Some(5.0),
+        })
+    );
+    let e = parse_scene("[camera]\nstereo = \"over_under\"\n", Path::new(""))
+        .err()
+        .unwrap();
+    assert!(e.message.contains("unknown stereo layout 'over_under'"));
+    let e = parse_scene("[camera]\ninterocular = 0.1\n", Path::new(""))
+        .err()
+        .unwrap();
+    assert!(e.message.contains("need stereo"));
+}
+#[test]
+fn test_parse_scene_focus_and_aperture() {
+    let text = r#"
+[camera]
+lookfrom = [0, 0, 10]
+lookat = [0, 0, 0]
+autofocus = true
+aperture_blades = 7
+aperture_rotation = 15
+cat_eye = 0.5
+
+[materials]
+red = { type = "lambertian", albedo = [1, 0, 0] }
+
+[[objects]]
+type = "sphere"
+center = [0, 0, 2]
+radius = 1
+material = "red"
+"#;
+    let mut scene = parse_scene(text, Path::new("")).unwrap();
+    assert_eq!(scene.focus_distance(), 7.0);
+    assert_eq!(
+        scene.camera.lens,
+        Aperture {
+            shape: ApertureShape::Polygon {
+                blades: 7,
+                rotation: 15.0
+            },
+            cat_eye: 0.5,
+            squeeze: 1.0,
+        }
+    );
+    // along the view, not straight to the point
+    scene.camera.focus_point = Some(Vec3::new(3.0, 1.0, -2.0));
+    assert_eq!(scene.focus_distance(), 12.0);
+    scene.camera.focus_point = None;
+    scene.camera.lookat = Vec3::new(0.0, 10.0, 10.0);
+    assert_eq!(scene.focus_distance(), scene.camera.focus_dist);
+
+    let masked = "[camera]\naperture_mask = \"sky.hdr\"\nanamorphic_squeeze = 2\n";
+    let scene = parse_scene(masked, Path::new("scenes")).unwrap();
+    match scene.camera.lens.shape {
+        ApertureShape::Mask(_) => {}
+        _ => panic!("expected an aperture mask"),
+    }
+    assert_eq!(scene.camera.lens.squeeze, 2.0);
+
+    let error = |camera: &str| {
+        parse_scene(&format!("[camera]\n{}\n", camera), Path::new(""))
+            .err()
+            .unwrap()
+            .message
+    };
+    assert!(error("focus_dist = 5\nautofocus = true").contains("cannot be used together"));
+    assert!(
+        error("focus_point = [20, 0, 0]\nlookfrom = [10, 0, 0]\nlookat = [0, 0, 0]")
+            .contains("in front of the camera")
+    );
+    assert!(error("aperture_blades = 2").contains("at least 3"));
+    assert!(error("cat_eye = 1.5").contains("between 0 and 1"));
+    assert!(error("aperture_mask = \"nope.png\"").contains("nope.png"));
+}
+#[test]
+fn test_parse_scene_textures() {
+    let text = r#"
+[textures]
+white = { type = "constant", color = [1, 1, 1] }
+checks = { type = "checker", odd = [0, 0, 0], even = "white", scale = 10 }
+
+[materials]
+ground = { type = "lambertian", albedo = "checks" }
+"#;
+    assert!(parse_scene(text, Path::new("")).is_ok());
+
+    let cycle = "[textures]\na = { type = \"checker\", odd = \"b\", even = [0, 0, 0] }\nb = { type = \"checker\", odd = \"a\", even = [0, 0, 0] }\n";
+    let e = parse_scene(cycle, Path::new("")).err().unwrap();
+    assert!(e.message.contains("refers to itself"));
+
+    let unknown = "[materials]\nred = { type = \"lambertian\", albedo = \"rouge\" }\n";
+    let e = parse_scene(unknown, Path::new("")).err().unwrap();
+    assert_eq!(e.line, Some(2));
+    assert!(e.message.contains("rouge"));
+}
+#[test]
+fn test_parse_scene_instances() {
+    let text = r#"
+[materials]
+red = { type = "lambertian", albedo = [1, 0, 0] }
+
+[shapes]
+ball = { type = "sphere", center = [0, 0, 0], radius = 1, material = "red" }
+
+[[objects]]
+type = "instance"
+shape = "ball"
+transform = [{ scale = [1, 0.5, 1] }, { rotate = { axis = [0, 0, 1], angle = 30 } }]
+
+[[objects]]
+type = "instance"
+shape = "ball"
+transform = [{ scale = 2 }, { look_at = { from = [0, 0, 5], to = [0, 0, 0] } }]
+"#;
+    let scene = parse_scene(text, Path::new("")).unwrap();
+    let mut bbox = Aabb::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 0.0));
+    assert!(scene.world.bounding_box(&mut bbox));
+    assert!((bbox.max.z() - 7.0).abs() < 1e-9);
+
+    let unknown = "[[objects]]\ntype = \"instance\"\nshape = \"ball\"\n";
+    let e = parse_scene(unknown, Path::new("")).err().unwrap();
+    assert!(e.message.contains("unknown shape 'ball'"));
+
+    let flat = "[materials]\nred = { type = \"lambertian\", albedo = [1, 0, 0] }\n[shapes]\nball = { type = \"sphere\", center = [0, 0, 0], radius = 1, material = \"red\" }\n\n[[objects]]\ntype = \"instance\"\nshape = \"ball\"\ntransform = [{ scale = [1, 0, 1] }]\n";
+    let e = parse_scene(flat, Path::new("")).err().unwrap();
+    assert_eq!(e.line, Some(6));
+    let nan = flat.replace("[1, 0, 1]", "[nan, 1, 1]");
+    let e = parse_scene(&nan, Path::new("")).err().unwrap();
+    assert_eq!(e.line, Some(9));
+    let huge = flat.replace("[1, 0, 1]", "1e200 }, { scale = 1e200");
+    let e = parse_scene(&huge, Path::new("")).err().unwrap();
+    assert_eq!(e.line, Some(6));
+    assert!(e.message.contains("too large"));
+
+    // media fill other shapes, & can be shapes themselves
+    let text = r#"
+[materials]
+red = { type = "lambertian", albedo = [1, 0, 0] }
+
+[shapes]
+a_smoke = { type = "constant_medium", density = 50, boundary = { type = "instance", shape = "ball" } }
+ball = { type = "sphere", center = [0, 0, 0], radius = 1, material = "red" }
+
+[[objects]]
+type = "instance"
+shape = "a_smoke"
+transform = [{ translate = [0, 5, 0] }]
+
+[[objects]]
+type = "constant_medium"
+density = 50
+boundary = { type = "instance", shape = "ball", transform = [{ scale = 2 }] }
+"#;
+    let scene = parse_scene(text, Path::new("")).unwrap();
+    let seed: &[_] = &[7];
+    let mut rng: StdRng = SeedableRng::from_seed(seed);
+    let scatter = |medium: &ConstantMedium, y: f64, rng: &mut StdRng| {
+        let r = Ray::new(Vec3::new(0.0, y, 5.0), Vec3::new(0.0, 0.0, -1.0));
+        medium
+            .sample_distance(&r, f64::MAX, rng)
+            .map(|t| r.point_at_parameter(t).z())
+    };
+    assert!(scatter(&scene.media[0], 5.0, &mut rng).is_some_and(|z| z > 0.9 && z < 1.0));
+    assert_eq!(scatter(&scene.media[0], 0.0, &mut rng), None);
+    assert!(scatter(&scene.media[1], 0.0, &mut rng).is_some_and(|z| z > 1.9 && z < 2.0));
+
+    let nested = "[shapes]\nsmoke = { type = \"constant_medium\", density = 1, boundary = { type = \"sphere\", center = [0, 0, 0], radius = 1 } }\n\n[[objects]]\ntype = \"constant_medium\"\ndensity = 1\nboundary = { type = \"instance\", shape = \"smoke\" }\n";
+    let e = parse_scene(nested, Path::new("")).err().unwrap();
+    assert_eq!(e.line, Some(4));
+    assert!(e.message.contains("shape 'smoke' is a constant_medium"));
+}
+#[test]
+fn test_parse_scene_errors() {
+    let unknown_material =
+        "[[objects]]\ntype = \"sphere\"\ncenter = [0, 0, 0]\nradius = 1\nmaterial = \"nope\"\n";
+    let e = parse_scene(unknown_material, Path::new("")).err().unwrap();
+    assert_eq!(e.line, Some(1));
+    assert!(e.message.contains("nope"));
+
+    let missing_field =
+        "[materials]\nred = { type = \"lambertian\", albedo = [1, 0, 0] }\n\n[[objects]]\ntype = \"sphere\"\nradius = 1\nmaterial = \"red\"\n";
+    let e = parse_scene(missing_field, Path::new("")).err().unwrap();
+    assert_eq!(e.line, Some(4));
+    assert!(e.message.contains("center"));
+
+    let bad_value = "[materials]\n\nred = { type = \"metal\", albedo = [1, 0, 0], fuzz = \"x\" }\n";
+    let e = parse_scene(bad_value, Path::new("")).err().unwrap();
+    assert_eq!(e.line, Some(3));
+
+    let empty_box =
+        "[materials]\nred = { type = \"lambertian\", albedo = [1, 0, 0] }\n\n[[objects]]\ntype = \"box\"\nmin = [0, 0, 0]\nmax = [1, 0, 1]\nmaterial = \"red\"\n";
+    let e = parse_scene(empty_box, Path::new("")).err().unwrap();
+    assert_eq!(e.line, Some(4));
+
+    let bad_image = "[image]\nwidth = 0\n";
+    let e = parse_scene(bad_image, Path::new("")).err().unwrap();
+    assert_eq!(e.line, Some(1));
+
+    // TOML's inf & nan, wherever they are
+    for (text, line) in &[
+        ("[camera]\nlookfrom = [0, 0, 1]\nvfov = nan\n", 3),
+        ("[camera]\nfocus_point = [0, -inf, 0]\n", 2),
+        ("[fog]\ndensity = inf\n", 2),
+        (
+            "[materials]\nm = { type = \"metal\", albedo = [1, 1, 1], fuzz = -nan }\n",
+            2,
+        ),
+        (
+            "[[objects]]\ntype = \"sphere\"\ncenter = [0, 0, 0]\nradius = +inf\nmaterial = \"m\"\n",
+            4,
+        ),
+    ] {
+        let e = parse_scene(text, Path::new("")).err().unwrap();
+        assert_eq!(e.line, Some(*line));
+        assert!(e.message.contains("expected a finite number"));
+    }
+    let keyframes = "[materials]\nred = { type = \"lambertian\", albedo = [1, 0, 0] }\n\n[[objects]]\ntype = \"moving_sphere\"\nradius = 1\nmaterial = \"red\"\nkeyframes = [\n  { time = 0, center = [0, 0, 0] },\n  { time = nan, center = [1, 0, 0] },\n]\n";
+    let e = parse_scene(keyframes, Path::new("")).err().unwrap();
+    assert_eq!(e.line, Some(10));
+}
@@ -0,0 +1,292 @@
+use std::f64::consts::PI;
+use std::fmt;
+use std::sync::Arc;
+
+use aabb::Aabb;
+use environment::EnvironmentMap;
+use hitable::{HitRecord, Hitable};
+use onb::Onb;
+use ray::Ray;
+use spectrum::xyz_to_rgb;
+use vec3::{dot, unit_vector, Vec3};
+
+// the sun's angular radius, as seen from the earth
+const SUN_RADIUS: f64 = 0.2667 * PI / 180.0;
+
+// the sun's luminance above the atmosphere, in kcd/m²
+const SUN_LUMINANCE: f64 = 2.0e6;
+
+// kcd/m² to the renderer's units, so that a white surface facing the
+// midday sun comes out at about 1
+const UNIT: f64 = 0.025;
+
+// The Perez et al. sky luminance distribution, relative to the zenith.
+// theta is the angle from the zenith, gamma the angle from the sun.
+#[derive(Clone, Copy, Debug)]
+struct Perez([f64; 5]);
+
+impl Perez {
+    fn f(&self, cos_theta: f64, gamma: f64) -> f64 {
+        let [a, b, c, d, e] = self.0;
+        let cos_gamma = gamma.cos();
+        (1.0 + a * (b / cos_theta).exp())
+            * (1.0 + c * (d * gamma).exp() + e * cos_gamma * cos_gamma)
+    }
+}
+
+// The sun seen through the atmosphere: a disk of the right size (about
+// 6.8e-5 sr) and a colour from how much air the light crosses.  As a
+// light it is sampled uniformly over the disk.
+#[derive(Clone, Copy, Debug)]
+pub struct Sun {
+    direction: Vec3, // towards the sun
+    cos_max: f64,
+    radiance: Vec3,
+}
+
+impl Sun {
+    fn new(direction: Vec3, turbidity: f64, intensity: f64) -> Sun {
+        let zenith = direction.y().clamp(0.0, 1.0).acos().to_degrees();
+        // Kasten & Young's relative air mass
+        let air_mass =
+            1.0 / (zenith.to_radians().cos() + 0.50572 * (96.07995 - zenith).powf(-1.6364));
+        // Rayleigh scattering & Ångström's aerosol turbidity, at roughly
+        // the red, green & blue primaries' wavelengths (µm)
+        let beta = 0.04608 * turbidity - 0.04586;
+        let transmittance = |lambda: f64| {
+            (-air_mass * (0.008735 * lambda.powf(-4.08) + beta * lambda.powf(-1.3))).exp()
+        };
+        let radiance = Vec3::new(
+            transmittance(0.68),
+            transmittance(0.55),
+            transmittance(0.44),
+        );
+        Sun {
+            direction,
+            cos_max: SUN_RADIUS.cos(),
+            radiance: radiance * (SUN_LUMINANCE * UNIT * intensity),
+        }
+    }
+
+    pub fn solid_angle(&self) -> f64 {
+        2.0 * PI * (1.0 - self.cos_max)
+    }
+
+    // what is seen along the unit direction d
+    fn color(&self, d: &Vec3) -> Vec3 {
+        if dot(d, &self.direction) >= self.cos_max {
+            self.radiance
+        } else {
+            Vec3::new(0.0, 0.0, 0.0)
+        }
+    }
+}
+
+impl Hitable for Sun {
+    fn hit(&self, _r: &Ray, _t_min: f64, _t_max: f64, _rec: &mut HitRecord) -> bool {
+        false
+    }
+    fn bounding_box(&self, _output_box: &mut Aabb) -> bool {
+        false
+    }
+    fn is_light(&self) -> bool {
+        true
+    }
+    fn pdf_value(&self, _o: &Vec3, v: &Vec3) -> f64 {
+        if dot(&unit_vector(*v), &self.direction) >= self.cos_max {
+            1.0 / self.solid_angle()
+        } else {
+            0.0
+        }
+    }
+    fn random(&self, _o: &Vec3, sample: (f64, f64)) -> Vec3 {
+        let z = 1.0 + sample.1 * (self.cos_max - 1.0);
+        let phi = 2.0 * PI * sample.0;
+        let r = (1.0 - z * z).max(0.0).sqrt();
+        Onb::build_from_w(&self.direction).local(&Vec3::new(phi.cos() * r, phi.sin() * r, z))
+    }
+}
+
+// Preetham, Shirley & Smits' analytic daylight (1999): the sky's
+// luminance & chromaticity for a sun direction & turbidity.
+#[derive(Clone, Copy, Debug)]
+struct Preetham {
+    sun: Vec3,    // towards the sun
+    zenith: Vec3, // Y (kcd/m²), x & y straight up
+    perez: [Perez; 3],
+    scale: f64,
+}
+
+impl Preetham {
+    fn new(sun: Vec3, t: f64, scale: f64) -> Preetham {
+        let theta = sun.y().clamp(-1.0, 1.0).acos();
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta);
+        let zenith_y = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+        let chromaticity = |c: [[f64; 4]; 3]| {
+            let row = |r: [f64; 4]| ((r[0] * theta + r[1]) * theta + r[2]) * theta + r[3];
+            t * t * row(c[0]) + t * row(c[1]) + row(c[2])
+        };
+        let zenith_x = chromaticity([
+            [0.00166, -0.00375, 0.00209, 0.0],
+            [-0.02903, 0.06377, -0.03202, 0.00394],
+            [0.11693, -0.21196, 0.06052, 0.25886],
+        ]);
+        let zenith_y_chroma = chromaticity([
+            [0.00275, -0.00610, 0.00317, 0.0],
+            [-0.04214, 0.08970, -0.04153, 0.00516],
+            [0.15346, -0.26756, 0.06670, 0.26688],
+        ]);
+        let perez = [
+            Perez([
+                0.1787 * t - 1.4630,
+                -0.3554 * t + 0.4275,
+                -0.0227 * t + 5.3251,
+                0.1206 * t - 2.5771,
+                -0.0670 * t + 0.3703,
+            ]),
+            Perez([
+                -0.0193 * t - 0.2592,
+                -0.0665 * t + 0.0008,
+                -0.0004 * t + 0.2125,
+                -0.0641 * t - 0.8989,
+                -0.0033 * t + 0.0452,
+            ]),
+            Perez([
+                -0.0167 * t - 0.2608,
+                -0.0950 * t + 0.0092,
+                -0.0079 * t + 0.2102,
+                -0.0441 * t - 1.6537,
+                -0.0109 * t + 0.0529,
+            ]),
+        ];
+        // relative to the zenith, which is theta from the sun
+        let mut zenith = Vec3::new(zenith_y, zenith_x, zenith_y_chroma);
+        for (i, perez) in perez.iter().enumerate() {
+            zenith[i] /= perez.f(1.0, theta);
+        }
+        Preetham {
+            sun,
+            zenith,
+            perez,
+            scale,
+        }
+    }
+
+    // along the unit direction d, black below the horizon
+    fn color(&self, d: &Vec3) -> Vec3 {
+        if d.y() <= 0.0 {
+            return Vec3::new(0.0, 0.0, 0.0);
+        }
+        let gamma = dot(d, &self.sun).clamp(-1.0, 1.0).acos();
+        let lum = self.zenith[0] * self.perez[0].f(d.y(), gamma);
+        let x = self.zenith[1] * self.perez[1].f(d.y(), gamma);
+        let y = self.zenith[2] * self.perez[2].f(d.y(), gamma);
+        let rgb = xyz_to_rgb(&Vec3::new(x / y * lum, lum, (1.0 - x - y) / y * lum));
+        Vec3::new(rgb.x().max(0.0), rgb.y().max(0.0), rgb.z().max(0.0)) * self.scale
+    }
+}
+
+// A daylight sky, with the sun at some elevation & azimuth (degrees)
+// and a turbidity from about 2 (very clear) to 10 (hazy).  Azimuth 0
+// puts the sun towards -z, and 90 towards +x.  Below the horizon it is
+// black, so scenes need a ground of their own.
+//
+// As lights, the sky is sampled from an image of it, and the sun disk
+// on its own.
+pub struct Sky {
+    model: Preetham,
+    sun: Option<Sun>,
+    dome: Arc<EnvironmentMap>,
+}
+
+impl fmt::Debug for Sky {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Sky({:?})", self.model.sun)
+    }
+}
+
+// compared by identity, like environment maps
+impl PartialEq for Sky {
+    fn eq(&self, other: &Sky) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl Sky {
+    pub fn new(elevation: f64, azimuth: f64, turbidity: f64, intensity: f64, sun: bool) -> Sky {
+        let (elevation, azimuth) = (elevation.to_radians(), azimuth.to_radians());
+        let direction = Vec3::new(
+            azimuth.sin() * elevation.cos(),
+            elevation.sin(),
+            -azimuth.cos() * elevation.cos(),
+        );
+        let model = Preetham::new(direction, turbidity, UNIT * intensity);
+        Sky {
+            model,
+            sun: if sun {
+                Some(Sun::new(direction, turbidity, intensity))
+            } else {
+                None
+            },
+            dome: Arc::new(EnvironmentMap::bake(128, 64, |d| model.color(d))),
+        }
+    }
+
+    // what is seen along the direction d
+    pub fn color(&self, d: &Vec3) -> Vec3 {
+        let d = unit_vector(*d);
+        match self.sun {
+            Some(ref sun) => self.model.color(&d) + sun.color(&d),
+            None => self.model.color(&d),
+        }
+    }
+
+    // the parts of it to sample as lights
+    pub fn lights(&self) -> Vec<Arc<dyn Hitable>> {
+        let mut lights: Vec<Arc<dyn Hitable>> = vec![self.dome.clone()];
+        if let Some(sun) = self.sun {
+            lights.push(Arc::new(sun));
+        }
+        lights
+    }
+}
+
+// ======================================================================
+// Unit testing
+// ======================================================================
+#[test]
+fn test_sky() {
+    let sky = Sky::new(45.0, 90.0, 3.0, 1.0, true);
+    let up = sky.color(&Vec3::new(0.0, 1.0, 0.0));
+    assert!(up.z() > up.x() && up.x() > 0.0);
+    assert_eq!(
+        sky.color(&Vec3::new(0.3, -0.5, 0.1)),
+        Vec3::new(0.0, 0.0, 0.0)
+    );
+    // the sun is towards +x, & much brighter than the sky around it
+    let sun = Vec3::new(1.0, 1.0, 0.0);
+    assert!(sky.color(&sun).y() > 1000.0 * sky.color(&Vec3::new(1.0, 1.1, 0.0)).y());
+    assert_eq!(sky.lights().len(), 2);
+    assert_eq!(Sky::new(45.0, 0.0, 3.0, 1.0, false).lights().len(), 1);
+    // redder near the horizon, where the light crosses more air
+    let low = Sky::new(5.0, 0.0, 3.0, 1.0, true).sun.unwrap().radiance;
+    let high = sky.sun.unwrap().radiance;
+    assert!(low.x() / low.z() > high.x() / high.z());
+}
+#[test]
+fn test_sun_sampling() {
+    let sun = Sun::new(unit_vector(Vec3::new(0.2, 1.0, -0.4)), 3.0, 1.0);
+    assert!((sun.solid_angle() - 6.8e-5).abs() < 0.1e-5);
+    let o = Vec3::new(0.0, 0.0, 0.0);
+    let n = 16;
+    for i in 0..n {
+        for j in 0..n {
+            let sample = ((i as f64 + 0.5) / n as f64, (j as f64 + 0.5) / n as f64);
+            let d = sun.random(&o, sample);
+            assert!((d.length() - 1.0).abs() < 1e-9);
+            assert_eq!(sun.pdf_value(&o, &d), 1.0 / sun.solid_angle());
+            assert_eq!(sun.color(&d), sun.radiance);
+        }
+    }
+    assert_eq!(sun.pdf_value(&o, &Vec3::new(0.0, 1.0, 0.0)), 0.0);
+}
@@ -0,0 +1,249 @@
+use vec3::Vec3;
+
+// Spectral rendering.  Each path carries radiance at three wavelengths
+// in a Vec3: a "hero" wavelength picked at random and two more spread
+// evenly across the visible range from it (Wilkie et al. 2014).  RGB
+// colours are turned into spectra as they are used, and the wavelengths
+// are added back up through the CIE colour matching functions.
+
+pub const LAMBDA_MIN: f64 = 380.0; // nm
+pub const LAMBDA_MAX: f64 = 780.0;
+
+// the helium d line, where glasses' single index of refraction is given
+pub const D_LINE: f64 = 587.6;
+
+// CIE 1931 2° colour matching functions, the multi-lobe Gaussian fit
+// of Wyman, Sloan & Shirley 2013.  lambda in nm.
+pub fn cie_xyz(lambda: f64) -> Vec3 {
+    let g = |mu: f64, sigma1: f64, sigma2: f64| {
+        let t = (lambda - mu) / if lambda < mu { sigma1 } else { sigma2 };
+        (-0.5 * t * t).exp()
+    };
+    Vec3::new(
+        1.056 * g(599.8, 37.9, 31.0) + 0.362 * g(442.0, 16.0, 26.7) - 0.065 * g(501.1, 20.4, 26.2),
+        0.821 * g(568.8, 46.9, 40.5) + 0.286 * g(530.9, 16.3, 31.1),
+        1.217 * g(437.0, 11.8, 36.0) + 0.681 * g(459.0, 26.0, 13.8),
+    )
+}
+
+// to linear sRGB, with its D65 white point
+pub fn xyz_to_rgb(xyz: &Vec3) -> Vec3 {
+    let (x, y, z) = (xyz.x(), xyz.y(), xyz.z());
+    Vec3::new(
+        3.2404542 * x - 1.5371385 * y - 0.4985314 * z,
+        -0.9692660 * x + 1.8760108 * y + 0.0415560 * z,
+        0.0556434 * x - 0.2040259 * y + 1.0572252 * z,
+    )
+}
+
+// Smits 1999: RGB to spectrum as a mix of these, sampled in ten even
+// bins from 380 to 720nm.  Greys become flat spectra, and the result
+// scales with the colour, so it also works for lights & path weights.
+#[rustfmt::skip]
+const SMITS: [[f64; 10]; 7] = [
+    [1.0000, 1.0000, 0.9999, 0.9993, 0.9992, 0.9998, 1.0000, 1.0000, 1.0000, 1.0000], // white
+    [0.9710, 0.9426, 1.0007, 1.0007, 1.0007, 1.0007, 0.1564, 0.0000, 0.0000, 0.0000], // cyan
+    [1.0000, 1.0000, 0.9685, 0.2229, 0.0000, 0.0458, 0.8369, 1.0000, 1.0000, 0.9959], // magenta
+    [0.0001, 0.0000, 0.1088, 0.6651, 1.0000, 1.0000, 0.9996, 0.9586, 0.9685, 0.9840], // yellow
+    [0.1012, 0.0515, 0.0000, 0.0000, 0.0000, 0.0000, 0.8325, 1.0149, 1.0149, 1.0149], // red
+    [0.0000, 0.0000, 0.0273, 0.7937, 1.0000, 0.9418, 0.1719, 0.0000, 0.0000, 0.0025], // green
+    [1.0000, 1.0000, 0.8916, 0.3323, 0.0000, 0.0000, 0.0003, 0.0369, 0.0483, 0.0496], // blue
+];
+const WHITE: usize = 0;
+const CYAN: usize = 1;
+const MAGENTA: usize = 2;
+const YELLOW: usize = 3;
+const RED: usize = 4;
+const GREEN: usize = 5;
+const BLUE: usize = 6;
+
+// one of Smits' spectra at lambda, interpolating between bin centres
+fn smits(spectrum: usize, lambda: f64) -> f64 {
+    let bins = &SMITS[spectrum];
+    let x = ((lambda - 380.0) / 34.0 - 0.5).clamp(0.0, 9.0);
+    let i = (x as usize).min(8);
+    let t = x - i as f64;
+    (1.0 - t) * bins[i] + t * bins[i + 1]
+}
+
+// The film's side of spectral rendering: picks each path's wavelengths
+// and scales them so a flat spectrum of 1 comes out as RGB white.
+#[derive(Clone, Copy, Debug)]
+pub struct Spectral {
+    white: Vec3, // the RGB of a flat spectrum, before that scaling
+}
+
+impl Default for Spectral {
+    fn default() -> Spectral {
+        Spectral::new()
+    }
+}
+
+impl Spectral {
+    pub fn new() -> Spectral {
+        let steps = 4 * (LAMBDA_MAX - LAMBDA_MIN) as usize;
+        let dl = (LAMBDA_MAX - LAMBDA_MIN) / steps as f64;
+        let mut xyz = Vec3::new(0.0, 0.0, 0.0);
+        for i in 0..steps {
+            xyz += cie_xyz(LAMBDA_MIN + (i as f64 + 0.5) * dl) * dl;
+        }
+        Spectral {
+            white: xyz_to_rgb(&xyz),
+        }
+    }
+
+    // u is uniform in [0,1) and picks the hero wavelength
+    pub fn wavelengths(&self, u: f64) -> Wavelengths {
+        let range = LAMBDA_MAX - LAMBDA_MIN;
+        let mut lambda = [0.0; 3];
+        let mut basis = [Vec3::new(0.0, 0.0, 0.0); 7];
+        let mut response = [Vec3::new(0.0, 0.0, 0.0); 3];
+        for i in 0..3 {
+            lambda[i] = LAMBDA_MIN + range * (u + i as f64 / 3.0).fract();
+            for (s, b) in basis.iter_mut().enumerate() {
+                b[i] = smits(s, lambda[i]);
+            }
+            // each wavelength stands for a third of the range
+            response[i] = xyz_to_rgb(&cie_xyz(lambda[i])) * (range / 3.0) / self.white;
+        }
+        Wavelengths {
+            lambda,
+            basis,
+            response,
+            hero_only: false,
+        }
+    }
+}
+
+// The wavelengths one path carries, in nm, with what is needed to turn
+// colours into spectra at them & the result back into a colour.
+#[derive(Clone, Debug)]
+pub struct Wavelengths {
+    pub lambda: [f64; 3],
+    basis: [Vec3; 7],    // Smits' spectra at lambda
+    response: [Vec3; 3], // the RGB each unit of radiance adds
+    hero_only: bool,     // the others have been dropped
+}
+
+impl Wavelengths {
+    // the one wavelength a path keeps after dispersion
+    pub fn hero(&self) -> f64 {
+        self.lambda[0]
+    }
+
+    // Dispersion bends each wavelength its own way, so from there the
+    // hero carries on alone, standing in for all three.  None if it
+    // already does, else these wavelengths for the rest of the path, &
+    // what the radiance at them is multiplied by.
+    pub fn drop_secondary(&self) -> Option<(Wavelengths, Vec3)> {
+        if self.hero_only {
+            return None;
+        }
+        let mut hero = self.clone();
+        hero.hero_only = true;
+        Some((hero, Vec3::new(3.0, 0.0, 0.0)))
+    }
+
+    // the spectrum of an RGB colour at these wavelengths
+    pub fn upsample(&self, rgb: &Vec3) -> Vec3 {
+        let (r, g, b) = (rgb.x(), rgb.y(), rgb.z());
+        let s = &self.basis;
+        if r <= g && r <= b {
+            r * s[WHITE]
+                + if g <= b {
+                    (g - r) * s[CYAN] + (b - g) * s[BLUE]
+                } else {
+                    (b - r) * s[CYAN] + (g - b) * s[GREEN]
+                }
+        } else if g <= r && g <= b {
+            g * s[WHITE]
+                + if r <= b {
+                    (r - g) * s[MAGENTA] + (b - r) * s[BLUE]
+                } else {
+                    (b - g) * s[MAGENTA] + (r - b) * s[RED]
+                }
+        } else if r <= g {
+            b * s[WHITE] + (r - b) * s[YELLOW] + (g - r) * s[GREEN]
+        } else {
+            b * s[WHITE] + (g - b) * s[YELLOW] + (r - g) * s[RED]
+        }
+    }
+
+    // radiance at these wavelengths as (linear sRGB) colour
+    pub fn to_rgb(&self, radiance: &Vec3) -> Vec3 {
+        radiance[0] * self.response[0]
+            + radiance[1] * self.response[1]
+            + radiance[2] * self.response[2]
+    }
+}
+
+// An index of refraction that varies with wavelength, so white light
+// splits into colours.  Coefficients are for wavelengths in µm.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Dispersion {
+    // n = a + b / λ²
+    Cauchy { a: f64, b: f64 },
+    // n² = 1 + Σ b λ² / (λ² - c)
+    Sellmeier { b: [f64; 3], c: [f64; 3] },
+}
+
+impl Dispersion {
+    // lambda in nm
+    pub fn ior(&self, lambda: f64) -> f64 {
+        let l2 = (lambda * 1e-3) * (lambda * 1e-3);
+        match *self {
+            Dispersion::Cauchy { a, b } => a + b / l2,
+            Dispersion::Sellmeier { b, c } => {
+                let mut n2 = 1.0;
+                for i in 0..3 {
+                    n2 += b[i] * l2 / (l2 - c[i]);
+                }
+                n2.sqrt()
+            }
+        }
+    }
+}
+
+// ======================================================================
+// Unit testing
+// ======================================================================
+#[test]
+fn test_spectral_white() {
+    // a flat spectrum averages out to white, & so does white upsampled
+    let spectral = Spectral::new();
+    let white = Vec3::new(1.0, 1.0, 1.0);
+    let n = 3000;
+    let mut flat = Vec3::new(0.0, 0.0, 0.0);
+    let mut upsampled = Vec3::new(0.0, 0.0, 0.0);
+    for i in 0..n {
+        let w = spectral.wavelengths((i as f64 + 0.5) / n as f64);
+        flat += w.to_rgb(&white);
+        upsampled += w.to_rgb(&w.upsample(&white));
+        // greys stay flat & everything scales
+        assert!((w.upsample(&(0.5 * white)) - 0.5 * w.upsample(&white)).length() < 1e-12);
+        let c = Vec3::new(0.2, 0.7, 0.4);
+        assert!((w.upsample(&(4.0 * c)) - 4.0 * w.upsample(&c)).length() < 1e-12);
+    }
+    assert!((flat / n as f64 - white).length() < 1e-3);
+    assert!((upsampled / n as f64 - white).length() < 1e-2);
+    // a saturated colour keeps its hue
+    let mut red = Vec3::new(0.0, 0.0, 0.0);
+    for i in 0..n {
+        let w = spectral.wavelengths((i as f64 + 0.5) / n as f64);
+        red += w.to_rgb(&w.upsample(&Vec3::new(1.0, 0.0, 0.0)));
+    }
+    red /= n as f64;
+    assert!(red.x() > 0.9 && red.y() < 0.05 && red.z() < 0.05);
+}
+#[test]
+fn test_dispersion() {
+    // N-BK7 glass
+    let bk7 = Dispersion::Sellmeier {
+        b: [1.03961212, 0.231792344, 1.01046945],
+        c: [0.00600069867, 0.0200179144, 103.560653],
+    };
+    assert!((bk7.ior(D_LINE) - 1.5168).abs() < 1e-3);
+    assert!(bk7.ior(450.0) > bk7.ior(650.0));
+    let cauchy = Dispersion::Cauchy { a: 1.5, b: 0.01 };
+    assert!((cauchy.ior(500.0) - 1.54).abs() < 1e-12);
+}
@@ -1,13 +1,16 @@
-use vec3::{dot, Vec3};
+use vec3::{dot, unit_vector, Vec3};
 use ray::Ray;
 use hitable::{HitRecord, Hitable};
 use material::Material;
+use aabb::Aabb;
+use onb::Onb;
+use std::f64::consts::PI;
 
 #[derive(Debug)]
 pub struct Sphere {
     center: Vec3,
     radius: f64,
-    pub material: Material, // FIXME reference?
+    material: Material,
 }
 
 impl Sphere {
@@ -20,29 +23,131 @@ impl Sphere {
     }
 }
 
-impl Hitable for Sphere {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
-        let oc = r.origin - self.center;
-        let a = dot(&r.direction, &r.direction);
-        let b = dot(&oc, &r.direction);
-        let c = dot(&oc, &oc) - self.radius * self.radius;
-        let discriminant = b * b - a * c;
-        if discriminant > 0.0 {
-            let mut temp = (-b - discriminant.sqrt()) / a;
-            if temp < t_max && temp > t_min {
-                rec.t = temp;
-                rec.p = r.point_at_parameter(rec.t);
-                rec.normal = (rec.p - self.center) / self.radius;
-                return true;
-            }
+// p is a point on the unit sphere.  u goes once around the equator and v
+// from the south to the north pole (+y).
+fn get_sphere_uv(p: &Vec3) -> (f64, f64) {
+    let phi = p.z().atan2(p.x());
+    let theta = p.y().clamp(-1.0, 1.0).asin();
+    (1.0 - (phi + PI) / (2.0 * PI), (theta + PI / 2.0) / PI)
+}
+
+// Shared with MovingSphere, which hits the sphere where it is at r.time.
+pub fn hit_sphere<'a>(
+    center: Vec3,
+    radius: f64,
+    material: &'a Material,
+    r: &Ray,
+    t_min: f64,
+    t_max: f64,
+    rec: &mut HitRecord<'a>,
+) -> bool {
+    let oc = r.origin - center;
+    let a = dot(&r.direction, &r.direction);
+    let b = dot(&oc, &r.direction);
+    let c = dot(&oc, &oc) - radius * radius;
+    let discriminant = b * b - a * c;
+    if discriminant > 0.0 {
+        let mut temp = (-b - discriminant.sqrt()) / a;
+        if !(temp < t_max && temp > t_min) {
             temp = (-b + discriminant.sqrt()) / a;
-            if temp < t_max && temp > t_min {
-                rec.t = temp;
-                rec.p = r.point_at_parameter(rec.t);
-                rec.normal = (rec.p - self.center) / self.radius;
-                return true;
-            }
         }
-        false
+        if temp < t_max && temp > t_min {
+            rec.t = temp;
+            rec.p = r.point_at_parameter(rec.t);
+            rec.normal = (rec.p - center) / radius;
+            let (u, v) = get_sphere_uv(&((rec.p - center) / radius.abs()));
+            rec.u = u;
+            rec.v = v;
+            rec.material = material;
+            return true;
+        }
+    }
+    false
+}
+
+// radius may be negative for bubbles
+pub fn sphere_box(center: Vec3, radius: f64) -> Aabb {
+    let r = Vec3::new(radius.abs(), radius.abs(), radius.abs());
+    Aabb::new(center - r, center + r)
+}
+
+impl Hitable for Sphere {
+    fn hit<'a>(&'a self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord<'a>) -> bool {
+        hit_sphere(
+            self.center,
+            self.radius,
+            &self.material,
+            r,
+            t_min,
+            t_max,
+            rec,
+        )
+    }
+    fn bounding_box(&self, output_box: &mut Aabb) -> bool {
+        *output_box = sphere_box(self.center, self.radius);
+        true
+    }
+    fn is_light(&self) -> bool {
+        matches!(self.material, Material::DiffuseLight { .. })
+    }
+    // Uniform over the cone of directions that see the sphere from o,
+    // which only ever picks the visible side.  From inside, uniform
+    // over all directions.
+    fn pdf_value(&self, o: &Vec3, v: &Vec3) -> f64 {
+        let direction = self.center - *o;
+        let distance_squared = direction.squared_length();
+        let radius_squared = self.radius * self.radius;
+        if distance_squared <= radius_squared {
+            return 1.0 / (4.0 * PI);
+        }
+        let cos_theta_max = (1.0 - radius_squared / distance_squared).sqrt();
+        if dot(&unit_vector(*v), &unit_vector(direction)) < cos_theta_max {
+            return 0.0;
+        }
+        1.0 / (2.0 * PI * (1.0 - cos_theta_max))
+    }
+    fn random(&self, o: &Vec3, sample: (f64, f64)) -> Vec3 {
+        let direction = self.center - *o;
+        let distance_squared = direction.squared_length();
+        let radius_squared = self.radius * self.radius;
+        let z = if distance_squared <= radius_squared {
+            1.0 - 2.0 * sample.1
+        } else {
+            let cos_theta_max = (1.0 - radius_squared / distance_squared).sqrt();
+            1.0 + sample.1 * (cos_theta_max - 1.0)
+        };
+        let phi = 2.0 * PI * sample.0;
+        let r = (1.0 - z * z).max(0.0).sqrt();
+        Onb::build_from_w(&direction).local(&Vec3::new(phi.cos() * r, phi.sin() * r, z))
+    }
+}
+
+// ======================================================================
+// Unit testing
+// ======================================================================
+#[test]
+fn test_sphere_light_sampling() {
+    let light = Sphere::new(
+        Vec3::new(0.0, 0.0, -4.0),
+        2.0,
+        Material::DiffuseLight {
+            emit: Vec3::new(1.0, 1.0, 1.0),
+            strength: 1.0,
+        },
+    );
+    assert!(light.is_light());
+    let o = Vec3::new(0.0, 0.0, 0.0);
+    // 30 degree cone around -z
+    let pdf = 1.0 / (2.0 * PI * (1.0 - (0.75f64).sqrt()));
+    assert!((light.pdf_value(&o, &Vec3::new(0.0, 0.0, -1.0)) - pdf).abs() < 1e-9);
+    assert_eq!(light.pdf_value(&o, &Vec3::new(0.0, 1.0, -1.0)), 0.0);
+    // every sampled direction hits the sphere
+    for i in 0..10 {
+        for j in 0..10 {
+            let v = light.random(&o, (i as f64 / 10.0, j as f64 / 10.0));
+            let mut rec = HitRecord::new();
+            assert!(light.hit(&Ray::new(o, v), 0.001, f64::MAX, &mut rec));
+            assert!((light.pdf_value(&o, &v) - pdf).abs() < 1e-9);
+        }
     }
 }
@@ -0,0 +1,488 @@
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use image_reader::{decode_exr, decode_hdr, is_exr, is_hdr, Image};
+use jpeg_decoder;
+use png;
+use rand::Rng;
+use vec3::{dot, unit_vector, Vec3};
+
+// A colour that can vary over a surface, looked up by the hit's surface
+// coordinates (u,v) and/or its position p.
+pub trait Texture: fmt::Debug + Send + Sync {
+    fn value(&self, u: f64, v: f64, p: &Vec3) -> Vec3;
+}
+
+// shorthand for the common single colour case
+pub fn solid(color: Vec3) -> Arc<dyn Texture> {
+    Arc::new(ConstantTexture::new(color))
+}
+
+#[derive(Debug)]
+pub struct ConstantTexture {
+    color: Vec3,
+}
+
+impl ConstantTexture {
+    pub fn new(color: Vec3) -> ConstantTexture {
+        ConstantTexture { color }
+    }
+}
+
+impl Texture for ConstantTexture {
+    fn value(&self, _u: f64, _v: f64, _p: &Vec3) -> Vec3 {
+        self.color
+    }
+}
+
+// A 3D checkerboard, so it does not depend on the object's uv mapping.
+// scale is the number of squares per 2π units.
+#[derive(Debug)]
+pub struct CheckerTexture {
+    odd: Arc<dyn Texture>,
+    even: Arc<dyn Texture>,
+    scale: f64,
+}
+
+impl CheckerTexture {
+    pub fn new(odd: Arc<dyn Texture>, even: Arc<dyn Texture>, scale: f64) -> CheckerTexture {
+        CheckerTexture { odd, even, scale }
+    }
+}
+
+impl Texture for CheckerTexture {
+    fn value(&self, u: f64, v: f64, p: &Vec3) -> Vec3 {
+        let sines =
+            (self.scale * p.x()).sin() * (self.scale * p.y()).sin() * (self.scale * p.z()).sin();
+        if sines < 0.0 {
+            self.odd.value(u, v, p)
+        } else {
+            self.even.value(u, v, p)
+        }
+    }
+}
+
+// ======================================================================
+// Image textures
+// ======================================================================
+
+#[derive(Debug)]
+pub enum TextureError {
+    Io(io::Error),
+    Format(String),
+}
+
+impl fmt::Display for TextureError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TextureError::Io(ref e) => write!(f, "{}", e),
+            TextureError::Format(ref message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl Error for TextureError {}
+
+impl From<io::Error> for TextureError {
+    fn from(e: io::Error) -> TextureError {
+        TextureError::Io(e)
+    }
+}
+
+// What to do with uv coordinates outside [0,1].
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum WrapMode {
+    #[default]
+    Repeat,
+    Clamp,
+    Mirror,
+}
+
+impl FromStr for WrapMode {
+    type Err = String;
+    fn from_str(s: &str) -> Result<WrapMode, String> {
+        match s {
+            "repeat" => Ok(WrapMode::Repeat),
+            "clamp" => Ok(WrapMode::Clamp),
+            "mirror" => Ok(WrapMode::Mirror),
+            _ => Err(format!(
+                "unknown wrap mode '{}' (expected repeat, clamp or mirror)",
+                s
+            )),
+        }
+    }
+}
+
+impl WrapMode {
+    fn apply(self, i: i64, n: usize) -> usize {
+        let n = n as i64;
+        let i = match self {
+            WrapMode::Repeat => i.rem_euclid(n),
+            WrapMode::Clamp => i.clamp(0, n - 1),
+            WrapMode::Mirror => {
+                let m = i.rem_euclid(2 * n);
+                if m < n {
+                    m
+                } else {
+                    2 * n - 1 - m
+                }
+            }
+        };
+        i as usize
+    }
+}
+
+// An image, bilinearly filtered.  u runs left to right and v
+// bottom to top.
+pub struct ImageTexture {
+    width: usize,
+    height: usize,
+    texels: Vec<Vec3>, // linear, top row first
+    wrap: WrapMode,
+}
+
+impl fmt::Debug for ImageTexture {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "ImageTexture({}x{}, {:?})",
+            self.width, self.height, self.wrap
+        )
+    }
+}
+
+impl ImageTexture {
+    pub fn new(width: usize, height: usize, texels: Vec<Vec3>, wrap: WrapMode) -> ImageTexture {
+        assert!(width > 0 && height > 0 && texels.len() == width * height);
+        ImageTexture {
+            width,
+            height,
+            texels,
+            wrap,
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P, wrap: WrapMode) -> Result<ImageTexture, TextureError> {
+        let (width, height, texels) = load_image(path)?;
+        Ok(ImageTexture::new(width, height, texels, wrap))
+    }
+
+    fn texel(&self, i: i64, j: i64) -> Vec3 {
+        let i = self.wrap.apply(i, self.width);
+        let j = self.wrap.apply(j, self.height);
+        self.texels[j * self.width + i]
+    }
+}
+
+impl Texture for ImageTexture {
+    fn value(&self, u: f64, v: f64, _p: &Vec3) -> Vec3 {
+        // texel centres are at half-integer positions
+        let x = u * self.width as f64 - 0.5;
+        let y = (1.0 - v) * self.height as f64 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (i, j) = (x0 as i64, y0 as i64);
+        let top = (1.0 - fx) * self.texel(i, j) + fx * self.texel(i + 1, j);
+        let bottom = (1.0 - fx) * self.texel(i, j + 1) + fx * self.texel(i + 1, j + 1);
+        (1.0 - fy) * top + fy * bottom
+    }
+}
+
+// Reads a PNG, JPEG, Radiance HDR or OpenEXR image as linear texels, top
+// row first.  The file type comes from its contents, not its name.
+pub fn load_image<P: AsRef<Path>>(path: P) -> Result<Image, TextureError> {
+    let mut data = Vec::new();
+    File::open(path)?.read_to_end(&mut data)?;
+    let (width, height, texels) = if data.starts_with(b"\x89PNG") {
+        decode_png(&data)?
+    } else if data.starts_with(&[0xff, 0xd8]) {
+        decode_jpeg(&data)?
+    } else if is_hdr(&data) {
+        decode_hdr(&data).map_err(TextureError::Format)?
+    } else if is_exr(&data) {
+        decode_exr(&data).map_err(TextureError::Format)?
+    } else {
+        return Err(TextureError::Format(
+            "not a PNG, JPEG, HDR or EXR image".to_string(),
+        ));
+    };
+    if width == 0 || height == 0 {
+        return Err(TextureError::Format("image is empty".to_string()));
+    }
+    Ok((width, height, texels))
+}
+
+// 8 bit image values are gamma encoded.  Undo the same gamma 2 that the
+// image writer applies so a texture lit by white comes back unchanged.
+fn to_linear(c: u8) -> f64 {
+    let c = c as f64 / 255.0;
+    c * c
+}
+
+// channels is 1 (gray) or 3 (RGB), plus one when there is an alpha to skip
+fn to_texels(data: &[u8], channels: usize, color_channels: usize) -> Vec<Vec3> {
+    data.chunks(channels)
+        .map(|px| {
+            if color_channels == 1 {
+                let l = to_linear(px[0]);
+                Vec3::new(l, l, l)
+            } else {
+                Vec3::new(to_linear(px[0]), to_linear(px[1]), to_linear(px[2]))
+            }
+        })
+        .collect()
+}
+
+fn decode_png(data: &[u8]) -> Result<Image, TextureError> {
+    let format_err = |e: png::DecodingError| TextureError::Format(format!("bad PNG: {}", e));
+    let mut decoder = png::Decoder::new(data);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info().map_err(format_err)?;
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buf).map_err(format_err)?;
+    let (channels, color_channels) = match info.color_type {
+        png::ColorType::Grayscale => (1, 1),
+        png::ColorType::GrayscaleAlpha => (2, 1),
+        png::ColorType::Rgb => (3, 3),
+        png::ColorType::Rgba => (4, 3),
+        png::ColorType::Indexed => {
+            return Err(TextureError::Format(
+                "unexpected indexed PNG output".to_string(),
+            ))
+        }
+    };
+    let (width, height) = (info.width as usize, info.height as usize);
+    let mut texels = Vec::with_capacity(width * height);
+    for row in buf.chunks(info.line_size).take(height) {
+        texels.extend(to_texels(
+            &row[..width * channels],
+            channels,
+            color_channels,
+        ));
+    }
+    Ok((width, height, texels))
+}
+
+fn decode_jpeg(data: &[u8]) -> Result<Image, TextureError> {
+    let mut decoder = jpeg_decoder::Decoder::new(data);
+    let pixels = decoder
+        .decode()
+        .map_err(|e| TextureError::Format(format!("bad JPEG: {}", e)))?;
+    let info = decoder.info().unwrap(); // decode() succeeded so this is set
+    let texels = match info.pixel_format {
+        jpeg_decoder::PixelFormat::L8 => to_texels(&pixels, 1, 1),
+        jpeg_decoder::PixelFormat::RGB24 => to_texels(&pixels, 3, 3),
+        other => {
+            return Err(TextureError::Format(format!(
+                "unsupported JPEG pixel format {:?}",
+                other
+            )))
+        }
+    };
+    Ok((info.width as usize, info.height as usize, texels))
+}
+
+// ======================================================================
+// Perlin noise
+// ======================================================================
+
+const PERLIN_POINTS: usize = 256;
+
+// Gradient noise from Ken Perlin, with random unit vectors at the
+// lattice points.
+pub struct Perlin {
+    ranvec: Vec<Vec3>,
+    perm_x: Vec<usize>,
+    perm_y: Vec<usize>,
+    perm_z: Vec<usize>,
+}
+
+impl fmt::Debug for Perlin {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Perlin")
+    }
+}
+
+impl Perlin {
+    pub fn new<R: Rng>(rng: &mut R) -> Perlin {
+        let ranvec = (0..PERLIN_POINTS)
+            .map(|_| {
+                unit_vector(Vec3::new(
+                    -1.0 + 2.0 * rng.gen::<f64>(),
+                    -1.0 + 2.0 * rng.gen::<f64>(),
+                    -1.0 + 2.0 * rng.gen::<f64>(),
+                ))
+            })
+            .collect();
+        Perlin {
+            ranvec,
+            perm_x: perlin_generate_perm(rng),
+            perm_y: perlin_generate_perm(rng),
+            perm_z: perlin_generate_perm(rng),
+        }
+    }
+
+    // in [-1,1]
+    pub fn noise(&self, p: &Vec3) -> f64 {
+        let (fx, fy, fz) = (p.x().floor(), p.y().floor(), p.z().floor());
+        let (u, v, w) = (p.x() - fx, p.y() - fy, p.z() - fz);
+        let (i, j, k) = (fx as i64, fy as i64, fz as i64);
+        let mut c = [[[Vec3::new(0.0, 0.0, 0.0); 2]; 2]; 2];
+        for (di, plane) in c.iter_mut().enumerate() {
+            for (dj, row) in plane.iter_mut().enumerate() {
+                for (dk, corner) in row.iter_mut().enumerate() {
+                    *corner = self.ranvec[self.perm_x[wrap_lattice(i + di as i64)]
+                        ^ self.perm_y[wrap_lattice(j + dj as i64)]
+                        ^ self.perm_z[wrap_lattice(k + dk as i64)]];
+                }
+            }
+        }
+        perlin_interp(&c, u, v, w)
+    }
+
+    // sum of `depth` octaves of |noise|
+    pub fn turb(&self, p: &Vec3, depth: usize) -> f64 {
+        let mut accum = 0.0;
+        let mut temp_p = *p;
+        let mut weight = 1.0;
+        for _ in 0..depth {
+            accum += weight * self.noise(&temp_p);
+            weight *= 0.5;
+            temp_p *= 2.0;
+        }
+        accum.abs()
+    }
+}
+
+fn wrap_lattice(i: i64) -> usize {
+    (i & (PERLIN_POINTS as i64 - 1)) as usize
+}
+
+fn perlin_generate_perm<R: Rng>(rng: &mut R) -> Vec<usize> {
+    let mut p: Vec<usize> = (0..PERLIN_POINTS).collect();
+    for i in (1..PERLIN_POINTS).rev() {
+        let target = (rng.gen::<f64>() * (i + 1) as f64) as usize;
+        p.swap(i, target);
+    }
+    p
+}
+
+// trilinear interpolation of the gradients, Hermite smoothed
+fn perlin_interp(c: &[[[Vec3; 2]; 2]; 2], u: f64, v: f64, w: f64) -> f64 {
+    let uu = u * u * (3.0 - 2.0 * u);
+    let vv = v * v * (3.0 - 2.0 * v);
+    let ww = w * w * (3.0 - 2.0 * w);
+    let mut accum = 0.0;
+    for (i, plane) in c.iter().enumerate() {
+        for (j, row) in plane.iter().enumerate() {
+            for (k, corner) in row.iter().enumerate() {
+                let (fi, fj, fk) = (i as f64, j as f64, k as f64);
+                let weight = Vec3::new(u - fi, v - fj, w - fk);
+                accum += (fi * uu + (1.0 - fi) * (1.0 - uu))
+                    * (fj * vv + (1.0 - fj) * (1.0 - vv))
+                    * (fk * ww + (1.0 - fk) * (1.0 - ww))
+                    * dot(corner, &weight);
+            }
+        }
+    }
+    accum
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NoiseKind {
+    Noise,      // smooth noise
+    Turbulence, // several octaves
+    Marble,     // turbulence phase shifting a sine wave
+}
+
+const TURBULENCE_DEPTH: usize = 7;
+
+// Perlin noise scaled into [0,1] and multiplied by a colour.  scale is
+// the frequency of the noise.
+#[derive(Debug)]
+pub struct NoiseTexture {
+    noise: Perlin,
+    kind: NoiseKind,
+    scale: f64,
+    color: Vec3,
+}
+
+impl NoiseTexture {
+    pub fn new<R: Rng>(kind: NoiseKind, scale: f64, color: Vec3, rng: &mut R) -> NoiseTexture {
+        NoiseTexture {
+            noise: Perlin::new(rng),
+            kind,
+            scale,
+            color,
+        }
+    }
+}
+
+impl Texture for NoiseTexture {
+    fn value(&self, _u: f64, _v: f64, p: &Vec3) -> Vec3 {
+        let amount = match self.kind {
+            NoiseKind::Noise => 0.5 * (1.0 + self.noise.noise(&(self.scale * *p))),
+            NoiseKind::Turbulence => self.noise.turb(&(self.scale * *p), TURBULENCE_DEPTH),
+            NoiseKind::Marble => {
+                0.5 * (1.0
+                    + (self.scale * p.z() + 10.0 * self.noise.turb(p, TURBULENCE_DEPTH)).sin())
+            }
+        };
+        amount * self.color
+    }
+}
+
+// ======================================================================
+// Unit testing
+// ======================================================================
+#[test]
+fn test_checker_texture() {
+    let checker = CheckerTexture::new(
+        solid(Vec3::new(0.0, 0.0, 0.0)),
+        solid(Vec3::new(1.0, 1.0, 1.0)),
+        10.0,
+    );
+    let even = Vec3::new(0.05, 0.05, 0.05); // all three sines positive
+    let odd = Vec3::new(-0.05, 0.05, 0.05);
+    assert_eq!(checker.value(0.0, 0.0, &even), Vec3::new(1.0, 1.0, 1.0));
+    assert_eq!(checker.value(0.0, 0.0, &odd), Vec3::new(0.0, 0.0, 0.0));
+}
+#[test]
+fn test_image_texture_filtering_and_wrap() {
+    // 2x1 image: black on the left, white on the right
+    let texels = vec![Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 1.0, 1.0)];
+    let p = Vec3::new(0.0, 0.0, 0.0);
+    let clamp = ImageTexture::new(2, 1, texels.clone(), WrapMode::Clamp);
+    assert_eq!(clamp.value(0.25, 0.5, &p).x(), 0.0); // texel centres
+    assert_eq!(clamp.value(0.75, 0.5, &p).x(), 1.0);
+    assert_eq!(clamp.value(0.5, 0.5, &p).x(), 0.5); // halfway between
+    assert_eq!(clamp.value(1.5, 0.5, &p).x(), 1.0);
+    let repeat = ImageTexture::new(2, 1, texels.clone(), WrapMode::Repeat);
+    assert_eq!(repeat.value(1.25, 0.5, &p).x(), 0.0);
+    assert_eq!(repeat.value(0.0, 0.5, &p).x(), 0.5); // blends across the seam
+    let mirror = ImageTexture::new(2, 1, texels, WrapMode::Mirror);
+    assert_eq!(mirror.value(1.25, 0.5, &p).x(), 1.0);
+    assert_eq!(mirror.value(0.0, 0.5, &p).x(), 0.0);
+}
+#[test]
+fn test_perlin_noise_range() {
+    use rand::{SeedableRng, StdRng};
+    let seed: &[_] = &[1];
+    let mut rng: StdRng = SeedableRng::from_seed(seed);
+    let marble = NoiseTexture::new(NoiseKind::Marble, 4.0, Vec3::new(1.0, 1.0, 1.0), &mut rng);
+    let noise = NoiseTexture::new(NoiseKind::Noise, 4.0, Vec3::new(1.0, 1.0, 1.0), &mut rng);
+    for i in 0..100 {
+        let p = Vec3::new(0.37 * i as f64, 0.11 * i as f64, -0.23 * i as f64);
+        for t in [&marble, &noise].iter() {
+            let c = t.value(0.0, 0.0, &p).x();
+            assert!((0.0..=1.0).contains(&c));
+        }
+    }
+    // noise is zero at the lattice points
+    assert_eq!(noise.noise.noise(&Vec3::new(3.0, 4.0, 5.0)), 0.0);
+}
@@ -0,0 +1,131 @@
+use aabb::Aabb;
+use hitable::{HitRecord, Hitable};
+use material::Material;
+use ray::Ray;
+#[cfg(test)]
+use texture::solid;
+use vec3::{cross, dot, unit_vector, Vec3};
+
+// Flat triangles still need some thickness for the slab test.
+const BBOX_PAD: f64 = 1e-6;
+
+#[derive(Debug)]
+pub struct Triangle {
+    p: [Vec3; 3],
+    normals: Option<[Vec3; 3]>, // per-vertex, for smooth shading
+    uvs: Option<[(f64, f64); 3]>,
+    material: Material,
+}
+
+impl Triangle {
+    // normals & uvs are optional per-vertex attributes
+    pub fn new(
+        p: [Vec3; 3],
+        normals: Option<[Vec3; 3]>,
+        uvs: Option<[(f64, f64); 3]>,
+        material: Material,
+    ) -> Triangle {
+        Triangle {
+            p,
+            normals,
+            uvs,
+            material,
+        }
+    }
+}
+
+impl Hitable for Triangle {
+    // Möller–Trumbore intersection
+    fn hit<'a>(&'a self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord<'a>) -> bool {
+        let e1 = self.p[1] - self.p[0];
+        let e2 = self.p[2] - self.p[0];
+        let pvec = cross(&r.direction, &e2);
+        let det = dot(&e1, &pvec);
+        if det.abs() < 1e-12 {
+            return false; // ray is parallel to the triangle
+        }
+        let inv_det = 1.0 / det;
+        let tvec = r.origin - self.p[0];
+        let u = dot(&tvec, &pvec) * inv_det;
+        if !(0.0..=1.0).contains(&u) {
+            return false;
+        }
+        let qvec = cross(&tvec, &e1);
+        let v = dot(&r.direction, &qvec) * inv_det;
+        if v < 0.0 || u + v > 1.0 {
+            return false;
+        }
+        let t = dot(&e2, &qvec) * inv_det;
+        if t < t_max && t > t_min {
+            let w = 1.0 - u - v;
+            // normal follows the winding order, like Sphere's outward normal
+            let geometric_normal = unit_vector(cross(&e1, &e2));
+            rec.t = t;
+            rec.p = r.point_at_parameter(t);
+            rec.normal = match self.normals {
+                Some(ref n) => {
+                    let shading_normal = unit_vector(w * n[0] + u * n[1] + v * n[2]);
+                    // keep the interpolated normal on the geometric side
+                    if dot(&shading_normal, &geometric_normal) < 0.0 {
+                        -shading_normal
+                    } else {
+                        shading_normal
+                    }
+                }
+                None => geometric_normal,
+            };
+            let (tex_u, tex_v) = match self.uvs {
+                Some(ref uv) => (
+                    w * uv[0].0 + u * uv[1].0 + v * uv[2].0,
+                    w * uv[0].1 + u * uv[1].1 + v * uv[2].1,
+                ),
+                None => (u, v),
+            };
+            rec.u = tex_u;
+            rec.v = tex_v;
+            rec.material = &self.material;
+            return true;
+        }
+        false
+    }
+    fn bounding_box(&self, output_box: &mut Aabb) -> bool {
+        let pad = Vec3::new(BBOX_PAD, BBOX_PAD, BBOX_PAD);
+        let mut min = self.p[0];
+        let mut max = self.p[0];
+        for p in &self.p[1..] {
+            for a in 0..3 {
+                min[a] = min[a].min(p[a]);
+                max[a] = max[a].max(p[a]);
+            }
+        }
+        *output_box = Aabb::new(min - pad, max + pad);
+        true
+    }
+}
+
+// ======================================================================
+// Unit testing
+// ======================================================================
+#[test]
+fn test_triangle_hit() {
+    let tri = Triangle::new(
+        [
+            Vec3::new(-1.0, -1.0, -2.0),
+            Vec3::new(1.0, -1.0, -2.0),
+            Vec3::new(0.0, 1.0, -2.0),
+        ],
+        None,
+        None,
+        Material::Lambertian {
+            albedo: solid(Vec3::new(0.5, 0.5, 0.5)),
+        },
+    );
+    let mut rec = HitRecord::new();
+    let r = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0));
+    assert!(tri.hit(&r, 0.001, f64::MAX, &mut rec));
+    assert_eq!(rec.t, 2.0);
+    assert_eq!(rec.normal, Vec3::new(0.0, 0.0, 1.0));
+    let miss = Ray::new(Vec3::new(2.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0));
+    assert!(!tri.hit(&miss, 0.001, f64::MAX, &mut rec));
+    assert!(!tri.hit(&r, 0.001, 1.0, &mut rec));
+}
@@ -1,6 +1,7 @@
 use std::ops::{Add, AddAssign, Div, DivAssign, Index, IndexMut, Mul, MulAssign, Neg, Sub,
                SubAssign};
 use rand::Rng;
+use std::f64::consts::PI;
 
 #[derive(Clone, Copy, Debug)]
 pub struct Vec3 {
@@ -45,7 +46,7 @@ impl Vec3 {
     }
     #[inline(always)]
     pub fn squared_length(&self) -> f64 {
-        (self.e[0] * self.e[0] + self.e[1] * self.e[1] + self.e[2] * self.e[2])
+        self.e[0] * self.e[0] + self.e[1] * self.e[1] + self.e[2] * self.e[2]
     }
     #[inline(always)]
     pub fn make_unit_vector(&mut self) {
@@ -254,6 +255,16 @@ pub fn random_in_unit_disk<R: Rng>(rng: &mut R) -> Vec3 {
     p
 }
 
+// Cosine-weighted about +z: the pdf of the direction is z / pi.
+#[inline(always)]
+pub fn random_cosine_direction<R: Rng>(rng: &mut R) -> Vec3 {
+    let r1 = rng.gen::<f64>();
+    let r2 = rng.gen::<f64>();
+    let phi = 2.0 * PI * r1;
+    let r = r2.sqrt();
+    Vec3::new(phi.cos() * r, phi.sin() * r, (1.0 - r2).sqrt())
+}
+
 #[inline(always)]
 pub fn reflect(v: &Vec3, n: &Vec3) -> Vec3 {
     (*v) - 2.0 * dot(v, n) * (*n)
@@ -272,6 +283,153 @@ pub fn refract(v: &Vec3, n: &Vec3, ni_over_nt: f64, refracted: &mut Vec3) -> boo
     false
 }
 
+// A 4x4 matrix for affine transforms, stored by rows.  Points are
+// column vectors, so a * b applies b first, then a.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Mat4 {
+    m: [[f64; 4]; 4],
+}
+
+impl Mat4 {
+    pub fn identity() -> Mat4 {
+        Mat4 {
+            m: [
+                [1.0, 0.0, 0.0, 0.0],
+                [0.0, 1.0, 0.0, 0.0],
+                [0.0, 0.0, 1.0, 0.0],
+                [0.0, 0.0, 0.0, 1.0],
+            ],
+        }
+    }
+    pub fn translate(offset: Vec3) -> Mat4 {
+        let mut t = Mat4::identity();
+        for i in 0..3 {
+            t.m[i][3] = offset[i];
+        }
+        t
+    }
+    pub fn scale(s: Vec3) -> Mat4 {
+        let mut t = Mat4::identity();
+        for i in 0..3 {
+            t.m[i][i] = s[i];
+        }
+        t
+    }
+    // counter-clockwise when looking down the axis towards the origin
+    pub fn rotate(axis: Vec3, degrees: f64) -> Mat4 {
+        let a = unit_vector(axis);
+        let (s, c) = degrees.to_radians().sin_cos();
+        let t = 1.0 - c;
+        let (x, y, z) = (a.x(), a.y(), a.z());
+        Mat4 {
+            m: [
+                [t * x * x + c, t * x * y - s * z, t * x * z + s * y, 0.0],
+                [t * x * y + s * z, t * y * y + c, t * y * z - s * x, 0.0],
+                [t * x * z - s * y, t * y * z + s * x, t * z * z + c, 0.0],
+                [0.0, 0.0, 0.0, 1.0],
+            ],
+        }
+    }
+    // Places an object at `from` with its -z axis pointing at `to` and
+    // its +y axis as close to `vup` as it can be, like the camera.
+    pub fn look_at(from: Vec3, to: Vec3, vup: Vec3) -> Mat4 {
+        let w = unit_vector(from - to);
+        let u = unit_vector(cross(&vup, &w));
+        let v = cross(&w, &u);
+        let mut t = Mat4::identity();
+        for i in 0..3 {
+            t.m[i][0] = u[i];
+            t.m[i][1] = v[i];
+            t.m[i][2] = w[i];
+            t.m[i][3] = from[i];
+        }
+        t
+    }
+    // self followed by next
+    pub fn then(&self, next: &Mat4) -> Mat4 {
+        *next * *self
+    }
+    pub fn transpose(&self) -> Mat4 {
+        let mut t = Mat4::identity();
+        for i in 0..4 {
+            for j in 0..4 {
+                t.m[i][j] = self.m[j][i];
+            }
+        }
+        t
+    }
+    pub fn is_finite(&self) -> bool {
+        self.m.iter().flatten().all(|x| x.is_finite())
+    }
+    // Gauss-Jordan elimination with partial pivoting.  None if the
+    // matrix is singular, e.g. it scales something to nothing, or has
+    // infinities or NaNs in it.
+    pub fn inverse(&self) -> Option<Mat4> {
+        if !self.is_finite() {
+            return None;
+        }
+        let mut a = self.m;
+        let mut inv = Mat4::identity().m;
+        for col in 0..4 {
+            let pivot = (col..4)
+                .max_by(|&i, &j| a[i][col].abs().total_cmp(&a[j][col].abs()))
+                .unwrap();
+            if a[pivot][col].abs() < 1e-12 {
+                return None;
+            }
+            a.swap(col, pivot);
+            inv.swap(col, pivot);
+            let k = 1.0 / a[col][col];
+            for j in 0..4 {
+                a[col][j] *= k;
+                inv[col][j] *= k;
+            }
+            for i in 0..4 {
+                if i != col {
+                    let f = a[i][col];
+                    for j in 0..4 {
+                        a[i][j] -= f * a[col][j];
+                        inv[i][j] -= f * inv[col][j];
+                    }
+                }
+            }
+        }
+        Some(Mat4 { m: inv })
+    }
+    #[inline(always)]
+    pub fn transform_point(&self, p: Vec3) -> Vec3 {
+        let m = &self.m;
+        Vec3::new(
+            m[0][0] * p.e[0] + m[0][1] * p.e[1] + m[0][2] * p.e[2] + m[0][3],
+            m[1][0] * p.e[0] + m[1][1] * p.e[1] + m[1][2] * p.e[2] + m[1][3],
+            m[2][0] * p.e[0] + m[2][1] * p.e[1] + m[2][2] * p.e[2] + m[2][3],
+        )
+    }
+    // directions ignore the translation
+    #[inline(always)]
+    pub fn transform_vector(&self, v: Vec3) -> Vec3 {
+        let m = &self.m;
+        Vec3::new(
+            m[0][0] * v.e[0] + m[0][1] * v.e[1] + m[0][2] * v.e[2],
+            m[1][0] * v.e[0] + m[1][1] * v.e[1] + m[1][2] * v.e[2],
+            m[2][0] * v.e[0] + m[2][1] * v.e[1] + m[2][2] * v.e[2],
+        )
+    }
+}
+
+impl Mul<Mat4> for Mat4 {
+    type Output = Mat4;
+    fn mul(self, b: Mat4) -> Mat4 {
+        let mut t = Mat4 { m: [[0.0; 4]; 4] };
+        for i in 0..4 {
+            for j in 0..4 {
+                t.m[i][j] = (0..4).map(|k| self.m[i][k] * b.m[k][j]).sum();
+            }
+        }
+        t
+    }
+}
+
 // ======================================================================
 // Unit testing
 // ======================================================================
@@ -331,3 +489,48 @@ fn test_cross() {
         )
     );
 }
+#[test]
+fn test_mat4_transforms() {
+    let close = |a: Vec3, b: Vec3| (a - b).length() < 1e-9;
+    let p = Vec3::new(1.0, 2.0, 3.0);
+    let t = Mat4::translate(Vec3::new(1.0, 0.0, -1.0));
+    assert_eq!(t.transform_point(p), Vec3::new(2.0, 2.0, 2.0));
+    assert_eq!(t.transform_vector(p), p);
+    // a quarter turn about +y takes +x to -z
+    let r = Mat4::rotate(Vec3::new(0.0, 2.0, 0.0), 90.0);
+    assert!(close(
+        r.transform_vector(Vec3::new(1.0, 0.0, 0.0)),
+        Vec3::new(0.0, 0.0, -1.0)
+    ));
+    // scale first, then translate
+    let s = Mat4::scale(Vec3::new(2.0, 2.0, 2.0)).then(&t);
+    assert_eq!(s, t * Mat4::scale(Vec3::new(2.0, 2.0, 2.0)));
+    assert_eq!(s.transform_point(p), Vec3::new(3.0, 4.0, 5.0));
+    // looking from +z at the origin, -z points at the origin
+    let l = Mat4::look_at(
+        Vec3::new(0.0, 0.0, 5.0),
+        Vec3::new(0.0, 0.0, 0.0),
+        Vec3::new(0.0, 1.0, 0.0),
+    );
+    assert!(close(
+        l.transform_point(Vec3::new(0.0, 0.0, -5.0)),
+        Vec3::new(0.0, 0.0, 0.0)
+    ));
+}
+#[test]
+fn test_mat4_inverse() {
+    let m = Mat4::rotate(Vec3::new(1.0, 1.0, 0.0), 30.0)
+        .then(&Mat4::scale(Vec3::new(1.0, 2.0, 3.0)))
+        .then(&Mat4::translate(Vec3::new(4.0, 5.0, 6.0)));
+    let i = m.inverse().unwrap() * m;
+    for r in 0..4 {
+        for c in 0..4 {
+            let expected = if r == c { 1.0 } else { 0.0 };
+            assert!((i.m[r][c] - expected).abs() < 1e-9);
+        }
+    }
+    assert_eq!(m.transpose().transpose(), m);
+    assert_eq!(Mat4::scale(Vec3::new(1.0, 0.0, 1.0)).inverse(), None);
+    assert_eq!(Mat4::scale(Vec3::new(f64::NAN, 1.0, 1.0)).inverse(), None);
+    assert_eq!(Mat4::translate(Vec3::new(0.0, f64::INFINITY, 0.0)).inverse(), None);
+}